@@ -0,0 +1,121 @@
+//! Measures calls to user functions with both engines, and counts the allocations each
+//! call makes: the scope of a call used to be a hash map, it's now a vector of slots
+//! filled in place from the arguments when the parameters are plain symbols. The counts
+//! are printed next to [`HASH_MAP_SCOPES`], the ones measured before that change. Rest
+//! arguments don't gain as much, since the list they're put in is copied into storage
+//! shared with the lists made from it.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust2::{
+    ast::Expr,
+    environment::{Env, Environment},
+    eval::{self, vm, EvalResult},
+    parser::parse,
+};
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+type Engine = fn(&Expr, &Env) -> EvalResult<Expr>;
+
+const ENGINES: &[(&str, Engine)] = &[("eval", eval::eval), ("vm", vm::eval)];
+
+/// Functions called in a loop, with the arguments of each call.
+const FUNCTIONS: &[(&str, &str, &str)] = &[
+    ("one", "(fn* [x] x)", "1"),
+    ("three", "(fn* [a b c] b)", "1 2 3"),
+    ("varargs", "(fn* [a & more] a)", "1 2 3"),
+    ("destructuring", "(fn* [[a b] {:keys [c]}] c)", "pair opts"),
+];
+
+const CALLS: usize = 1_000;
+
+/// Allocations per call when scopes were hash maps, for each engine and function.
+const HASH_MAP_SCOPES: &[(&str, [f64; 4])] =
+    &[("eval", [4.0, 4.0, 5.0, 4.0]), ("vm", [3.0, 3.0, 4.0, 3.0])];
+
+fn setup(engine: Engine) -> Env {
+    let env = Environment::with_builtins();
+    let run = |src: &str| engine(&parse(src).unwrap(), &env).unwrap();
+    run("(def! pair [1 2])");
+    run("(def! opts {:c 3})");
+    run("(def! empty (fn* [n] (loop* [i n] (if (= i 0) nil (do nil (recur (- i 1)))))))");
+    for (name, f, args) in FUNCTIONS {
+        run(&format!("(def! {name} {f})"));
+        run(&format!(
+            "(def! call-{name} (fn* [n] (loop* [i n] (if (= i 0) nil (do ({name} {args}) (recur (- i 1)))))))"
+        ));
+    }
+    env
+}
+
+/// Allocations made by evaluating `expr`, after a run to compile the functions it calls.
+fn allocations(engine: Engine, expr: &Expr, env: &Env) -> usize {
+    engine(expr, env).unwrap();
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    engine(expr, env).unwrap();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn report_allocations() {
+    for &(engine_name, engine) in ENGINES {
+        let env = setup(engine);
+        let empty = allocations(engine, &parse(&format!("(empty {CALLS})")).unwrap(), &env);
+        let (_, baseline) = HASH_MAP_SCOPES
+            .iter()
+            .find(|&&(name, _)| name == engine_name)
+            .expect("every engine has a baseline");
+        for ((name, _, _), before) in FUNCTIONS.iter().zip(baseline) {
+            let expr = parse(&format!("(call-{name} {CALLS})")).unwrap();
+            let calls = allocations(engine, &expr, &env) - empty;
+            println!(
+                "{engine_name}/{name}: {:.1} allocations per call, {before:.1} with hash map scopes",
+                calls as f64 / CALLS as f64
+            );
+        }
+    }
+}
+
+fn calls(c: &mut Criterion) {
+    report_allocations();
+
+    let mut group = c.benchmark_group("calls");
+    for &(engine_name, engine) in ENGINES {
+        let env = setup(engine);
+        for (name, _, _) in FUNCTIONS {
+            let expr = parse(&format!("(call-{name} {CALLS})")).unwrap();
+            group.bench_with_input(BenchmarkId::new(engine_name, name), &expr, |b, expr| {
+                b.iter(|| engine(expr, &env).unwrap())
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, calls);
+criterion_main!(benches);
@@ -0,0 +1,173 @@
+//! Compares the persistent vector, map, set and list backing `Expr` against copying a
+//! `Vec` or a hash map on every update, which is what `conj`, `assoc` and `rest` used to do.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use fnv::{FnvHashMap, FnvHashSet};
+use rust2::{
+    ast::{Expr, List, Map, MapKey, Set, Vector},
+    environment::Environment,
+    eval::eval,
+    parser::parse,
+};
+
+const SIZES: &[usize] = &[100, 1_000, 4_000];
+
+fn conj(c: &mut Criterion) {
+    let mut group = c.benchmark_group("conj");
+    for &n in SIZES {
+        group.bench_with_input(BenchmarkId::new("persistent", n), &n, |b, &n| {
+            b.iter(|| {
+                let mut v = Vector::new();
+                for i in 0..n {
+                    let mut next = v.clone();
+                    next.push_back(Expr::Int(i as i64));
+                    v = next;
+                }
+                black_box(v)
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("copy", n), &n, |b, &n| {
+            b.iter(|| {
+                let mut v = Vec::new();
+                for i in 0..n {
+                    let mut next = v.clone();
+                    next.push(Expr::Int(i as i64));
+                    v = next;
+                }
+                black_box(v)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn assoc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("assoc");
+    for &n in SIZES {
+        group.bench_with_input(BenchmarkId::new("persistent", n), &n, |b, &n| {
+            b.iter(|| {
+                let mut m = Map::new();
+                for i in 0..n {
+                    let mut next = m.clone();
+                    next.insert(MapKey::new(Expr::Int(i as i64)), Expr::Nil);
+                    m = next;
+                }
+                black_box(m)
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("copy", n), &n, |b, &n| {
+            b.iter(|| {
+                let mut m = FnvHashMap::default();
+                for i in 0..n {
+                    let mut next = m.clone();
+                    next.insert(MapKey::new(Expr::Int(i as i64)), Expr::Nil);
+                    m = next;
+                }
+                black_box(m)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn set_conj(c: &mut Criterion) {
+    let mut group = c.benchmark_group("set-conj");
+    for &n in SIZES {
+        group.bench_with_input(BenchmarkId::new("persistent", n), &n, |b, &n| {
+            b.iter(|| {
+                let mut s = Set::new();
+                for i in 0..n {
+                    let mut next = s.clone();
+                    next.insert(MapKey::new(Expr::Int(i as i64)));
+                    s = next;
+                }
+                black_box(s)
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("copy", n), &n, |b, &n| {
+            b.iter(|| {
+                let mut s = FnvHashSet::default();
+                for i in 0..n {
+                    let mut next = s.clone();
+                    next.insert(MapKey::new(Expr::Int(i as i64)));
+                    s = next;
+                }
+                black_box(s)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn rest(c: &mut Criterion) {
+    let mut group = c.benchmark_group("rest");
+    for &n in SIZES {
+        let items: Vec<Expr> = (0..n).map(|i| Expr::Int(i as i64)).collect();
+        let vector: Vector = items.iter().cloned().collect();
+        group.bench_with_input(BenchmarkId::new("list", n), &items, |b, items| {
+            b.iter(|| {
+                let mut l = List::from(items.clone()).into_inner();
+                while !l.is_empty() {
+                    l = l.rest();
+                }
+                black_box(l)
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("vector", n), &vector, |b, vector| {
+            b.iter(|| {
+                let mut l = List::from(vector.clone()).into_inner();
+                while !l.is_empty() {
+                    l = l.rest();
+                }
+                black_box(l)
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("copy", n), &items, |b, items| {
+            b.iter(|| {
+                let mut l = items.clone();
+                while !l.is_empty() {
+                    l = l[1..].to_vec();
+                }
+                black_box(l)
+            })
+        });
+    }
+    group.finish();
+}
+
+fn mal(c: &mut Criterion) {
+    let env = Environment::with_builtins();
+    let definitions = [
+        "(def! build-vec (fn* [v n] (if (= n 0) v (build-vec (conj v n) (- n 1)))))",
+        "(def! build-map (fn* [m n] (if (= n 0) m (build-map (assoc m n n) (- n 1)))))",
+        "(def! build-set (fn* [s n] (if (= n 0) s (build-set (conj s n) (- n 1)))))",
+        "(def! walk (fn* [l] (if (empty? l) nil (walk (rest l)))))",
+    ];
+    for def in definitions {
+        eval(&parse(def).unwrap(), &env).unwrap();
+    }
+
+    let mut group = c.benchmark_group("mal");
+    for &n in SIZES {
+        let build_vec = parse(&format!("(count (build-vec [] {n}))")).unwrap();
+        group.bench_with_input(BenchmarkId::new("conj", n), &build_vec, |b, expr| {
+            b.iter(|| eval(expr, &env).unwrap())
+        });
+        let build_map = parse(&format!("(build-map {{}} {n})")).unwrap();
+        group.bench_with_input(BenchmarkId::new("assoc", n), &build_map, |b, expr| {
+            b.iter(|| eval(expr, &env).unwrap())
+        });
+        let build_set = parse(&format!("(count (build-set #{{}} {n}))")).unwrap();
+        group.bench_with_input(BenchmarkId::new("set-conj", n), &build_set, |b, expr| {
+            b.iter(|| eval(expr, &env).unwrap())
+        });
+        let walk = parse(&format!("(walk (build-vec [] {n}))")).unwrap();
+        group.bench_with_input(BenchmarkId::new("rest", n), &walk, |b, expr| {
+            b.iter(|| eval(expr, &env).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, conj, assoc, set_conj, rest, mal);
+criterion_main!(benches);
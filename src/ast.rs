@@ -1,25 +1,92 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::{Deref, RangeFrom, RangeInclusive},
+    rc::Rc,
+};
 
 use fnv::FnvHashMap;
 
-use crate::environment::Env;
+use crate::{
+    environment::{gc, Env},
+    eval::{compile, vm, EvalResult},
+    span::{Span, Spanned},
+};
 
+use self::{
+    interner::SymbolId,
+    number::{BigInt, Ratio},
+    pattern::Pattern,
+    persistent::{
+        ListIter, PersistentList, PersistentMap, PersistentSet, PersistentVector, VectorIter,
+    },
+    sorted_map::SortedMap,
+};
+
+pub mod convert;
 pub mod display;
-pub type Map = FnvHashMap<MapKey, Expr>;
+pub mod interner;
+pub mod number;
+mod ordering;
+pub mod pattern;
+pub mod persistent;
+#[cfg(feature = "serde")]
+pub mod serialization;
+pub mod sorted_map;
+
+pub type List = Spanned<PersistentList<Expr>>;
+pub type Map = Spanned<PersistentMap<MapKey, Expr>>;
+pub type Set = Spanned<PersistentSet<MapKey>>;
+pub type Vector = Spanned<PersistentVector<Expr>>;
+
+impl List {
+    /// Location to attribute errors of the call this list is to: its head if that's a
+    /// symbol, otherwise the list itself.
+    pub fn call_span(&self) -> Option<&Rc<Span>> {
+        self.first().and_then(Expr::symbol_span).or(self.span())
+    }
+}
+
+impl From<Vec<Expr>> for List {
+    fn from(items: Vec<Expr>) -> Self {
+        PersistentList::from(items).into()
+    }
+}
+
+/// The items of `vector`, without copying them.
+impl From<Vector> for List {
+    fn from(vector: Vector) -> Self {
+        PersistentList::from(vector.into_inner()).into()
+    }
+}
+
+impl From<Vec<Expr>> for Vector {
+    fn from(items: Vec<Expr>) -> Self {
+        PersistentVector::from(items).into()
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Nil,
     Bool(bool),
     Int(i64),
+    BigInt(Rc<BigInt>),
+    Ratio(Rc<Ratio>),
+    Float(f64),
     String(String),
-    List(Vec<Expr>),
-    Vector(Vec<Expr>),
+    List(List),
+    Vector(Vector),
     Map(Rc<Map>),
-    Symbol(Rc<str>),
+    SortedMap(Rc<SortedMap>),
+    Set(Rc<Set>),
+    Symbol(Symbol),
     Keyword(Keyword),
     Function(Function),
-    BuiltinFunction(&'static str),
+    BuiltinFunction(SymbolId),
+    NativeFunction(NativeFunction),
     Atom(Rc<RefCell<Expr>>),
     MacroExpand(Rc<Expr>),
     WithMeta { expr: Rc<Expr>, meta: Rc<Expr> },
@@ -29,7 +96,34 @@ impl Expr {
     pub const NIL: Expr = Expr::Nil;
 
     pub fn atom(e: Expr) -> Expr {
-        Self::Atom(Rc::new(RefCell::new(e)))
+        let atom = Rc::new(RefCell::new(e));
+        gc::track_atom(&atom);
+        Self::Atom(atom)
+    }
+
+    /// Name of the value's type as shown in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Expr::Nil => "nil",
+            Expr::Bool(_) => "bool",
+            Expr::Int(_) => "int",
+            Expr::BigInt(_) => "big int",
+            Expr::Ratio(_) => "ratio",
+            Expr::Float(_) => "float",
+            Expr::String(_) => "string",
+            Expr::List(_) => "list",
+            Expr::Vector(_) => "vector",
+            Expr::Map(_) => "map",
+            Expr::SortedMap(_) => "sorted map",
+            Expr::Set(_) => "set",
+            Expr::Symbol(_) => "symbol",
+            Expr::Keyword(_) => "keyword",
+            Expr::Function(Function { is_macro: true, .. }) => "macro",
+            Expr::Function(_) | Expr::BuiltinFunction(_) | Expr::NativeFunction(_) => "function",
+            Expr::Atom(_) => "atom",
+            Expr::MacroExpand(_) => "macro expansion",
+            Expr::WithMeta { expr, .. } => expr.type_name(),
+        }
     }
 
     pub fn as_int(&self) -> Option<i64> {
@@ -46,15 +140,27 @@ impl Expr {
         }
     }
 
-    pub fn as_builtin(&self) -> Option<&'static str> {
+    /// Location of the symbol in the source it was parsed from, if known.
+    pub fn symbol_span(&self) -> Option<&Rc<Span>> {
+        match self.as_no_meta() {
+            Expr::Symbol(s) => s.span(),
+            _ => None,
+        }
+    }
+
+    pub fn as_builtin(&self) -> Option<SymbolId> {
         match self {
-            Expr::BuiltinFunction(s) => Some(s),
+            Expr::BuiltinFunction(id) => Some(*id),
             _ => None,
         }
     }
 
-    pub fn as_func_name(&self) -> Option<&str> {
-        self.as_symbol().or_else(|| self.as_builtin())
+    /// Name of a symbol or of a builtin function.
+    pub fn as_func_name(&self) -> Option<SymbolId> {
+        match self {
+            Expr::Symbol(s) => Some(s.id()),
+            _ => self.as_builtin(),
+        }
     }
 
     pub fn as_string(&self) -> Option<&str> {
@@ -64,31 +170,29 @@ impl Expr {
         }
     }
 
-    pub fn as_list_like(&self) -> Option<&[Expr]> {
+    pub fn as_list_like(&self) -> Option<ListLike<'_>> {
         match self {
-            Expr::List(l) | Expr::Vector(l) => Some(l),
+            Expr::List(l) => Some(ListLike::List(l)),
+            Expr::Vector(v) => Some(ListLike::Vector(v)),
             _ => None,
         }
     }
 
     pub fn into_list_like(self) -> Result<Vec<Expr>, Self> {
         match self {
-            Expr::List(l) | Expr::Vector(l) => Ok(l),
+            Expr::List(l) => Ok(l.to_vec()),
+            Expr::Vector(v) => Ok(v.to_vec()),
             _ => Err(self),
         }
     }
 
-    pub fn to_map_key(&self) -> Option<MapKey> {
-        match self {
-            Expr::String(s) => Some(MapKey::String(s.clone())),
-            Expr::Keyword(kw) => Some(MapKey::Keyword(kw.clone())),
-            _ => None,
-        }
+    pub fn to_map_key(&self) -> MapKey {
+        MapKey::new(self.clone())
     }
 
     pub fn as_no_meta(&self) -> &Self {
         match self {
-            Expr::WithMeta { expr, .. } => &*expr,
+            Expr::WithMeta { expr, .. } => expr,
             expr => expr,
         }
     }
@@ -101,48 +205,451 @@ impl Expr {
     }
 
     pub fn lenient_eq(&self, other: &Self) -> bool {
+        self.eq_by(other, |a, b| a == b)
+    }
+
+    /// Equality of map keys and set elements. Unlike [`Expr::lenient_eq`], floats with the
+    /// same bits are equal, so that a `##NaN` key can be found again, as its hash suggests.
+    fn key_eq(&self, other: &Self) -> bool {
+        self.eq_by(other, |a, b| a == b || a.to_bits() == b.to_bits())
+    }
+
+    fn eq_by(&self, other: &Self, float_eq: fn(f64, f64) -> bool) -> bool {
+        if let (Expr::Float(a), Expr::Float(b)) = (self, other) {
+            return float_eq(*a, *b);
+        }
         if self == other {
             return true;
         }
 
+        if let (Some(a), Some(b)) = (self.as_list_like(), other.as_list_like()) {
+            return a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a.eq_by(b, float_eq));
+        }
+
         match (self, other) {
-            (Expr::List(a) | Expr::Vector(a), Expr::List(b) | Expr::Vector(b)) => {
-                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.lenient_eq(b))
-            }
             (Expr::Map(a), Expr::Map(b)) => {
                 a.len() == b.len()
                     && a.iter()
-                        .all(|(k, v)| b.get(k).map(|v2| v.lenient_eq(v2)).unwrap_or(false))
+                        .all(|(k, v)| b.get(k).is_some_and(|v2| v.eq_by(v2, float_eq)))
+            }
+            (Expr::Map(a), Expr::SortedMap(b)) | (Expr::SortedMap(b), Expr::Map(a)) => {
+                a.len() == b.len()
+                    && b.iter().all(|(k, v)| {
+                        a.get(&k.to_map_key())
+                            .is_some_and(|v2| v.eq_by(v2, float_eq))
+                    })
+            }
+            // maps ordered the same way have equal keys at the same positions
+            (Expr::SortedMap(a), Expr::SortedMap(b)) if a.comparator() == b.comparator() => {
+                a.len() == b.len()
+                    && a.iter()
+                        .zip(b.iter())
+                        .all(|((k, v), (k2, v2))| k.eq_by(k2, float_eq) && v.eq_by(v2, float_eq))
+            }
+            (Expr::SortedMap(a), Expr::SortedMap(b)) => {
+                let b: FnvHashMap<_, _> = b.iter().map(|(k, v)| (k.to_map_key(), v)).collect();
+                a.len() == b.len()
+                    && a.iter().all(|(k, v)| {
+                        b.get(&k.to_map_key())
+                            .is_some_and(|v2| v.eq_by(v2, float_eq))
+                    })
             }
             _ => false,
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub enum MapKey {
-    String(String),
-    Keyword(Keyword),
+/// Borrowed list or vector, for code that treats both the same way.
+#[derive(Clone, Copy)]
+pub enum ListLike<'a> {
+    List(&'a List),
+    Vector(&'a Vector),
 }
 
+impl<'a> ListLike<'a> {
+    pub fn len(self) -> usize {
+        match self {
+            ListLike::List(l) => l.len(),
+            ListLike::Vector(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(self, index: usize) -> Option<&'a Expr> {
+        match self {
+            ListLike::List(l) => l.get(index),
+            ListLike::Vector(v) => v.get(index),
+        }
+    }
+
+    pub fn iter(self) -> ListLikeIter<'a> {
+        match self {
+            ListLike::List(l) => ListLikeIter::List(l.iter()),
+            ListLike::Vector(v) => ListLikeIter::Vector(v.iter()),
+        }
+    }
+
+    /// Items as a slice, copying them if they are in a vector.
+    pub fn to_slice(self) -> Cow<'a, [Expr]> {
+        match self {
+            ListLike::List(l) => Cow::Borrowed(l),
+            ListLike::Vector(v) => Cow::Owned(v.to_vec()),
+        }
+    }
+}
+
+pub enum ListLikeIter<'a> {
+    List(ListIter<'a, Expr>),
+    Vector(VectorIter<'a, Expr>),
+}
+
+impl<'a> Iterator for ListLikeIter<'a> {
+    type Item = &'a Expr;
+
+    fn next(&mut self) -> Option<&'a Expr> {
+        match self {
+            ListLikeIter::List(it) => it.next(),
+            ListLikeIter::Vector(it) => it.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            ListLikeIter::List(it) => it.size_hint(),
+            ListLikeIter::Vector(it) => it.size_hint(),
+        }
+    }
+}
+
+impl ExactSizeIterator for ListLikeIter<'_> {}
+
+/// Any value used as a hash-map key or set element.
+///
+/// Keys compare with [`Expr::lenient_eq`], so `[1 2]` and `(1 2)` are the same key,
+/// and their hash is computed from the structure consistently with it.
+/// Metadata on the key itself is dropped.
+#[derive(Debug, Clone)]
+pub struct MapKey(Expr);
+
 impl MapKey {
+    pub fn new(expr: Expr) -> Self {
+        Self(expr.into_no_meta())
+    }
+
+    pub fn as_expr(&self) -> &Expr {
+        &self.0
+    }
+
     pub fn to_expr(&self) -> Expr {
-        match self {
-            MapKey::String(s) => Expr::String(s.clone()),
-            MapKey::Keyword(kw) => Expr::Keyword(kw.clone()),
+        self.0.clone()
+    }
+
+    pub fn into_expr(self) -> Expr {
+        self.0
+    }
+}
+
+impl From<Expr> for MapKey {
+    fn from(expr: Expr) -> Self {
+        Self::new(expr)
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.key_eq(&other.0)
+    }
+}
+
+impl Eq for MapKey {}
+
+impl Hash for MapKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_expr(&self.0, state)
+    }
+}
+
+/// Hashes `expr` so that values equal by [`Expr::lenient_eq`] get the same hash.
+fn hash_expr<H: Hasher>(expr: &Expr, state: &mut H) {
+    match expr {
+        Expr::WithMeta { expr, .. } => return hash_expr(expr, state),
+        // lists and vectors can be equal, so they don't hash their variant, same for maps
+        Expr::List(_) | Expr::Vector(_) | Expr::Map(_) | Expr::SortedMap(_) => {}
+        _ => std::mem::discriminant(expr).hash(state),
+    }
+
+    match expr {
+        Expr::Bool(b) => b.hash(state),
+        Expr::Int(i) => i.hash(state),
+        Expr::BigInt(b) => b.hash(state),
+        Expr::Ratio(r) => r.hash(state),
+        // 0.0 and -0.0 are equal
+        Expr::Float(f) => (if *f == 0.0 { 0.0 } else { *f }).to_bits().hash(state),
+        Expr::String(s) => s.hash(state),
+        Expr::Symbol(s) => s.hash(state),
+        Expr::Keyword(kw) => kw.hash(state),
+        Expr::List(_) | Expr::Vector(_) => {
+            let items = expr.as_list_like().expect("matched a list or vector");
+            items.len().hash(state);
+            for item in items.iter() {
+                hash_expr(item, state);
+            }
         }
+        Expr::Map(map) => hash_entries(map.iter().map(|(k, v)| (k.as_expr(), v)), state),
+        Expr::SortedMap(map) => hash_entries(map.iter(), state),
+        Expr::Set(set) => {
+            let elements = set.iter().fold(0u64, |acc, k| {
+                let mut hasher = fnv::FnvHasher::default();
+                k.hash(&mut hasher);
+                acc.wrapping_add(hasher.finish())
+            });
+            set.len().hash(state);
+            elements.hash(state);
+        }
+        _ => {}
     }
 }
 
+/// Hashes map entries independently of their order.
+fn hash_entries<'a, H: Hasher>(
+    entries: impl ExactSizeIterator<Item = (&'a Expr, &'a Expr)>,
+    state: &mut H,
+) {
+    let len = entries.len();
+    // entries are unordered, so their hashes are combined with a commutative operation
+    let combined = entries.fold(0u64, |acc, (k, v)| {
+        let mut hasher = fnv::FnvHasher::default();
+        hash_expr(k, &mut hasher);
+        hash_expr(v, &mut hasher);
+        acc.wrapping_add(hasher.finish())
+    });
+    len.hash(state);
+    combined.hash(state);
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Function {
-    pub bindings: Vec<String>,
-    pub varargs: Option<String>,
-    pub expr: Rc<Expr>,
+    pub name: Option<Rc<str>>,
+    /// Parameter lists with their bodies, at most one of them is variadic.
+    pub arities: Rc<[FunctionArity]>,
     pub closure: Env,
     pub is_macro: bool,
 }
 
+impl Function {
+    /// Picks the body to run for `args` arguments, preferring a fixed arity over the variadic one.
+    pub fn find_arity(&self, args: usize) -> Option<&FunctionArity> {
+        let mut variadic = None;
+        for arity in self.arities.iter() {
+            match arity.varargs {
+                None if arity.bindings.len() == args => return Some(arity),
+                Some(_) if arity.bindings.len() <= args => variadic = Some(arity),
+                _ => {}
+            }
+        }
+        variadic
+    }
+
+    pub fn arities(&self) -> Vec<Arity> {
+        self.arities.iter().map(FunctionArity::arity).collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionArity {
+    pub bindings: Vec<Pattern>,
+    pub varargs: Option<Pattern>,
+    pub expr: Rc<Expr>,
+    /// Whether the parameters are distinct symbols, so arguments fill the slots of a call
+    /// in order without destructuring.
+    pub(crate) positional: bool,
+    pub(crate) compiled: compile::Cache,
+    pub(crate) bytecode: vm::Cache,
+}
+
+impl FunctionArity {
+    pub fn new(bindings: Vec<Pattern>, varargs: Option<Pattern>, expr: Rc<Expr>) -> Self {
+        let mut names = vec![];
+        let positional = bindings.iter().chain(&varargs).all(|param| match param {
+            Pattern::Symbol(name) if !names.contains(name) => {
+                names.push(*name);
+                true
+            }
+            _ => false,
+        });
+        Self {
+            positional,
+            bindings,
+            varargs,
+            expr,
+            compiled: compile::Cache::default(),
+            bytecode: vm::Cache::default(),
+        }
+    }
+
+    pub fn arity(&self) -> Arity {
+        Arity {
+            min: self.bindings.len(),
+            max: self.varargs.is_none().then_some(self.bindings.len()),
+        }
+    }
+}
+
+pub type NativeFn = dyn Fn(&[Expr], &Env) -> EvalResult<Expr>;
+
+/// Rust closure registered by the host application, called with evaluated arguments.
+///
+/// Two native functions are equal only if they share the same closure.
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: Rc<str>,
+    pub arity: Arity,
+    pub func: Rc<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        arity: impl Into<Arity>,
+        func: impl Fn(&[Expr], &Env) -> EvalResult<Expr> + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            arity: arity.into(),
+            func: Rc::new(func),
+        }
+    }
+}
+
+impl PartialEq for NativeFunction {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.func, &other.func)
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Number of arguments a function accepts, `max` is `None` for variadic functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn accepts(&self, args: usize) -> bool {
+        args >= self.min && self.max.is_none_or(|max| args <= max)
+    }
+}
+
+impl From<usize> for Arity {
+    fn from(n: usize) -> Self {
+        Self {
+            min: n,
+            max: Some(n),
+        }
+    }
+}
+
+impl From<RangeInclusive<usize>> for Arity {
+    fn from(range: RangeInclusive<usize>) -> Self {
+        Self {
+            min: *range.start(),
+            max: Some(*range.end()),
+        }
+    }
+}
+
+impl From<RangeFrom<usize>> for Arity {
+    fn from(range: RangeFrom<usize>) -> Self {
+        Self {
+            min: range.start,
+            max: None,
+        }
+    }
+}
+
+/// Interned symbol name, optionally remembering where it was read from.
+///
+/// The span doesn't take part in comparisons, two symbols are equal when their names are.
+#[derive(Clone)]
+pub struct Symbol {
+    id: SymbolId,
+    span: Option<Rc<Span>>,
+}
+
+impl Symbol {
+    pub fn new(name: &str) -> Self {
+        Self {
+            id: SymbolId::intern(name),
+            span: None,
+        }
+    }
+
+    pub fn with_span(name: impl Into<SymbolId>, span: Span) -> Self {
+        Self {
+            id: name.into(),
+            span: Some(Rc::new(span)),
+        }
+    }
+
+    pub fn id(&self) -> SymbolId {
+        self.id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.id.name()
+    }
+
+    pub fn span(&self) -> Option<&Rc<Span>> {
+        self.span.as_ref()
+    }
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.name()
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Symbol {}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state)
+    }
+}
+
+impl From<&Symbol> for SymbolId {
+    fn from(symbol: &Symbol) -> Self {
+        symbol.id
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.id, f)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Keyword(Rc<str>);
 
@@ -150,6 +657,11 @@ impl Keyword {
     pub fn new(s: &str) -> Self {
         Self(format!("\0{s}\0").into())
     }
+
+    /// Keyword name without the leading colon.
+    pub fn name(&self) -> &str {
+        self.0.trim_matches('\0')
+    }
 }
 
 impl AsRef<str> for Keyword {
@@ -157,3 +669,59 @@ impl AsRef<str> for Keyword {
         &*self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::hash::BuildHasher;
+
+    use fnv::FnvBuildHasher;
+
+    use super::MapKey;
+    use crate::{eval::check, parser::parse};
+
+    fn key(s: &str) -> MapKey {
+        MapKey::new(parse(s).unwrap())
+    }
+
+    #[test]
+    fn equal_keys_hash_equally() {
+        let hasher = FnvBuildHasher::default();
+        let hash = |k: &MapKey| hasher.hash_one(k);
+
+        for (a, b) in [
+            ("[1 [2 3]]", "(1 (2 3))"),
+            ("{:a 1 :b [2]}", "{:b (2) :a 1}"),
+            ("0.0", "-0.0"),
+            ("##NaN", "##NaN"),
+            ("[1 ##NaN]", "(1 ##NaN)"),
+        ] {
+            let (a, b) = (key(a), key(b));
+            assert_eq!(a, b);
+            assert_eq!(hash(&a), hash(&b), "{a} {b}");
+        }
+
+        assert_ne!(key("1"), key("1.0"));
+        assert_ne!(key("\"a\""), key(":a"));
+        assert_ne!(key("a"), key("\"a\""));
+    }
+
+    #[test]
+    fn nan_keys_and_sorted_maps() {
+        check(&[
+            ("(= ##NaN ##NaN)", "false"),
+            ("(get {##NaN 1} ##NaN)", "1"),
+            ("(get {[##NaN] 1} [##NaN])", "1"),
+            ("(contains? #{##NaN} ##NaN)", "true"),
+            ("(= (sorted-map 1 :a 2 :b) (sorted-map 2 :b 1 :a))", "true"),
+            ("(= (sorted-map 1 :a 2 :b) (sorted-map 1 :a 2 :c))", "false"),
+            (
+                "(= (sorted-map 1 :a 2 :b) (sorted-map-by > 1 :a 2 :b))",
+                "true",
+            ),
+            (
+                "(= (sorted-map 1 :a 2 :b) (sorted-map-by > 1 :a 3 :b))",
+                "false",
+            ),
+        ]);
+    }
+}
@@ -0,0 +1,281 @@
+use std::{collections::HashMap, hash::BuildHasher, rc::Rc};
+
+use crate::eval::{EvalError, EvalResult};
+
+use super::{
+    number::{BigInt, Number},
+    Expr, Keyword, ListLike, MapKey,
+};
+
+/// Conversion from a mal value into a Rust value, used to unpack native function arguments.
+///
+/// Metadata is ignored, so `(with-meta [1 2] {})` converts like `[1 2]`.
+pub trait FromExpr: Sized {
+    fn from_expr(expr: &Expr) -> EvalResult<Self>;
+}
+
+/// Conversion from a Rust value into a mal value, used to return native function results.
+pub trait IntoExpr {
+    fn into_expr(self) -> Expr;
+}
+
+fn mismatch<T>(expected: &'static str, actual: &Expr) -> EvalResult<T> {
+    Err(EvalError::TypeMismatch {
+        expected,
+        actual: actual.type_name(),
+    })
+}
+
+impl FromExpr for Expr {
+    fn from_expr(expr: &Expr) -> EvalResult<Self> {
+        Ok(expr.clone())
+    }
+}
+
+impl IntoExpr for Expr {
+    fn into_expr(self) -> Expr {
+        self
+    }
+}
+
+impl IntoExpr for () {
+    fn into_expr(self) -> Expr {
+        Expr::Nil
+    }
+}
+
+macro_rules! int_conversions {
+    ( $( $ty:ty ),* ) => {
+        $(
+            impl FromExpr for $ty {
+                fn from_expr(expr: &Expr) -> EvalResult<Self> {
+                    let out_of_range = || EvalError::IntegerOutOfRange {
+                        value: expr.to_string(),
+                        target: stringify!($ty),
+                    };
+                    match expr.as_no_meta() {
+                        Expr::Int(i) => (*i).try_into().map_err(|_| out_of_range()),
+                        Expr::BigInt(b) => {
+                            let i = b.to_i64().map(i128::from).or(b.to_u64().map(i128::from));
+                            i.and_then(|i| i.try_into().ok()).ok_or_else(out_of_range)
+                        }
+                        _ => mismatch("int", expr),
+                    }
+                }
+            }
+
+            impl IntoExpr for $ty {
+                fn into_expr(self) -> Expr {
+                    match i64::try_from(self) {
+                        Ok(i) => Expr::Int(i),
+                        // only u64 and usize can get here, they always fit in a u64
+                        Err(_) => Expr::BigInt(Rc::new(BigInt::from_u64(self as u64))),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+int_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl FromExpr for f64 {
+    /// Accepts any number, exact ones are rounded to the nearest float.
+    fn from_expr(expr: &Expr) -> EvalResult<Self> {
+        match Number::from_expr(expr) {
+            Some(n) => Ok(n.to_f64()),
+            None => mismatch("number", expr),
+        }
+    }
+}
+
+impl IntoExpr for f64 {
+    fn into_expr(self) -> Expr {
+        Expr::Float(self)
+    }
+}
+
+impl FromExpr for bool {
+    fn from_expr(expr: &Expr) -> EvalResult<Self> {
+        match expr.as_no_meta() {
+            Expr::Bool(b) => Ok(*b),
+            _ => mismatch("bool", expr),
+        }
+    }
+}
+
+impl IntoExpr for bool {
+    fn into_expr(self) -> Expr {
+        Expr::Bool(self)
+    }
+}
+
+impl FromExpr for String {
+    fn from_expr(expr: &Expr) -> EvalResult<Self> {
+        match expr.as_no_meta() {
+            Expr::String(s) => Ok(s.clone()),
+            _ => mismatch("string", expr),
+        }
+    }
+}
+
+impl IntoExpr for String {
+    fn into_expr(self) -> Expr {
+        Expr::String(self)
+    }
+}
+
+impl IntoExpr for &str {
+    fn into_expr(self) -> Expr {
+        Expr::String(self.to_owned())
+    }
+}
+
+impl FromExpr for Keyword {
+    fn from_expr(expr: &Expr) -> EvalResult<Self> {
+        match expr.as_no_meta() {
+            Expr::Keyword(kw) => Ok(kw.clone()),
+            _ => mismatch("keyword", expr),
+        }
+    }
+}
+
+impl IntoExpr for Keyword {
+    fn into_expr(self) -> Expr {
+        Expr::Keyword(self)
+    }
+}
+
+/// `nil` converts to `None`, anything else must convert to `T`.
+impl<T: FromExpr> FromExpr for Option<T> {
+    fn from_expr(expr: &Expr) -> EvalResult<Self> {
+        match expr.as_no_meta() {
+            Expr::Nil => Ok(None),
+            _ => T::from_expr(expr).map(Some),
+        }
+    }
+}
+
+impl<T: IntoExpr> IntoExpr for Option<T> {
+    fn into_expr(self) -> Expr {
+        self.map_or(Expr::Nil, T::into_expr)
+    }
+}
+
+/// Accepts both lists and vectors.
+impl<T: FromExpr> FromExpr for Vec<T> {
+    fn from_expr(expr: &Expr) -> EvalResult<Self> {
+        match expr.as_no_meta().as_list_like() {
+            Some(items) => items.iter().map(T::from_expr).collect(),
+            None => mismatch("list or vector", expr),
+        }
+    }
+}
+
+impl<T: IntoExpr> IntoExpr for Vec<T> {
+    fn into_expr(self) -> Expr {
+        Expr::List(self.into_iter().map(T::into_expr).collect())
+    }
+}
+
+/// Accepts string keys as well as keywords, which are converted to their name without the colon.
+impl<T: FromExpr, S: BuildHasher + Default> FromExpr for HashMap<String, T, S> {
+    fn from_expr(expr: &Expr) -> EvalResult<Self> {
+        let entries: Vec<(&Expr, &Expr)> = match expr.as_no_meta() {
+            Expr::Map(map) => map.iter().map(|(k, v)| (k.as_expr(), v)).collect(),
+            Expr::SortedMap(map) => map.iter().collect(),
+            _ => return mismatch("map", expr),
+        };
+        entries
+            .into_iter()
+            .map(|(k, v)| {
+                let k = match k {
+                    Expr::String(s) => s.clone(),
+                    Expr::Keyword(kw) => kw.name().to_owned(),
+                    k => return mismatch("string or keyword", k),
+                };
+                Ok((k, T::from_expr(v)?))
+            })
+            .collect()
+    }
+}
+
+/// Keys become strings.
+impl<T: IntoExpr, S> IntoExpr for HashMap<String, T, S> {
+    fn into_expr(self) -> Expr {
+        let map = self
+            .into_iter()
+            .map(|(k, v)| (MapKey::new(Expr::String(k)), v.into_expr()))
+            .collect();
+        Expr::Map(Rc::new(map))
+    }
+}
+
+macro_rules! tuple_conversions {
+    ( $( ( $( $ty:ident ),+ ) ),* ) => {
+        $(
+            /// Accepts a list or vector with exactly as many items as the tuple.
+            impl<$( $ty: FromExpr ),+> FromExpr for ( $( $ty, )+ ) {
+                fn from_expr(expr: &Expr) -> EvalResult<Self> {
+                    #[allow(non_snake_case)]
+                    match expr.as_no_meta().as_list_like().map(ListLike::to_slice).as_deref() {
+                        Some([ $( $ty ),+ ]) => Ok(( $( $ty::from_expr($ty)?, )+ )),
+                        Some(_) => Err(EvalError::InvalidArgumentCount(vec![])),
+                        None => mismatch("list or vector", expr),
+                    }
+                }
+            }
+
+            impl<$( $ty: IntoExpr ),+> IntoExpr for ( $( $ty, )+ ) {
+                fn into_expr(self) -> Expr {
+                    #[allow(non_snake_case)]
+                    let ( $( $ty, )+ ) = self;
+                    Expr::List(vec![ $( $ty.into_expr() ),+ ].into())
+                }
+            }
+        )*
+    };
+}
+
+tuple_conversions!((A), (A, B), (A, B, C), (A, B, C, D));
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{FromExpr, IntoExpr};
+    use crate::{eval::EvalError, parser::parse};
+
+    fn convert<T: FromExpr>(s: &str) -> Result<T, EvalError> {
+        T::from_expr(&parse(s).unwrap())
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(convert::<Vec<i32>>("[1 2 3]").unwrap(), vec![1, 2, 3]);
+        assert_eq!(convert::<Option<bool>>("nil").unwrap(), None);
+        assert_eq!(
+            convert::<(String, u8)>("(\"a\" 1)").unwrap(),
+            ("a".to_owned(), 1)
+        );
+
+        let map = convert::<HashMap<String, i64>>("{:a 1 \"b\" 2}").unwrap();
+        assert_eq!(map, HashMap::from([("a".into(), 1), ("b".into(), 2)]));
+
+        assert_eq!(u64::MAX.into_expr().to_string(), "18446744073709551615");
+        assert_eq!(convert::<u64>("18446744073709551615").unwrap(), u64::MAX);
+        assert_eq!((1, Some("x"), ()).into_expr().to_string(), "(1 x nil)");
+    }
+
+    #[test]
+    fn errors() {
+        let err = convert::<String>("12").unwrap_err();
+        assert_eq!(err.to_string(), "type mismatch: expected string, got int");
+
+        let err = convert::<Vec<bool>>("[true :x]").unwrap_err();
+        assert_eq!(err.to_string(), "type mismatch: expected bool, got keyword");
+
+        let err = convert::<u8>("300").unwrap_err();
+        assert_eq!(err.to_string(), "300 does not fit in u8");
+    }
+}
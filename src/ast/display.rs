@@ -1,18 +1,69 @@
-use std::fmt::{self, Write};
+use std::{
+    cell::Cell,
+    fmt::{self, Write},
+};
 
-use super::{Expr, Keyword, MapKey};
+use super::{number::format_float, Arity, Expr, Keyword, MapKey, Symbol};
+
+thread_local! {
+    static SORTED_PRINTING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Makes hash maps and sets print their entries ordered by [`Expr::total_cmp`]
+/// instead of in hash order, so that the output is deterministic.
+///
+/// The setting applies to the current thread.
+pub fn set_sorted_printing(enabled: bool) {
+    SORTED_PRINTING.with(|sorted| sorted.set(enabled));
+}
+
+pub fn sorted_printing() -> bool {
+    SORTED_PRINTING.with(Cell::get)
+}
 
 impl fmt::Display for Keyword {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_char(':')?;
-        f.write_str(self.as_ref().trim_matches('\0'))?;
+        f.write_str(self.name())?;
         Ok(())
     }
 }
 
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self)
+    }
+}
+
 impl fmt::Display for MapKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Display::fmt(&self.to_expr(), f)
+        fmt::Display::fmt(self.as_expr(), f)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{max}"),
+            Some(max) => write!(f, "{} to {max}", self.min),
+            None => write!(f, "at least {}", self.min),
+        }
+    }
+}
+
+/// Formats the accepted arities for an error message, nothing if they're unknown.
+pub struct Expected<'a>(pub &'a [Arity]);
+
+impl fmt::Display for Expected<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some((last, init)) = self.0.split_last() else {
+            return Ok(());
+        };
+        f.write_str(", expected ")?;
+        if !init.is_empty() {
+            write!(f, "{} or ", Join(init, ", "))?;
+        }
+        write!(f, "{last}")
     }
 }
 
@@ -57,6 +108,9 @@ impl fmt::Display for Expr {
             Expr::Nil => f.write_str("nil"),
             Expr::Bool(b) => write!(f, "{b}"),
             Expr::Int(i) => write!(f, "{i}"),
+            Expr::BigInt(i) => write!(f, "{i}"),
+            Expr::Ratio(r) => write!(f, "{r}"),
+            Expr::Float(x) => f.write_str(&format_float(*x)),
             Expr::String(s) => {
                 if f.alternate() {
                     // prints escaped strings
@@ -70,15 +124,30 @@ impl fmt::Display for Expr {
             Expr::Vector(vector) => {
                 fmt::Display::fmt(&Surrounded(Join(vector, " "), ['[', ']']), f)
             }
-            Expr::Map(map) => {
-                let items = map
+            Expr::Map(map) if sorted_printing() => {
+                let mut entries = map
                     .iter()
-                    .flat_map(|(k, v)| [k as &dyn fmt::Display, v as _]);
-                fmt::Display::fmt(&Surrounded(Join(items, " "), ['{', '}']), f)
+                    .map(|(k, v)| (k.as_expr(), v))
+                    .collect::<Vec<_>>();
+                entries.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+                fmt_entries(entries.into_iter(), f)
+            }
+            Expr::Map(map) => fmt_entries(map.iter().map(|(k, v)| (k.as_expr(), v)), f),
+            Expr::SortedMap(map) => fmt_entries(map.iter(), f),
+            Expr::Set(set) if sorted_printing() => {
+                let mut elements = set.iter().map(MapKey::as_expr).collect::<Vec<_>>();
+                elements.sort_by(|a, b| a.total_cmp(b));
+                f.write_char('#')?;
+                fmt::Display::fmt(&Surrounded(Join(elements, " "), ['{', '}']), f)
+            }
+            Expr::Set(set) => {
+                f.write_char('#')?;
+                fmt::Display::fmt(&Surrounded(Join(set.iter(), " "), ['{', '}']), f)
             }
             Expr::Symbol(s) => write!(f, "{s}"),
             Expr::Function(_) => f.write_str("#<function>"),
             Expr::BuiltinFunction(fname) => write!(f, "{fname}"),
+            Expr::NativeFunction(native) => write!(f, "#<native-function {}>", native.name),
             Expr::Atom(a) => {
                 f.write_str("(atom ")?;
                 fmt::Display::fmt(&*a.borrow(), f)?;
@@ -90,3 +159,11 @@ impl fmt::Display for Expr {
         }
     }
 }
+
+fn fmt_entries<'a>(
+    entries: impl Iterator<Item = (&'a Expr, &'a Expr)> + Clone,
+    f: &mut fmt::Formatter<'_>,
+) -> fmt::Result {
+    let items = entries.flat_map(|(k, v)| [k, v]);
+    fmt::Display::fmt(&Surrounded(Join(items, " "), ['{', '}']), f)
+}
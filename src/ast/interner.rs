@@ -0,0 +1,227 @@
+//! Interning of symbol names, so that symbols are compared and hashed as integers.
+//!
+//! The interner is shared by all threads and names are never freed, so the names scripts
+//! add by reading code count against the element limit of
+//! [`EvalLimits`](crate::eval::limits::EvalLimits). Each thread keeps a copy of the names it
+//! has seen, so looking them up doesn't lock the interner. Builtin names are interned first,
+//! in the order of [`BUILTINS`] and then [`THUNK_BUILTINS`], so the ID of a builtin is its
+//! index in those tables and finding it doesn't need a lookup.
+
+use std::{
+    cell::RefCell,
+    fmt,
+    sync::{LazyLock, PoisonError, RwLock, RwLockReadGuard},
+};
+
+use fnv::FnvHashMap;
+
+use crate::eval::{
+    builtins::{BUILTINS, THUNK_BUILTINS},
+    limits::{self, Limit},
+};
+
+/// Names interned after the builtins, which the evaluator recognizes without them being bound.
+const SPECIAL: &[&str] = &["unquote", "splice-unquote"];
+
+const PREINTERNED: usize = BUILTINS.len() + THUNK_BUILTINS.len() + SPECIAL.len();
+
+/// Interned symbol name.
+///
+/// Two IDs are equal when their names are, IDs of builtins are the same in every run.
+///
+/// ```
+/// use rust2::ast::interner::SymbolId;
+///
+/// let id = SymbolId::intern("my-var");
+/// assert_eq!(id, SymbolId::intern("my-var"));
+/// assert_eq!(id.name(), "my-var");
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SymbolId(u32);
+
+#[derive(Default)]
+struct Interner {
+    ids: FnvHashMap<&'static str, SymbolId>,
+    names: Vec<&'static str>,
+}
+
+static INTERNER: LazyLock<RwLock<Interner>> = LazyLock::new(|| {
+    let mut interner = Interner::default();
+    for index in 0..PREINTERNED {
+        let name = preinterned_name(index);
+        let id = interner.insert(name);
+        assert_eq!(id.index(), index, "{name} is interned twice");
+    }
+    RwLock::new(interner)
+});
+
+thread_local! {
+    /// The part of [`INTERNER`] the thread has seen.
+    static LOCAL: RefCell<Interner> = RefCell::default();
+}
+
+fn interner() -> RwLockReadGuard<'static, Interner> {
+    INTERNER.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Interner {
+    fn insert(&mut self, name: &str) -> SymbolId {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = SymbolId(u32::try_from(self.names.len()).expect("too many symbols"));
+        let name: &'static str = Box::leak(name.into());
+        self.names.push(name);
+        self.ids.insert(name, id);
+        id
+    }
+}
+
+impl SymbolId {
+    pub(crate) const UNQUOTE: Self = Self::preinterned("unquote");
+    pub(crate) const SPLICE_UNQUOTE: Self = Self::preinterned("splice-unquote");
+
+    pub fn intern(name: &str) -> Self {
+        Self::lookup(name).unwrap_or_else(|| Self::insert(name))
+    }
+
+    /// Interns a name read from a script, counting it as an element if it's new.
+    pub(crate) fn try_intern(name: &str) -> Result<Self, Limit> {
+        match Self::lookup(name) {
+            Some(id) => Ok(id),
+            None => {
+                limits::count_elements(1)?;
+                Ok(Self::insert(name))
+            }
+        }
+    }
+
+    fn lookup(name: &str) -> Option<Self> {
+        LOCAL.with(|local| {
+            if let Some(&id) = local.borrow().ids.get(name) {
+                return Some(id);
+            }
+            let interner = interner();
+            let id = *interner.ids.get(name)?;
+            local
+                .borrow_mut()
+                .ids
+                .insert(interner.names[id.index()], id);
+            Some(id)
+        })
+    }
+
+    fn insert(name: &str) -> Self {
+        INTERNER
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name)
+    }
+
+    pub fn name(self) -> &'static str {
+        LOCAL.with(|local| {
+            if let Some(&name) = local.borrow().names.get(self.index()) {
+                return name;
+            }
+            let names = &mut local.borrow_mut().names;
+            names.extend_from_slice(&interner().names[names.len()..]);
+            names[self.index()]
+        })
+    }
+
+    /// Position of the name in the order of interning, builtins come first.
+    pub(crate) fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    /// ID of a builtin or of one of the names in `SPECIAL`, found at compile time.
+    pub(crate) const fn preinterned(name: &str) -> Self {
+        let mut index = 0;
+        while index < PREINTERNED {
+            if str_eq(preinterned_name(index), name) {
+                return Self(index as u32);
+            }
+            index += 1;
+        }
+        panic!("name isn't interned in advance")
+    }
+}
+
+const fn preinterned_name(index: usize) -> &'static str {
+    if index < BUILTINS.len() {
+        BUILTINS[index].0
+    } else if index < BUILTINS.len() + THUNK_BUILTINS.len() {
+        THUNK_BUILTINS[index - BUILTINS.len()].0
+    } else {
+        SPECIAL[index - BUILTINS.len() - THUNK_BUILTINS.len()]
+    }
+}
+
+const fn str_eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+impl From<&str> for SymbolId {
+    fn from(name: &str) -> Self {
+        Self::intern(name)
+    }
+}
+
+impl fmt::Display for SymbolId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl fmt::Debug for SymbolId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.name(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SymbolId;
+    use crate::eval::builtins::{BUILTINS, THUNK_BUILTINS};
+
+    #[test]
+    fn builtins_are_interned_first() {
+        let tables = BUILTINS
+            .iter()
+            .map(|&(name, _)| name)
+            .chain(THUNK_BUILTINS.iter().map(|&(name, _)| name));
+        for (index, name) in tables.enumerate() {
+            let id = SymbolId::intern(name);
+            assert_eq!(id.index(), index, "{name}");
+            assert_eq!(id, SymbolId::preinterned(name));
+        }
+        assert_eq!(SymbolId::UNQUOTE.name(), "unquote");
+
+        let id = SymbolId::intern("not-a-builtin");
+        assert!(id.index() >= BUILTINS.len() + THUNK_BUILTINS.len());
+        assert_eq!(SymbolId::intern(&String::from("not-a-builtin")), id);
+    }
+
+    #[test]
+    fn threads_see_each_others_names() {
+        let id = SymbolId::intern("interned-on-main");
+        let (name, other) =
+            std::thread::spawn(move || (id.name(), SymbolId::intern("interned-on-other")))
+                .join()
+                .unwrap();
+        assert_eq!(name, "interned-on-main");
+        assert_eq!(other.name(), "interned-on-other");
+        assert_eq!(SymbolId::intern("interned-on-other"), other);
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    cmp::Ordering,
+    ops::{Add, Mul, Sub},
+    rc::Rc,
+};
+
+use super::Expr;
+
+mod bigint;
+mod ratio;
+pub use bigint::BigInt;
+pub use ratio::{parse_ratio, Ratio};
+
+/// Numeric value extracted from an [`Expr`].
+///
+/// Integer operations that overflow promote to [`BigInt`], dividing integers that don't
+/// divide evenly gives a [`Ratio`], anything mixed with a float is computed as a float.
+#[derive(Debug, Clone)]
+pub enum Number {
+    Int(i64),
+    Big(BigInt),
+    Ratio(Ratio),
+    Float(f64),
+}
+
+impl Number {
+    pub fn from_expr(expr: &Expr) -> Option<Self> {
+        match expr.as_no_meta() {
+            Expr::Int(i) => Some(Self::Int(*i)),
+            Expr::BigInt(b) => Some(Self::Big(BigInt::clone(b))),
+            Expr::Ratio(r) => Some(Self::Ratio(Ratio::clone(r))),
+            Expr::Float(f) => Some(Self::Float(*f)),
+            _ => None,
+        }
+    }
+
+    pub fn to_expr(self) -> Expr {
+        match self {
+            Self::Int(i) => Expr::Int(i),
+            Self::Big(b) => Expr::BigInt(Rc::new(b)),
+            Self::Ratio(r) => Expr::Ratio(Rc::new(r)),
+            Self::Float(f) => Expr::Float(f),
+        }
+    }
+
+    /// Narrows `b` back to a machine integer if it fits.
+    pub fn from_big(b: BigInt) -> Self {
+        match b.to_i64() {
+            Some(i) => Self::Int(i),
+            None => Self::Big(b),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Self::Int(i) => *i as f64,
+            Self::Big(b) => b.to_f64(),
+            Self::Ratio(r) => r.to_f64(),
+            Self::Float(f) => *f,
+        }
+    }
+
+    fn to_big(&self) -> BigInt {
+        match self {
+            Self::Int(i) => BigInt::from(*i),
+            Self::Big(b) => b.clone(),
+            Self::Ratio(_) | Self::Float(_) => unreachable!("only integers are promoted to big"),
+        }
+    }
+
+    fn to_ratio(&self) -> Ratio {
+        match self {
+            Self::Ratio(r) => r.clone(),
+            Self::Float(_) => unreachable!("floats are never promoted to ratios"),
+            n => Ratio::from_integer(n.to_big()),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        match self {
+            Self::Int(i) => *i == 0,
+            Self::Big(b) => b.is_zero(),
+            Self::Ratio(_) => false,
+            Self::Float(f) => *f == 0.0,
+        }
+    }
+
+    pub fn is_nan(&self) -> bool {
+        matches!(self, Self::Float(f) if f.is_nan())
+    }
+
+    /// `None` for non-integers.
+    pub fn is_even(&self) -> Option<bool> {
+        match self {
+            Self::Int(i) => Some(i % 2 == 0),
+            Self::Big(b) => Some(b.is_even()),
+            Self::Ratio(_) | Self::Float(_) => None,
+        }
+    }
+
+    pub fn abs(self) -> Self {
+        match self {
+            Self::Int(i) => match i.checked_abs() {
+                Some(i) => Self::Int(i),
+                None => Self::Big(BigInt::from(i).abs()),
+            },
+            Self::Big(b) => Self::Big(b.abs()),
+            Self::Ratio(r) if r.numer().is_negative() => Self::Int(0) - Self::Ratio(r),
+            Self::Ratio(r) => Self::Ratio(r),
+            Self::Float(f) => Self::Float(f.abs()),
+        }
+    }
+
+    fn apply(
+        self,
+        rhs: Self,
+        int_op: impl FnOnce(i64, i64) -> Option<i64>,
+        big_op: impl FnOnce(&BigInt, &BigInt) -> BigInt,
+        ratio_op: impl FnOnce(&Ratio, &Ratio) -> Self,
+        float_op: impl FnOnce(f64, f64) -> f64,
+    ) -> Self {
+        match (self, rhs) {
+            (Self::Int(a), Self::Int(b)) => match int_op(a, b) {
+                Some(i) => Self::Int(i),
+                None => Self::from_big(big_op(&a.into(), &b.into())),
+            },
+            (a @ Self::Float(_), b) | (a, b @ Self::Float(_)) => {
+                Self::Float(float_op(a.to_f64(), b.to_f64()))
+            }
+            (a @ Self::Ratio(_), b) | (a, b @ Self::Ratio(_)) => {
+                ratio_op(&a.to_ratio(), &b.to_ratio())
+            }
+            (a, b) => Self::from_big(big_op(&a.to_big(), &b.to_big())),
+        }
+    }
+
+    /// Exact division, `None` if a rational number is divided by zero.
+    pub fn checked_div(self, rhs: Self) -> Option<Self> {
+        match (self, rhs) {
+            (a @ Self::Float(_), b) | (a, b @ Self::Float(_)) => {
+                Some(Self::Float(a.to_f64() / b.to_f64()))
+            }
+            (Self::Int(a), Self::Int(b)) if b != 0 && a.checked_rem(b) == Some(0) => {
+                match a.checked_div(b) {
+                    Some(i) => Some(Self::Int(i)),
+                    None => Some(Self::from_big(-&BigInt::from(a))),
+                }
+            }
+            (a, b) => a.to_ratio().div(&b.to_ratio()),
+        }
+    }
+
+    /// Quotient rounded towards zero, `None` when dividing by zero.
+    pub fn checked_quot(self, rhs: Self) -> Option<Self> {
+        if rhs.is_zero() {
+            return None;
+        }
+        match self.checked_div(rhs)? {
+            Self::Ratio(r) => Some(Self::from_big(r.trunc())),
+            Self::Float(f) => Some(Self::Float(f.trunc())),
+            n => Some(n),
+        }
+    }
+
+    /// Remainder of [`Number::checked_quot`], has the sign of `self`. `None` if an exact
+    /// number is divided by zero, while floats give NaN.
+    pub fn checked_rem(self, rhs: Self) -> Option<Self> {
+        match (self, rhs) {
+            (a @ Self::Float(_), b) | (a, b @ Self::Float(_)) => {
+                Some(Self::Float(a.to_f64() % b.to_f64()))
+            }
+            (_, rhs) if rhs.is_zero() => None,
+            (Self::Int(a), Self::Int(b)) => Some(Self::Int(a.checked_rem(b).unwrap_or(0))),
+            (a, b) => {
+                let quot = a.clone().checked_quot(b.clone())?;
+                Some(a - b * quot)
+            }
+        }
+    }
+
+    /// Remainder of floored division, has the sign of `rhs`.
+    pub fn checked_mod(self, rhs: Self) -> Option<Self> {
+        let rem = self.checked_rem(rhs.clone())?;
+        let zero = Self::Int(0);
+        if !rem.is_zero() && (rem < zero) != (rhs < zero) {
+            Some(rem + rhs)
+        } else {
+            Some(rem)
+        }
+    }
+}
+
+macro_rules! number_binop {
+    ( $trait:ident :: $method:ident ( $op:tt, $checked:ident ) ) => {
+        impl $trait for Number {
+            type Output = Number;
+
+            fn $method(self, rhs: Self) -> Self {
+                self.apply(
+                    rhs,
+                    i64::$checked,
+                    |a, b| a $op b,
+                    Ratio::$method,
+                    |a, b| a $op b,
+                )
+            }
+        }
+    };
+}
+
+number_binop!(Add::add(+, checked_add));
+number_binop!(Sub::sub(-, checked_sub));
+number_binop!(Mul::mul(*, checked_mul));
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Number {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => a.partial_cmp(b),
+            (a @ Self::Float(_), b) | (a, b @ Self::Float(_)) => {
+                a.to_f64().partial_cmp(&b.to_f64())
+            }
+            (a @ Self::Ratio(_), b) | (a, b @ Self::Ratio(_)) => {
+                a.to_ratio().partial_cmp(&b.to_ratio())
+            }
+            (a, b) => a.to_big().partial_cmp(&b.to_big()),
+        }
+    }
+}
+
+/// Parses the float spellings accepted by the reader, including `##Inf`, `##-Inf` and `##NaN`.
+pub fn parse_float(s: &str) -> Option<f64> {
+    match s {
+        "##Inf" => return Some(f64::INFINITY),
+        "##-Inf" => return Some(f64::NEG_INFINITY),
+        "##NaN" => return Some(f64::NAN),
+        _ => {}
+    }
+
+    // rust also accepts `inf`, `nan` and `.5`, which are symbols here
+    let digits = s.strip_prefix(['-', '+']).unwrap_or(s);
+    let starts_with_digit = digits.starts_with(|c: char| c.is_ascii_digit());
+    let only_float_chars = digits
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '-' | '+'));
+    if !starts_with_digit || !only_float_chars {
+        return None;
+    }
+
+    s.parse().ok()
+}
+
+pub fn format_float(f: f64) -> String {
+    if f.is_nan() {
+        "##NaN".into()
+    } else if f.is_infinite() {
+        if f > 0.0 { "##Inf" } else { "##-Inf" }.into()
+    } else {
+        // debug formatting always keeps the decimal point or exponent
+        format!("{f:?}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_float, parse_float};
+
+    #[test]
+    fn float_round_trip() {
+        let cases = [
+            ("1.0", "1.0"),
+            ("-2.5", "-2.5"),
+            ("1e3", "1000.0"),
+            ("1.5E-7", "1.5e-7"),
+            ("+0.25", "0.25"),
+            ("##Inf", "##Inf"),
+            ("##-Inf", "##-Inf"),
+            ("##NaN", "##NaN"),
+        ];
+
+        for (input, expected) in cases {
+            let f = parse_float(input).unwrap();
+            assert_eq!(format_float(f), expected);
+            assert_eq!(format_float(parse_float(expected).unwrap()), expected);
+        }
+    }
+
+    #[test]
+    fn not_floats() {
+        for input in ["inf", "nan", "NaN", ".5", "-", "1.0.0", "e5", "1-2", "abc"] {
+            assert_eq!(parse_float(input), None, "{input}");
+        }
+    }
+}
@@ -0,0 +1,511 @@
+use std::{
+    cmp::Ordering,
+    fmt,
+    ops::{Add, Mul, Neg, Sub},
+    str::FromStr,
+};
+
+const LIMB_BITS: u32 = 32;
+const DECIMAL_CHUNK: u32 = 1_000_000_000;
+const DECIMAL_CHUNK_DIGITS: usize = 9;
+
+/// Arbitrary-precision integer in sign-magnitude form.
+///
+/// The magnitude is stored as little-endian 32-bit limbs without trailing zeros,
+/// so every value has exactly one representation and zero is never negative.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    magnitude: Vec<u32>,
+}
+
+impl BigInt {
+    fn from_parts(negative: bool, mut magnitude: Vec<u32>) -> Self {
+        while magnitude.last() == Some(&0) {
+            magnitude.pop();
+        }
+        let negative = negative && !magnitude.is_empty();
+        Self {
+            negative,
+            magnitude,
+        }
+    }
+
+    pub fn from_u64(u: u64) -> Self {
+        Self::from_parts(false, vec![u as u32, (u >> LIMB_BITS) as u32])
+    }
+
+    /// The integer part of `f`, or `None` if it's infinite or NaN.
+    pub fn from_f64_trunc(f: f64) -> Option<Self> {
+        if !f.is_finite() {
+            return None;
+        }
+        let bits = f.to_bits();
+        let exponent = (bits >> 52 & 0x7ff) as i32;
+        if exponent < 1023 {
+            // less than 1 in magnitude, including zero and subnormals
+            return Some(Self::default());
+        }
+        let mantissa = Self::from_u64(bits & ((1 << 52) - 1) | 1 << 52).magnitude;
+        let shift = exponent - 1023 - 52;
+        let magnitude = if shift <= 0 {
+            let shift = -shift as u32;
+            shr_bits(&mantissa[(shift / LIMB_BITS) as usize..], shift % LIMB_BITS)
+        } else {
+            let mut magnitude = vec![0; (shift as u32 / LIMB_BITS) as usize];
+            magnitude.extend(shl_bits(&mantissa, shift as u32 % LIMB_BITS));
+            magnitude
+        };
+        Some(Self::from_parts(bits >> 63 == 1, magnitude))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.magnitude.is_empty()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn is_even(&self) -> bool {
+        self.magnitude.first().is_none_or(|limb| limb & 1 == 0)
+    }
+
+    pub fn abs(&self) -> Self {
+        Self::from_parts(false, self.magnitude.clone())
+    }
+
+    fn magnitude_u64(&self) -> Option<u64> {
+        if self.magnitude.len() > 2 {
+            return None;
+        }
+        let magnitude = self
+            .magnitude
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &limb| acc << LIMB_BITS | limb as u64);
+        Some(magnitude)
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        let magnitude = self.magnitude_u64()?;
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+        } else {
+            magnitude.try_into().ok()
+        }
+    }
+
+    pub fn to_u64(&self) -> Option<u64> {
+        match self.negative {
+            true => None,
+            false => self.magnitude_u64(),
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self.magnitude.iter().rev().fold(0.0, |acc, &limb| {
+            acc * 2f64.powi(LIMB_BITS as i32) + limb as f64
+        });
+
+        if self.negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    /// Truncating division, the remainder has the sign of `self`.
+    ///
+    /// Returns `None` when dividing by zero.
+    pub fn div_rem(&self, rhs: &Self) -> Option<(Self, Self)> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let (quotient, remainder) = div_rem_magnitude(&self.magnitude, &rhs.magnitude);
+        Some((
+            Self::from_parts(self.negative != rhs.negative, quotient),
+            Self::from_parts(self.negative, remainder),
+        ))
+    }
+
+    pub fn gcd(&self, rhs: &Self) -> Self {
+        let (mut a, mut b) = (self.abs(), rhs.abs());
+        while !b.is_zero() {
+            let (_, r) = a.div_rem(&b).expect("divisor is not zero");
+            a = b;
+            b = r;
+        }
+        a
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(i: i64) -> Self {
+        let magnitude = i.unsigned_abs();
+        Self::from_parts(
+            i < 0,
+            vec![magnitude as u32, (magnitude >> LIMB_BITS) as u32],
+        )
+    }
+}
+
+fn cmp_magnitude(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut result = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &limb) in long.iter().enumerate() {
+        let sum = limb as u64 + short.get(i).copied().unwrap_or(0) as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> LIMB_BITS;
+    }
+    result.push(carry as u32);
+    result
+}
+
+/// Computes `a - b`, requires `a >= b`.
+fn sub_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &limb) in a.iter().enumerate() {
+        let diff = limb as i64 - b.get(i).copied().unwrap_or(0) as i64 - borrow;
+        result.push(diff as u32);
+        borrow = (diff < 0) as i64;
+    }
+    debug_assert_eq!(borrow, 0);
+    result
+}
+
+fn mul_magnitude(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let product = x as u64 * y as u64 + result[i + j] as u64 + carry;
+            result[i + j] = product as u32;
+            carry = product >> LIMB_BITS;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+    result
+}
+
+fn div_rem_small(a: &[u32], divisor: u32) -> (Vec<u32>, u32) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder = 0u64;
+    for (i, &limb) in a.iter().enumerate().rev() {
+        let current = remainder << LIMB_BITS | limb as u64;
+        quotient[i] = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+    (quotient, remainder as u32)
+}
+
+fn shl_bits(a: &[u32], shift: u32) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0u32;
+    for &limb in a {
+        result.push(limb << shift | carry);
+        carry = if shift == 0 {
+            0
+        } else {
+            limb >> (LIMB_BITS - shift)
+        };
+    }
+    result.push(carry);
+    result
+}
+
+fn shr_bits(a: &[u32], shift: u32) -> Vec<u32> {
+    if shift == 0 {
+        return a.to_vec();
+    }
+    (0..a.len())
+        .map(|i| a[i] >> shift | a.get(i + 1).map_or(0, |next| next << (LIMB_BITS - shift)))
+        .collect()
+}
+
+/// Long division of magnitudes, Knuth's algorithm D (TAOCP vol. 2, 4.3.1).
+fn div_rem_magnitude(u: &[u32], v: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if cmp_magnitude(u, v) == Ordering::Less {
+        return (vec![], u.to_vec());
+    }
+    if let [divisor] = v {
+        let (quotient, remainder) = div_rem_small(u, *divisor);
+        return (quotient, vec![remainder]);
+    }
+
+    const BASE: u64 = 1 << LIMB_BITS;
+    // normalize so that the top limb of the divisor has its highest bit set
+    let shift = v.last().unwrap().leading_zeros();
+    let v = &shl_bits(v, shift)[..v.len()];
+    let mut u = shl_bits(u, shift);
+    let n = v.len();
+    let mut quotient = vec![0u32; u.len() - n];
+
+    for j in (0..u.len() - n).rev() {
+        let numerator = (u[j + n] as u64) << LIMB_BITS | u[j + n - 1] as u64;
+        let mut q_hat = numerator / v[n - 1] as u64;
+        let mut r_hat = numerator % v[n - 1] as u64;
+        while q_hat >= BASE || q_hat * v[n - 2] as u64 > (r_hat << LIMB_BITS | u[j + n - 2] as u64)
+        {
+            q_hat -= 1;
+            r_hat += v[n - 1] as u64;
+            if r_hat >= BASE {
+                break;
+            }
+        }
+
+        let mut borrow = 0i64;
+        let mut carry = 0u64;
+        for i in 0..n {
+            let product = q_hat * v[i] as u64 + carry;
+            carry = product >> LIMB_BITS;
+            let diff = u[i + j] as i64 - borrow - (product as u32) as i64;
+            u[i + j] = diff as u32;
+            borrow = (diff < 0) as i64;
+        }
+        let diff = u[j + n] as i64 - borrow - carry as i64;
+        u[j + n] = diff as u32;
+
+        if diff < 0 {
+            // q_hat was one too large, add the divisor back
+            q_hat -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let sum = u[i + j] as u64 + v[i] as u64 + carry;
+                u[i + j] = sum as u32;
+                carry = sum >> LIMB_BITS;
+            }
+            u[j + n] = u[j + n].wrapping_add(carry as u32);
+        }
+        quotient[j] = q_hat as u32;
+    }
+
+    (quotient, shr_bits(&u[..n], shift))
+}
+
+fn add_signed(a: &BigInt, b: &BigInt, b_negative: bool) -> BigInt {
+    if a.negative == b_negative {
+        return BigInt::from_parts(a.negative, add_magnitude(&a.magnitude, &b.magnitude));
+    }
+    match cmp_magnitude(&a.magnitude, &b.magnitude) {
+        Ordering::Less => BigInt::from_parts(b_negative, sub_magnitude(&b.magnitude, &a.magnitude)),
+        _ => BigInt::from_parts(a.negative, sub_magnitude(&a.magnitude, &b.magnitude)),
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, rhs: Self) -> BigInt {
+        add_signed(self, rhs, rhs.negative)
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, rhs: Self) -> BigInt {
+        add_signed(self, rhs, !rhs.negative)
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, rhs: Self) -> BigInt {
+        BigInt::from_parts(
+            self.negative != rhs.negative,
+            mul_magnitude(&self.magnitude, &rhs.magnitude),
+        )
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.magnitude.clone())
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_magnitude(&self.magnitude, &other.magnitude),
+            (true, true) => cmp_magnitude(&other.magnitude, &self.magnitude),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_zero() {
+            return f.write_str("0");
+        }
+
+        let mut chunks = vec![];
+        let mut magnitude = self.magnitude.clone();
+        while !magnitude.is_empty() {
+            let (quotient, chunk) = div_rem_small(&magnitude, DECIMAL_CHUNK);
+            chunks.push(chunk);
+            magnitude = BigInt::from_parts(false, quotient).magnitude;
+        }
+
+        if self.negative {
+            f.write_str("-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        write!(f, "{}", chunks.next().unwrap())?;
+        for chunk in chunks {
+            write!(f, "{chunk:0width$}", width = DECIMAL_CHUNK_DIGITS)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseBigIntError;
+
+impl FromStr for BigInt {
+    type Err = ParseBigIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseBigIntError);
+        }
+
+        let mut magnitude = vec![];
+        let first_chunk = match digits.len() % DECIMAL_CHUNK_DIGITS {
+            0 => DECIMAL_CHUNK_DIGITS,
+            n => n,
+        };
+        let mut start = 0;
+        let mut end = first_chunk;
+        while start < digits.len() {
+            let chunk: u32 = digits[start..end].parse().map_err(|_| ParseBigIntError)?;
+            let scale = 10u32.pow((end - start) as u32);
+            magnitude = add_magnitude(&mul_magnitude(&magnitude, &[scale]), &[chunk]);
+            start = end;
+            end += DECIMAL_CHUNK_DIGITS;
+        }
+
+        Ok(Self::from_parts(negative, magnitude))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BigInt;
+
+    fn big(s: &str) -> BigInt {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_display() {
+        let cases = [
+            "0",
+            "-1",
+            "4294967296",
+            "18446744073709551616",
+            "-123456789012345678901234567890",
+            "1000000000000000000000000000",
+        ];
+        for case in cases {
+            assert_eq!(big(case).to_string(), case);
+        }
+        assert_eq!(big("-0").to_string(), "0");
+        assert_eq!(big("+007").to_string(), "7");
+        assert!("12a".parse::<BigInt>().is_err());
+        assert!("-".parse::<BigInt>().is_err());
+    }
+
+    #[test]
+    fn i64_conversions() {
+        for i in [0, 1, -1, i64::MAX, i64::MIN, 1 << 32, -(1 << 32)] {
+            assert_eq!(BigInt::from(i).to_i64(), Some(i));
+            assert_eq!(BigInt::from(i).to_string(), i.to_string());
+        }
+        assert_eq!(big("9223372036854775808").to_i64(), None);
+        assert_eq!(big("-9223372036854775809").to_i64(), None);
+    }
+
+    #[test]
+    fn from_f64() {
+        for (f, expected) in [
+            (0.0, "0"),
+            (-0.5, "0"),
+            (1e-310, "0"),
+            (1.0, "1"),
+            (-2.75, "-2"),
+            (4294967296.5, "4294967296"),
+            (9007199254740993.0, "9007199254740992"),
+            (9.223372036854776e18, "9223372036854775808"),
+            (-1e20, "-100000000000000000000"),
+            (2f64.powi(100), "1267650600228229401496703205376"),
+        ] {
+            assert_eq!(
+                BigInt::from_f64_trunc(f).unwrap().to_string(),
+                expected,
+                "{f}"
+            );
+        }
+        assert_eq!(BigInt::from_f64_trunc(f64::MAX).unwrap().to_f64(), f64::MAX);
+        for f in [f64::INFINITY, f64::NEG_INFINITY, f64::NAN] {
+            assert_eq!(BigInt::from_f64_trunc(f), None);
+        }
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = big("123456789012345678901234567890");
+        let b = big("-987654321098765432109876543210");
+        assert_eq!((&a + &b).to_string(), "-864197532086419753208641975320");
+        assert_eq!((&a - &b).to_string(), "1111111110111111111011111111100");
+        assert_eq!(
+            (&a * &b).to_string(),
+            "-121932631137021795226185032733622923332237463801111263526900"
+        );
+        assert_eq!(
+            b.div_rem(&a).map(|(q, r)| (q.to_string(), r.to_string())),
+            Some(("-8".into(), "-9000000000900000000090".into()))
+        );
+        assert_eq!(a.div_rem(&BigInt::default()), None);
+    }
+
+    #[test]
+    fn division_round_trip() {
+        let a = big("340282366920938463463374607431768211455123");
+        for d in [
+            "3",
+            "-4294967297",
+            "18446744073709551617",
+            "99999999999999999999999",
+        ] {
+            let d = big(d);
+            let (q, r) = a.div_rem(&d).unwrap();
+            assert_eq!(&(&q * &d) + &r, a);
+            assert!(r.abs() < d.abs());
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::{cmp::Ordering, fmt};
+
+use super::{BigInt, Number};
+
+/// Exact fraction, always kept in lowest terms with a positive denominator.
+///
+/// Constructed through [`Number::ratio`], which turns whole results back into integers,
+/// so a `Ratio` value is never an integer.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Ratio {
+    numer: BigInt,
+    denom: BigInt,
+}
+
+impl Number {
+    /// Builds the normalised value of `numer / denom`, `None` if `denom` is zero.
+    pub fn ratio(numer: BigInt, denom: BigInt) -> Option<Self> {
+        if denom.is_zero() {
+            return None;
+        }
+
+        let gcd = numer.gcd(&denom);
+        let (mut numer, _) = numer.div_rem(&gcd)?;
+        let (mut denom, _) = denom.div_rem(&gcd)?;
+        if denom.is_negative() {
+            numer = -&numer;
+            denom = -&denom;
+        }
+
+        if denom == BigInt::from(1) {
+            Some(Self::from_big(numer))
+        } else {
+            Some(Self::Ratio(Ratio { numer, denom }))
+        }
+    }
+}
+
+impl Ratio {
+    pub(super) fn from_integer(numer: BigInt) -> Self {
+        Self {
+            numer,
+            denom: BigInt::from(1),
+        }
+    }
+
+    pub fn numer(&self) -> &BigInt {
+        &self.numer
+    }
+
+    pub fn denom(&self) -> &BigInt {
+        &self.denom
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.numer.to_f64() / self.denom.to_f64()
+    }
+
+    fn div_rem(&self) -> (BigInt, BigInt) {
+        self.numer
+            .div_rem(&self.denom)
+            .expect("denominator is not zero")
+    }
+
+    pub fn trunc(&self) -> BigInt {
+        self.div_rem().0
+    }
+
+    pub fn floor(&self) -> BigInt {
+        match self.div_rem() {
+            (q, r) if r.is_negative() => &q - &BigInt::from(1),
+            (q, _) => q,
+        }
+    }
+
+    pub fn ceil(&self) -> BigInt {
+        match self.div_rem() {
+            (q, r) if !r.is_negative() && !r.is_zero() => &q + &BigInt::from(1),
+            (q, _) => q,
+        }
+    }
+
+    /// Rounds half away from zero.
+    pub fn round(&self) -> BigInt {
+        let (q, r) = self.div_rem();
+        let twice_remainder = &r.abs() * &BigInt::from(2);
+        match twice_remainder.cmp(&self.denom) {
+            Ordering::Less => q,
+            _ if self.numer.is_negative() => &q - &BigInt::from(1),
+            _ => &q + &BigInt::from(1),
+        }
+    }
+
+    pub(super) fn add(&self, rhs: &Self) -> Number {
+        let numer = &(&self.numer * &rhs.denom) + &(&rhs.numer * &self.denom);
+        Number::ratio(numer, &self.denom * &rhs.denom).expect("denominators are not zero")
+    }
+
+    pub(super) fn sub(&self, rhs: &Self) -> Number {
+        let numer = &(&self.numer * &rhs.denom) - &(&rhs.numer * &self.denom);
+        Number::ratio(numer, &self.denom * &rhs.denom).expect("denominators are not zero")
+    }
+
+    pub(super) fn mul(&self, rhs: &Self) -> Number {
+        Number::ratio(&self.numer * &rhs.numer, &self.denom * &rhs.denom)
+            .expect("denominators are not zero")
+    }
+
+    pub(super) fn div(&self, rhs: &Self) -> Option<Number> {
+        Number::ratio(&self.numer * &rhs.denom, &self.denom * &rhs.numer)
+    }
+}
+
+impl Ord for Ratio {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (&self.numer * &other.denom).cmp(&(&other.numer * &self.denom))
+    }
+}
+
+impl PartialOrd for Ratio {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.numer, self.denom)
+    }
+}
+
+/// Splits a `3/4` style literal into numerator and denominator.
+pub fn parse_ratio(s: &str) -> Option<(BigInt, BigInt)> {
+    let (numer, denom) = s.split_once('/')?;
+    if denom.starts_with(['-', '+']) {
+        return None;
+    }
+    Some((numer.parse().ok()?, denom.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{BigInt, Number};
+    use crate::{ast::Expr, parser::parse};
+
+    fn ratio(numer: i64, denom: i64) -> Option<String> {
+        Number::ratio(BigInt::from(numer), BigInt::from(denom)).map(|n| n.to_expr().to_string())
+    }
+
+    #[test]
+    fn normalised() {
+        assert_eq!(ratio(2, 4).as_deref(), Some("1/2"));
+        assert_eq!(ratio(4, 2).as_deref(), Some("2"));
+        assert_eq!(ratio(-6, 4).as_deref(), Some("-3/2"));
+        assert_eq!(ratio(1, -2).as_deref(), Some("-1/2"));
+        assert_eq!(ratio(-1, -2).as_deref(), Some("1/2"));
+        assert_eq!(ratio(0, -5).as_deref(), Some("0"));
+        assert_eq!(ratio(1, 0), None);
+    }
+
+    #[test]
+    fn literals() {
+        for (src, expected) in [("2/4", "1/2"), ("4/2", "2"), ("-2/4", "-1/2"), ("0/3", "0")] {
+            assert_eq!(parse(src).unwrap().to_string(), expected, "{src}");
+        }
+        assert!(matches!(parse("4/2").unwrap(), Expr::Int(2)));
+        assert_eq!(parse("1/0").unwrap_err().to_string(), "invalid number: 1/0");
+        // a signed denominator isn't part of a ratio literal
+        assert!(matches!(parse("1/-2").unwrap(), Expr::Symbol(_)));
+    }
+}
@@ -0,0 +1,146 @@
+use std::{cmp::Ordering, rc::Rc};
+
+use super::{number::Number, Expr, MapKey};
+
+impl Expr {
+    /// Total ordering over all values, used by sorted maps and sorted printing.
+    ///
+    /// Values of different kinds are ordered by kind (`nil`, booleans, numbers, strings,
+    /// keywords, symbols, sequences, maps, sets, functions, atoms), numbers are compared
+    /// by value across representations, and collections lexicographically.
+    /// Values that are [`lenient_eq`](Expr::lenient_eq) compare as equal.
+    pub fn total_cmp(&self, other: &Self) -> Ordering {
+        let (a, b) = (self.as_no_meta(), other.as_no_meta());
+        kind_rank(a).cmp(&kind_rank(b)).then_with(|| match (a, b) {
+            (Expr::Bool(a), Expr::Bool(b)) => a.cmp(b),
+            (Expr::String(a), Expr::String(b)) => a.cmp(b),
+            (Expr::Keyword(a), Expr::Keyword(b)) => a.name().cmp(b.name()),
+            (Expr::Symbol(a), Expr::Symbol(b)) => (**a).cmp(&**b),
+            (Expr::List(_) | Expr::Vector(_), Expr::List(_) | Expr::Vector(_)) => {
+                let a = a.as_list_like().expect("matched a list or vector");
+                let b = b.as_list_like().expect("matched a list or vector");
+                cmp_seq(a.iter(), b.iter())
+            }
+            (Expr::Map(_) | Expr::SortedMap(_), Expr::Map(_) | Expr::SortedMap(_)) => {
+                let (a, b) = (sorted_entries(a), sorted_entries(b));
+                a.len().cmp(&b.len()).then_with(|| {
+                    let keys = cmp_seq(a.iter().map(|(k, _)| *k), b.iter().map(|(k, _)| *k));
+                    keys.then_with(|| cmp_seq(a.iter().map(|(_, v)| *v), b.iter().map(|(_, v)| *v)))
+                })
+            }
+            (Expr::Set(a), Expr::Set(b)) => {
+                let mut a = a.iter().map(MapKey::as_expr).collect::<Vec<_>>();
+                let mut b = b.iter().map(MapKey::as_expr).collect::<Vec<_>>();
+                a.sort_by(|x, y| x.total_cmp(y));
+                b.sort_by(|x, y| x.total_cmp(y));
+                a.len()
+                    .cmp(&b.len())
+                    .then_with(|| cmp_seq(a.into_iter(), b.into_iter()))
+            }
+            (Expr::BuiltinFunction(a), Expr::BuiltinFunction(b)) => a.name().cmp(b.name()),
+            // functions and atoms have no meaningful order, only a stable one
+            (Expr::Function(a), Expr::Function(b)) => Rc::as_ptr(&a.arities)
+                .cast::<()>()
+                .cmp(&Rc::as_ptr(&b.arities).cast::<()>()),
+            (Expr::NativeFunction(a), Expr::NativeFunction(b)) => Rc::as_ptr(&a.func)
+                .cast::<()>()
+                .cmp(&Rc::as_ptr(&b.func).cast::<()>()),
+            (Expr::Atom(a), Expr::Atom(b)) => Rc::as_ptr(a).cmp(&Rc::as_ptr(b)),
+            _ => match (Number::from_expr(a), Number::from_expr(b)) {
+                (Some(x), Some(y)) => cmp_numbers(&x, &y),
+                _ => Ordering::Equal,
+            },
+        })
+    }
+}
+
+fn kind_rank(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Nil => 0,
+        Expr::Bool(_) => 1,
+        Expr::Int(_) | Expr::BigInt(_) | Expr::Ratio(_) | Expr::Float(_) => 2,
+        Expr::String(_) => 3,
+        Expr::Keyword(_) => 4,
+        Expr::Symbol(_) => 5,
+        Expr::List(_) | Expr::Vector(_) => 6,
+        Expr::Map(_) | Expr::SortedMap(_) => 7,
+        Expr::Set(_) => 8,
+        Expr::BuiltinFunction(_) => 9,
+        Expr::Function(_) => 10,
+        Expr::NativeFunction(_) => 11,
+        Expr::Atom(_) => 12,
+        Expr::MacroExpand(_) => 13,
+        Expr::WithMeta { expr, .. } => kind_rank(expr),
+    }
+}
+
+fn cmp_seq<'a>(
+    mut a: impl Iterator<Item = &'a Expr>,
+    mut b: impl Iterator<Item = &'a Expr>,
+) -> Ordering {
+    loop {
+        match (a.next(), b.next()) {
+            (Some(x), Some(y)) => match x.total_cmp(y) {
+                Ordering::Equal => continue,
+                o => return o,
+            },
+            (x, y) => return x.is_some().cmp(&y.is_some()),
+        }
+    }
+}
+
+/// Numbers equal in value are ordered by representation, since `1` and `1.0` are
+/// different keys. NaN sorts after every other number.
+fn cmp_numbers(a: &Number, b: &Number) -> Ordering {
+    let representation = |n: &Number| match n {
+        Number::Int(_) | Number::Big(_) => 0,
+        Number::Ratio(_) => 1,
+        Number::Float(_) => 2,
+    };
+    let is_nan = |n: &Number| matches!(n, Number::Float(f) if f.is_nan());
+
+    match a.partial_cmp(b) {
+        Some(Ordering::Equal) => representation(a).cmp(&representation(b)),
+        Some(o) => o,
+        None => is_nan(a).cmp(&is_nan(b)),
+    }
+}
+
+/// Entries of a hash map or sorted map, ordered by key.
+fn sorted_entries(map: &Expr) -> Vec<(&Expr, &Expr)> {
+    let mut entries = match map {
+        Expr::Map(map) => map
+            .iter()
+            .map(|(k, v)| (k.as_expr(), v))
+            .collect::<Vec<_>>(),
+        Expr::SortedMap(map) => map.iter().collect(),
+        _ => unreachable!("not a map"),
+    };
+    entries.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use crate::parser::parse;
+
+    #[test]
+    fn total_order() {
+        let sorted = [
+            "nil", "false", "true", "-1.5", "-1", "1/2", "1", "1.0", "2", "##NaN", "\"a\"",
+            "\"b\"", ":a", "a", "[]", "(1)", "[1 2]", "[2]", "{}", "{1 2}", "#{}",
+        ]
+        .map(|s| parse(s).unwrap());
+
+        for (i, a) in sorted.iter().enumerate() {
+            for (j, b) in sorted.iter().enumerate() {
+                assert_eq!(a.total_cmp(b), i.cmp(&j), "{a} <=> {b}");
+            }
+        }
+        let list = parse("(1 2)").unwrap();
+        let vector = parse("[1 2]").unwrap();
+        assert_eq!(list.total_cmp(&vector), Ordering::Equal);
+    }
+}
@@ -0,0 +1,235 @@
+use std::fmt;
+
+use crate::eval::{EvalError, EvalResult};
+
+use super::{interner::SymbolId, Expr, Keyword, Symbol};
+
+/// Binding target in `let*`, `def!` and function parameters, using Clojure-style destructuring.
+///
+/// ```text
+/// [a b & rest :as all]                      ; sequential
+/// {x :x, [y z] :point, :keys [k] :or {k 0}} ; associative
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// Binds the whole value.
+    Symbol(SymbolId),
+    /// Binds items of a list or vector by position.
+    Sequential {
+        items: Vec<Pattern>,
+        rest: Option<Box<Pattern>>,
+        whole: Option<SymbolId>,
+    },
+    /// Binds values of a map found under the given keys.
+    Associative {
+        entries: Vec<(Pattern, Expr)>,
+        /// Expressions evaluated for symbols whose key is missing, from `:or`.
+        defaults: Vec<(SymbolId, Expr)>,
+        whole: Option<SymbolId>,
+    },
+}
+
+impl Pattern {
+    pub fn parse(expr: &Expr) -> EvalResult<Self> {
+        match expr.as_no_meta() {
+            Expr::Symbol(s) if &**s == "&" => Err(invalid(expr, "`&` can't be bound")),
+            Expr::Symbol(s) => Ok(Self::Symbol(s.id())),
+            Expr::List(_) | Expr::Vector(_) => Self::parse_sequential(expr),
+            Expr::Map(_) => Self::parse_associative(expr),
+            _ => Err(invalid(expr, "expected a symbol, a vector or a map")),
+        }
+    }
+
+    /// Parses function parameters, the part after `&` is returned separately.
+    pub fn parse_params(params: &Expr) -> EvalResult<(Vec<Self>, Option<Box<Self>>)> {
+        match Self::parse_sequential(params)? {
+            Self::Sequential { whole: Some(_), .. } => {
+                Err(invalid(params, "`:as` can't be used in parameters"))
+            }
+            Self::Sequential { items, rest, .. } => Ok((items, rest)),
+            _ => unreachable!("parsed a sequential pattern"),
+        }
+    }
+
+    /// Names the pattern binds.
+    pub fn names(&self) -> Vec<SymbolId> {
+        let mut names = vec![];
+        self.collect_names(&mut names);
+        names
+    }
+
+    fn collect_names(&self, names: &mut Vec<SymbolId>) {
+        let whole = match self {
+            Self::Symbol(name) => return names.push(*name),
+            Self::Sequential { items, rest, whole } => {
+                items
+                    .iter()
+                    .chain(rest.as_deref())
+                    .for_each(|p| p.collect_names(names));
+                whole
+            }
+            Self::Associative { entries, whole, .. } => {
+                entries.iter().for_each(|(p, _)| p.collect_names(names));
+                whole
+            }
+        };
+        names.extend(*whole);
+    }
+
+    fn parse_sequential(expr: &Expr) -> EvalResult<Self> {
+        let list = expr
+            .as_no_meta()
+            .as_list_like()
+            .ok_or_else(|| invalid(expr, "expected a list or vector"))?;
+
+        let mut items = vec![];
+        let mut rest = None;
+        let mut whole = None;
+        let mut it = list.iter();
+        while let Some(item) = it.next() {
+            if whole.is_some() {
+                return Err(invalid(expr, "`:as` must come last"));
+            }
+            match item.as_no_meta() {
+                Expr::Symbol(s) if &**s == "&" => {
+                    if rest.is_some() {
+                        return Err(invalid(expr, "`&` can only appear once"));
+                    }
+                    let pattern = it
+                        .next()
+                        .ok_or_else(|| invalid(expr, "expected a pattern after `&`"))?;
+                    rest = Some(Box::new(Self::parse(pattern)?));
+                }
+                Expr::Keyword(kw) if kw.name() == "as" => {
+                    whole = Some(as_name(expr, it.next())?);
+                }
+                _ if rest.is_some() => {
+                    return Err(invalid(expr, "only one pattern can follow `&`"))
+                }
+                _ => items.push(Self::parse(item)?),
+            }
+        }
+
+        Ok(Self::Sequential { items, rest, whole })
+    }
+
+    fn parse_associative(expr: &Expr) -> EvalResult<Self> {
+        let Expr::Map(map) = expr.as_no_meta() else {
+            return Err(invalid(expr, "expected a map"));
+        };
+
+        let mut entries = vec![];
+        let mut defaults = vec![];
+        let mut whole = None;
+        for (key, value) in map.iter() {
+            let option = match key.as_expr() {
+                Expr::Keyword(kw) => kw.name(),
+                _ => "",
+            };
+            let make_key: fn(&Symbol) -> Expr = match option {
+                "keys" => |name| Expr::Keyword(Keyword::new(name)),
+                "strs" => |name| Expr::String(name.name().to_owned()),
+                // the name was interned when reading it, so it isn't interned again
+                "syms" => |name| Expr::Symbol(name.clone()),
+                "as" => {
+                    whole = Some(as_name(expr, Some(value))?);
+                    continue;
+                }
+                "or" => {
+                    let Expr::Map(or) = value.as_no_meta() else {
+                        return Err(invalid(expr, "expected a map after `:or`"));
+                    };
+                    for (name, default) in or.iter() {
+                        let Expr::Symbol(name) = name.as_expr() else {
+                            return Err(invalid(expr, "`:or` keys must be symbols"));
+                        };
+                        defaults.push((name.id(), default.clone()));
+                    }
+                    continue;
+                }
+                _ => {
+                    entries.push((Self::parse(key.as_expr())?, value.clone()));
+                    continue;
+                }
+            };
+
+            let not_names = || {
+                invalid(
+                    expr,
+                    format_args!("expected a vector of symbols after {key}"),
+                )
+            };
+            let names = value.as_no_meta().as_list_like().ok_or_else(not_names)?;
+            for name in names.iter() {
+                let Expr::Symbol(name) = name else {
+                    return Err(not_names());
+                };
+                entries.push((Self::Symbol(name.id()), make_key(name)));
+            }
+        }
+
+        Ok(Self::Associative {
+            entries,
+            defaults,
+            whole,
+        })
+    }
+}
+
+fn as_name(pattern: &Expr, name: Option<&Expr>) -> EvalResult<SymbolId> {
+    match name {
+        Some(Expr::Symbol(name)) => Ok(name.id()),
+        _ => Err(invalid(pattern, "expected a symbol after `:as`")),
+    }
+}
+
+fn invalid(pattern: &Expr, reason: impl fmt::Display) -> EvalError {
+    EvalError::InvalidPattern(format!("{reason} in {pattern:#}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pattern;
+    use crate::{
+        ast::{interner::SymbolId, Expr},
+        eval::EvalError,
+        parser::parse,
+    };
+
+    fn symbol(name: &str) -> Pattern {
+        Pattern::Symbol(name.into())
+    }
+
+    #[test]
+    fn parse_patterns() {
+        let pattern = Pattern::parse(&parse("[a [b] & {:keys [c]} :as all]").unwrap()).unwrap();
+        assert_eq!(pattern.names(), ["a", "b", "c", "all"].map(SymbolId::from));
+        let Pattern::Sequential { items, rest, whole } = pattern else {
+            panic!("expected a sequential pattern");
+        };
+        assert_eq!(items[0], symbol("a"));
+        assert!(matches!(&items[1], Pattern::Sequential { items, .. } if items == &[symbol("b")]));
+        assert!(matches!(
+            rest.as_deref(),
+            Some(Pattern::Associative { entries, .. })
+                if matches!(&entries[..], [(p, Expr::Keyword(kw))] if *p == symbol("c") && kw.name() == "c")
+        ));
+        assert_eq!(whole, Some("all".into()));
+
+        for malformed in [
+            "[a &]",
+            "[& a b]",
+            "[a :as]",
+            "{:keys a}",
+            "{:or [a 1]}",
+            "1",
+            "&",
+        ] {
+            let err = Pattern::parse(&parse(malformed).unwrap()).unwrap_err();
+            assert!(
+                matches!(err, EvalError::InvalidPattern(_)),
+                "{malformed}: {err}"
+            );
+        }
+    }
+}
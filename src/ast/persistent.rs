@@ -0,0 +1,10 @@
+//! Immutable collections sharing structure between versions, so that updating a large
+//! collection doesn't copy it.
+
+mod list;
+mod map;
+mod vector;
+
+pub use list::{Iter as ListIter, PersistentList};
+pub use map::{Iter as MapIter, PersistentMap, PersistentSet};
+pub use vector::{Iter as VectorIter, PersistentVector};
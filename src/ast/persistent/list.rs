@@ -0,0 +1,336 @@
+use std::{cell::OnceCell, fmt, iter::FromIterator, mem, ops::Deref, rc::Rc, slice};
+
+use crate::environment::gc::{Trace, Tracer};
+
+use super::vector::{self, PersistentVector};
+
+/// Immutable list sharing its items with the lists [`rest`](Self::rest) and
+/// [`cons`](Self::cons) make from it, which are O(1).
+///
+/// A list is usually a slice of shared items, like the code the parser reads. It can also
+/// be the items of a vector after the first ones, or an item added in front of another
+/// list. Those are copied into a slice the first time one is needed, e.g. to evaluate
+/// them as code, while `len`, `get` and `iter` don't need one.
+pub struct PersistentList<T>(Repr<T>);
+
+enum Repr<T> {
+    Empty,
+    /// Items from `start` on.
+    Items {
+        items: Rc<[T]>,
+        start: usize,
+    },
+    /// Items of a vector from `start` on.
+    Vector {
+        vector: Rc<VectorItems<T>>,
+        start: usize,
+    },
+    Cons(Rc<Cons<T>>),
+}
+
+struct VectorItems<T> {
+    vector: PersistentVector<T>,
+    slice: OnceCell<Box<[T]>>,
+}
+
+struct Cons<T> {
+    head: T,
+    tail: PersistentList<T>,
+    len: usize,
+    slice: OnceCell<Box<[T]>>,
+}
+
+impl<T> PersistentList<T> {
+    pub fn new() -> Self {
+        Self(Repr::Empty)
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.0 {
+            Repr::Empty => 0,
+            Repr::Items { items, start } => items.len() - start,
+            Repr::Vector { vector, start } => vector.vector.len() - start,
+            Repr::Cons(cell) => cell.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, mut index: usize) -> Option<&T> {
+        let mut list = self;
+        loop {
+            return match &list.0 {
+                Repr::Empty => None,
+                Repr::Items { items, start } => items.get(start + index),
+                Repr::Vector { vector, start } => vector.vector.get(start + index),
+                Repr::Cons(cell) if index == 0 => Some(&cell.head),
+                Repr::Cons(cell) => {
+                    index -= 1;
+                    list = &cell.tail;
+                    continue;
+                }
+            };
+        }
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter(match &self.0 {
+            Repr::Empty => IterRepr::Items([].iter()),
+            Repr::Items { items, start } => IterRepr::Items(items[*start..].iter()),
+            Repr::Vector { vector, start } => IterRepr::Vector(vector.vector.iter_from(*start)),
+            Repr::Cons(cell) => IterRepr::Cons(cell),
+        })
+    }
+
+    /// The list without its first item, sharing the others.
+    pub fn rest(&self) -> Self {
+        if self.len() <= 1 {
+            return Self::new();
+        }
+        Self(match &self.0 {
+            Repr::Empty => Repr::Empty,
+            Repr::Items { items, start } => Repr::Items {
+                items: Rc::clone(items),
+                start: start + 1,
+            },
+            Repr::Vector { vector, start } => Repr::Vector {
+                vector: Rc::clone(vector),
+                start: start + 1,
+            },
+            Repr::Cons(cell) => return cell.tail.clone(),
+        })
+    }
+
+    /// The list with `head` added in front, sharing the others.
+    pub fn cons(&self, head: T) -> Self {
+        Self(Repr::Cons(Rc::new(Cons {
+            head,
+            tail: self.clone(),
+            len: self.len() + 1,
+            slice: OnceCell::new(),
+        })))
+    }
+}
+
+impl<T: Clone> PersistentList<T> {
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().cloned().collect()
+    }
+}
+
+impl<T: Clone> Deref for PersistentList<T> {
+    type Target = [T];
+
+    /// The items as a slice, copied into one the first time if they aren't already.
+    fn deref(&self) -> &[T] {
+        match &self.0 {
+            Repr::Empty => &[],
+            Repr::Items { items, start } => &items[*start..],
+            Repr::Vector { vector, start } => &vector
+                .slice
+                .get_or_init(|| vector.vector.iter().cloned().collect())[*start..],
+            Repr::Cons(cell) => cell.slice.get_or_init(|| self.iter().cloned().collect()),
+        }
+    }
+}
+
+impl<T> Drop for Cons<T> {
+    fn drop(&mut self) {
+        // the tails are dropped one after the other, a long chain of them would
+        // overflow the stack if each dropped the next
+        let mut tail = mem::take(&mut self.tail);
+        while let Repr::Cons(cell) = mem::replace(&mut tail.0, Repr::Empty) {
+            match Rc::try_unwrap(cell) {
+                Ok(mut cell) => tail = mem::take(&mut cell.tail),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+impl<T: Trace> Trace for PersistentList<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        match &self.0 {
+            Repr::Empty => {}
+            Repr::Items { items, .. } => tracer.edge(items),
+            Repr::Vector { vector, .. } => tracer.edge(vector),
+            Repr::Cons(cell) => tracer.edge(cell),
+        }
+    }
+}
+
+impl<T: Trace> Trace for VectorItems<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.vector.trace(tracer);
+        if let Some(slice) = self.slice.get() {
+            slice.trace(tracer);
+        }
+    }
+}
+
+impl<T: Trace> Trace for Cons<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.head.trace(tracer);
+        self.tail.trace(tracer);
+        if let Some(slice) = self.slice.get() {
+            slice.trace(tracer);
+        }
+    }
+}
+
+impl<T> Clone for PersistentList<T> {
+    fn clone(&self) -> Self {
+        Self(match &self.0 {
+            Repr::Empty => Repr::Empty,
+            Repr::Items { items, start } => Repr::Items {
+                items: Rc::clone(items),
+                start: *start,
+            },
+            Repr::Vector { vector, start } => Repr::Vector {
+                vector: Rc::clone(vector),
+                start: *start,
+            },
+            Repr::Cons(cell) => Repr::Cons(Rc::clone(cell)),
+        })
+    }
+}
+
+impl<T> Default for PersistentList<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: PartialEq> PartialEq for PersistentList<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PersistentList<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T> FromIterator<T> for PersistentList<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let items: Rc<[T]> = iter.into_iter().collect();
+        match items.len() {
+            0 => Self::new(),
+            _ => Self(Repr::Items { items, start: 0 }),
+        }
+    }
+}
+
+impl<T> From<Vec<T>> for PersistentList<T> {
+    fn from(items: Vec<T>) -> Self {
+        match items.len() {
+            0 => Self::new(),
+            _ => Self(Repr::Items {
+                items: items.into(),
+                start: 0,
+            }),
+        }
+    }
+}
+
+/// The items of `vector`, without copying them.
+impl<T> From<PersistentVector<T>> for PersistentList<T> {
+    fn from(vector: PersistentVector<T>) -> Self {
+        match vector.len() {
+            0 => Self::new(),
+            _ => Self(Repr::Vector {
+                vector: Rc::new(VectorItems {
+                    vector,
+                    slice: OnceCell::new(),
+                }),
+                start: 0,
+            }),
+        }
+    }
+}
+
+pub struct Iter<'a, T>(IterRepr<'a, T>);
+
+enum IterRepr<'a, T> {
+    Items(slice::Iter<'a, T>),
+    Vector(vector::Iter<'a, T>),
+    Cons(&'a Cons<T>),
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        match &mut self.0 {
+            IterRepr::Items(it) => it.next(),
+            IterRepr::Vector(it) => it.next(),
+            IterRepr::Cons(cell) => {
+                let cell: &'a Cons<T> = *cell;
+                *self = cell.tail.iter();
+                Some(&cell.head)
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match &self.0 {
+            IterRepr::Items(it) => it.size_hint(),
+            IterRepr::Vector(it) => it.size_hint(),
+            IterRepr::Cons(cell) => (cell.len, Some(cell.len)),
+        }
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+impl<'a, T> IntoIterator for &'a PersistentList<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PersistentList;
+    use crate::ast::persistent::PersistentVector;
+
+    #[test]
+    fn rest_and_cons_share_items() {
+        let list = PersistentList::from((0..100).collect::<Vec<i64>>());
+        let vector = (0..100).collect::<PersistentVector<i64>>();
+        for mut list in [list, PersistentList::from(vector)] {
+            for i in 0..100 {
+                assert_eq!(list.len(), 100 - i as usize);
+                assert_eq!(list.first(), Some(&i));
+                assert_eq!(list.get(1), (i < 99).then_some(&(i + 1)));
+                assert!(list.iter().copied().eq(i..100));
+                assert_eq!(&list[..], &(i..100).collect::<Vec<_>>()[..]);
+                list = list.rest();
+            }
+            assert!(list.is_empty() && list.rest().is_empty());
+        }
+
+        let mut list = PersistentList::new();
+        for i in (0..100_000).rev() {
+            list = list.cons(i);
+        }
+        assert_eq!(list.len(), 100_000);
+        assert_eq!(list.get(99_999), Some(&99_999));
+        assert!(list.iter().copied().eq(0..100_000));
+        assert_eq!(list.rest().cons(-1).to_vec()[..2], [-1, 1]);
+        assert_eq!(list[50_000], 50_000);
+        // dropping a long chain doesn't overflow the stack
+        drop(list);
+    }
+}
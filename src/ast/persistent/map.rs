@@ -0,0 +1,594 @@
+use std::{
+    fmt,
+    hash::{BuildHasher, Hash},
+    iter::FromIterator,
+    mem,
+    rc::Rc,
+    slice,
+};
+
+use fnv::FnvBuildHasher;
+
+use crate::environment::gc::{Trace, Tracer};
+
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+/// Immutable hash map with structural sharing, a hash array mapped trie.
+///
+/// Cloning is O(1), `insert` and `remove` copy only the nodes on the path to the key.
+pub struct PersistentMap<K, V> {
+    len: usize,
+    root: Rc<Node<K, V>>,
+}
+
+#[derive(Clone)]
+enum Node<K, V> {
+    /// Children are stored densely, `bitmap` marks which of the 32 slots are present.
+    Branch {
+        bitmap: u32,
+        children: Vec<Entry<K, V>>,
+    },
+    /// Keys whose 64-bit hashes are all equal.
+    Collision { hash: u64, entries: Vec<(K, V)> },
+}
+
+#[derive(Clone)]
+enum Entry<K, V> {
+    Leaf { hash: u64, key: K, value: V },
+    Node(Rc<Node<K, V>>),
+}
+
+fn hash_key<K: Hash>(key: &K) -> u64 {
+    FnvBuildHasher::default().hash_one(key)
+}
+
+/// Bit of the slot `hash` falls into at the level of `shift`.
+fn slot(hash: u64, shift: u32) -> u32 {
+    1 << ((hash >> shift) & MASK)
+}
+
+impl<K, V> Node<K, V> {
+    const EMPTY: Self = Node::Branch {
+        bitmap: 0,
+        children: vec![],
+    };
+}
+
+impl<K, V> PersistentMap<K, V> {
+    pub fn new() -> Self {
+        Self {
+            len: 0,
+            root: Rc::new(Node::EMPTY),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            stack: vec![],
+            current: NodeIter::from(&*self.root),
+            remaining: self.len,
+        }
+    }
+
+    pub fn keys(&self) -> impl ExactSizeIterator<Item = &K> + Clone {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl ExactSizeIterator<Item = &V> + Clone {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+impl<K: Hash + Eq, V> PersistentMap<K, V> {
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let hash = hash_key(key);
+        let mut node = &*self.root;
+        let mut shift = 0;
+        loop {
+            match node {
+                Node::Branch { bitmap, children } => {
+                    let bit = slot(hash, shift);
+                    if bitmap & bit == 0 {
+                        return None;
+                    }
+                    match &children[index_of(*bitmap, bit)] {
+                        Entry::Leaf { key: k, value, .. } => {
+                            return (k == key).then_some(value);
+                        }
+                        Entry::Node(child) => node = child,
+                    }
+                }
+                Node::Collision { entries, .. } => {
+                    return entries.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+                }
+            }
+            shift += BITS;
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> PersistentMap<K, V> {
+    /// Inserts `value` under `key`, returning the previous value.
+    ///
+    /// An existing key is kept and only its value is replaced.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = hash_key(&key);
+        let old = insert(Rc::make_mut(&mut self.root), 0, hash, key, value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        // avoids copying the path when there is nothing to remove
+        if !self.contains_key(key) {
+            return None;
+        }
+
+        let old = remove(Rc::make_mut(&mut self.root), 0, hash_key(key), key);
+        if old.is_some() {
+            self.len -= 1;
+        }
+        old
+    }
+}
+
+fn index_of(bitmap: u32, bit: u32) -> usize {
+    (bitmap & (bit - 1)).count_ones() as usize
+}
+
+fn insert<K: Hash + Eq + Clone, V: Clone>(
+    node: &mut Node<K, V>,
+    shift: u32,
+    hash: u64,
+    key: K,
+    value: V,
+) -> Option<V> {
+    let (bitmap, children) = match node {
+        Node::Branch { bitmap, children } => (bitmap, children),
+        Node::Collision { entries, .. } => {
+            return match entries.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => Some(mem::replace(v, value)),
+                None => {
+                    entries.push((key, value));
+                    None
+                }
+            };
+        }
+    };
+
+    let bit = slot(hash, shift);
+    let index = index_of(*bitmap, bit);
+    if *bitmap & bit == 0 {
+        children.insert(index, Entry::Leaf { hash, key, value });
+        *bitmap |= bit;
+        return None;
+    }
+
+    match &mut children[index] {
+        Entry::Leaf {
+            key: k, value: v, ..
+        } if *k == key => Some(mem::replace(v, value)),
+        Entry::Leaf { .. } => {
+            let new = Entry::Leaf { hash, key, value };
+            let old = mem::replace(&mut children[index], Entry::Node(Rc::new(Node::EMPTY)));
+            children[index] = Entry::Node(Rc::new(pair(shift + BITS, old, new)));
+            None
+        }
+        Entry::Node(child) => insert(Rc::make_mut(child), shift + BITS, hash, key, value),
+    }
+}
+
+/// Node holding two leaves with different keys that share a slot at the previous level.
+fn pair<K, V>(shift: u32, a: Entry<K, V>, b: Entry<K, V>) -> Node<K, V> {
+    let (hash_a, hash_b) = match (&a, &b) {
+        (Entry::Leaf { hash: a, .. }, Entry::Leaf { hash: b, .. }) => (*a, *b),
+        _ => unreachable!("only leaves are paired"),
+    };
+
+    if shift >= u64::BITS {
+        let entries = [a, b]
+            .into_iter()
+            .map(|e| match e {
+                Entry::Leaf { key, value, .. } => (key, value),
+                Entry::Node(_) => unreachable!(),
+            })
+            .collect();
+        return Node::Collision {
+            hash: hash_a,
+            entries,
+        };
+    }
+
+    let (bit_a, bit_b) = (slot(hash_a, shift), slot(hash_b, shift));
+    if bit_a == bit_b {
+        Node::Branch {
+            bitmap: bit_a,
+            children: vec![Entry::Node(Rc::new(pair(shift + BITS, a, b)))],
+        }
+    } else {
+        let children = if bit_a < bit_b {
+            vec![a, b]
+        } else {
+            vec![b, a]
+        };
+        Node::Branch {
+            bitmap: bit_a | bit_b,
+            children,
+        }
+    }
+}
+
+fn remove<K: Hash + Eq + Clone, V: Clone>(
+    node: &mut Node<K, V>,
+    shift: u32,
+    hash: u64,
+    key: &K,
+) -> Option<V> {
+    let (bitmap, children) = match node {
+        Node::Branch { bitmap, children } => (bitmap, children),
+        Node::Collision { entries, .. } => {
+            let index = entries.iter().position(|(k, _)| k == key)?;
+            return Some(entries.swap_remove(index).1);
+        }
+    };
+
+    let bit = slot(hash, shift);
+    if *bitmap & bit == 0 {
+        return None;
+    }
+
+    let index = index_of(*bitmap, bit);
+    let child = match &mut children[index] {
+        Entry::Leaf { key: k, .. } if k == key => {
+            *bitmap &= !bit;
+            return match children.remove(index) {
+                Entry::Leaf { value, .. } => Some(value),
+                Entry::Node(_) => unreachable!(),
+            };
+        }
+        Entry::Leaf { .. } => return None,
+        Entry::Node(child) => child,
+    };
+
+    let old = remove(Rc::make_mut(child), shift + BITS, hash, key)?;
+    if let Some(leaf) = single_leaf(child) {
+        children[index] = leaf;
+    }
+    Some(old)
+}
+
+/// Collapses a node left with a single key into a leaf, keeping the trie minimal.
+fn single_leaf<K: Clone, V: Clone>(node: &Node<K, V>) -> Option<Entry<K, V>> {
+    match node {
+        Node::Branch { children, .. } => match children.as_slice() {
+            [leaf @ Entry::Leaf { .. }] => Some(leaf.clone()),
+            _ => None,
+        },
+        Node::Collision { hash, entries } => match entries.as_slice() {
+            [(key, value)] => Some(Entry::Leaf {
+                hash: *hash,
+                key: key.clone(),
+                value: value.clone(),
+            }),
+            _ => None,
+        },
+    }
+}
+
+impl<K, V> Clone for PersistentMap<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            len: self.len,
+            root: Rc::clone(&self.root),
+        }
+    }
+}
+
+impl<K, V> Default for PersistentMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq, V: PartialEq> PartialEq for PersistentMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for PersistentMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> FromIterator<(K, V)> for PersistentMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Extend<(K, V)> for PersistentMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+enum NodeIter<'a, K, V> {
+    Branch(slice::Iter<'a, Entry<K, V>>),
+    Collision(slice::Iter<'a, (K, V)>),
+}
+
+impl<K, V> Clone for NodeIter<'_, K, V> {
+    fn clone(&self) -> Self {
+        match self {
+            NodeIter::Branch(it) => NodeIter::Branch(it.clone()),
+            NodeIter::Collision(it) => NodeIter::Collision(it.clone()),
+        }
+    }
+}
+
+impl<'a, K, V> From<&'a Node<K, V>> for NodeIter<'a, K, V> {
+    fn from(node: &'a Node<K, V>) -> Self {
+        match node {
+            Node::Branch { children, .. } => NodeIter::Branch(children.iter()),
+            Node::Collision { entries, .. } => NodeIter::Collision(entries.iter()),
+        }
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    /// Parents of the node being iterated.
+    stack: Vec<NodeIter<'a, K, V>>,
+    current: NodeIter<'a, K, V>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let entry = match &mut self.current {
+                NodeIter::Branch(children) => children.next(),
+                NodeIter::Collision(entries) => match entries.next() {
+                    Some((k, v)) => {
+                        self.remaining -= 1;
+                        return Some((k, v));
+                    }
+                    None => None,
+                },
+            };
+
+            match entry {
+                Some(Entry::Leaf { key, value, .. }) => {
+                    self.remaining -= 1;
+                    return Some((key, value));
+                }
+                Some(Entry::Node(child)) => {
+                    let parent = mem::replace(&mut self.current, NodeIter::from(&**child));
+                    self.stack.push(parent);
+                }
+                None => self.current = self.stack.pop()?,
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+impl<K, V> Clone for Iter<'_, K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            stack: self.stack.clone(),
+            current: self.current.clone(),
+            remaining: self.remaining,
+        }
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a PersistentMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K: Trace, V: Trace> Trace for PersistentMap<K, V> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.edge(&self.root);
+    }
+}
+
+impl<K: Trace, V: Trace> Trace for Node<K, V> {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Node::Branch { children, .. } => {
+                for child in children {
+                    match child {
+                        Entry::Leaf { key, value, .. } => {
+                            key.trace(tracer);
+                            value.trace(tracer);
+                        }
+                        Entry::Node(node) => tracer.edge(node),
+                    }
+                }
+            }
+            Node::Collision { entries, .. } => {
+                for (key, value) in entries {
+                    key.trace(tracer);
+                    value.trace(tracer);
+                }
+            }
+        }
+    }
+}
+
+/// Immutable hash set sharing structure like [`PersistentMap`].
+pub struct PersistentSet<K>(PersistentMap<K, ()>);
+
+impl<K: Trace> Trace for PersistentSet<K> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.0.trace(tracer);
+    }
+}
+
+impl<K> PersistentSet<K> {
+    pub fn new() -> Self {
+        Self(PersistentMap::new())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = &K> + Clone {
+        self.0.iter().map(|(k, _)| k)
+    }
+}
+
+impl<K: Hash + Eq> PersistentSet<K> {
+    pub fn contains(&self, key: &K) -> bool {
+        self.0.contains_key(key)
+    }
+
+    pub fn is_subset(&self, other: &Self) -> bool {
+        self.len() <= other.len() && self.iter().all(|k| other.contains(k))
+    }
+}
+
+impl<K: Hash + Eq + Clone> PersistentSet<K> {
+    /// Returns `false` if the key was already present.
+    pub fn insert(&mut self, key: K) -> bool {
+        self.0.insert(key, ()).is_none()
+    }
+
+    pub fn remove(&mut self, key: &K) -> bool {
+        self.0.remove(key).is_some()
+    }
+}
+
+impl<K> Clone for PersistentSet<K> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<K> Default for PersistentSet<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq> PartialEq for PersistentSet<K> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<K: fmt::Debug> fmt::Debug for PersistentSet<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<K: Hash + Eq + Clone> FromIterator<K> for PersistentSet<K> {
+    fn from_iter<I: IntoIterator<Item = K>>(iter: I) -> Self {
+        Self(iter.into_iter().map(|k| (k, ())).collect())
+    }
+}
+
+impl<K: Hash + Eq + Clone> Extend<K> for PersistentSet<K> {
+    fn extend<I: IntoIterator<Item = K>>(&mut self, iter: I) {
+        self.0.extend(iter.into_iter().map(|k| (k, ())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{Hash, Hasher};
+
+    use super::PersistentMap;
+
+    /// Key with a controllable hash, to exercise collision nodes.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Colliding(u32, u64);
+
+    impl Hash for Colliding {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.1.hash(state)
+        }
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = PersistentMap::new();
+        for i in 0..3000 {
+            assert_eq!(map.insert(i, i * 2), None);
+        }
+        let snapshot = map.clone();
+
+        assert_eq!(map.insert(5, 0), Some(10));
+        assert_eq!(map.len(), 3000);
+        assert_eq!(map.iter().count(), 3000);
+        for i in (0..3000).step_by(3) {
+            assert!(map.remove(&i).is_some());
+        }
+        assert_eq!(map.remove(&0), None);
+
+        assert_eq!(map.len(), 2000);
+        assert_eq!(map.iter().len(), 2000);
+        assert_eq!(map.get(&3), None);
+        assert_eq!(map.get(&4), Some(&8));
+        assert_eq!(map.get(&5), Some(&0));
+        assert!((0..3000).all(|i| snapshot.get(&i) == Some(&(i * 2))));
+    }
+
+    #[test]
+    fn collisions() {
+        let mut map = PersistentMap::new();
+        for i in 0..10 {
+            map.insert(Colliding(i, 42), i);
+        }
+        map.insert(Colliding(100, 7), 100);
+
+        assert_eq!(map.len(), 11);
+        assert!((0..10).all(|i| map.get(&Colliding(i, 42)) == Some(&i)));
+        assert_eq!(map.get(&Colliding(10, 42)), None);
+
+        for i in 0..9 {
+            assert_eq!(map.remove(&Colliding(i, 42)), Some(i));
+        }
+        assert_eq!(map.get(&Colliding(9, 42)), Some(&9));
+        assert_eq!(map.iter().count(), 2);
+    }
+}
@@ -0,0 +1,314 @@
+use std::{fmt, iter::FromIterator, mem, rc::Rc};
+
+use crate::environment::gc::{Trace, Tracer};
+
+const BITS: u32 = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+/// Immutable vector with structural sharing, a 32-way bit-partitioned trie.
+///
+/// Cloning is O(1), `get`, `set` and `push_back` copy at most one path of the trie,
+/// so they are O(log32 n). The last partial leaf is kept outside the trie as the tail,
+/// making appends amortized O(1).
+pub struct PersistentVector<T> {
+    len: usize,
+    /// Level of the root node, leaves are at level 0.
+    shift: u32,
+    root: Rc<Node<T>>,
+    tail: Rc<Vec<T>>,
+}
+
+#[derive(Clone)]
+enum Node<T> {
+    Branch(Vec<Rc<Node<T>>>),
+    Leaf(Vec<T>),
+}
+
+impl<T> PersistentVector<T> {
+    pub fn new() -> Self {
+        Self {
+            len: 0,
+            shift: BITS,
+            root: Rc::new(Node::Branch(vec![])),
+            tail: Rc::new(vec![]),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn tail_offset(&self) -> usize {
+        self.len - self.tail.len()
+    }
+
+    /// Leaf holding the element at `index`, which must be in bounds.
+    fn leaf_for(&self, index: usize) -> &[T] {
+        if index >= self.tail_offset() {
+            return &self.tail;
+        }
+
+        let mut node = &*self.root;
+        let mut level = self.shift;
+        loop {
+            match node {
+                Node::Branch(children) => node = &children[(index >> level) & MASK],
+                Node::Leaf(items) => return items,
+            }
+            level -= BITS;
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            return None;
+        }
+        Some(&self.leaf_for(index)[index & MASK])
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.tail.last()
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.iter_from(0)
+    }
+
+    /// Iterates over the elements from `index` on.
+    pub fn iter_from(&self, index: usize) -> Iter<'_, T> {
+        Iter {
+            vector: self,
+            index: index.min(self.len),
+            leaf: &[],
+        }
+    }
+}
+
+impl<T: Clone> PersistentVector<T> {
+    pub fn push_back(&mut self, value: T) {
+        if self.tail.len() < WIDTH {
+            Rc::make_mut(&mut self.tail).push(value);
+            self.len += 1;
+            return;
+        }
+
+        let tail_offset = self.tail_offset();
+        let full_tail = mem::replace(&mut self.tail, Rc::new(vec![value]));
+        let leaf = Rc::new(Node::Leaf(Rc::unwrap_or_clone(full_tail)));
+
+        if (tail_offset >> BITS) >= (1 << self.shift) {
+            // the trie is full, grow it by one level
+            let old_root = mem::replace(&mut self.root, Rc::new(Node::Branch(vec![])));
+            let path = new_path(self.shift, leaf);
+            self.root = Rc::new(Node::Branch(vec![old_root, path]));
+            self.shift += BITS;
+        } else {
+            push_leaf(&mut self.root, self.shift, tail_offset, leaf);
+        }
+        self.len += 1;
+    }
+
+    /// Replaces the element at `index`, returning `false` if it's out of bounds.
+    pub fn set(&mut self, index: usize, value: T) -> bool {
+        if index >= self.len {
+            return false;
+        }
+
+        let tail_offset = self.tail_offset();
+        if index >= tail_offset {
+            Rc::make_mut(&mut self.tail)[index - tail_offset] = value;
+        } else {
+            set_in(&mut self.root, self.shift, index, value);
+        }
+        true
+    }
+
+    pub fn to_vec(&self) -> Vec<T> {
+        self.iter().cloned().collect()
+    }
+}
+
+fn new_path<T>(level: u32, leaf: Rc<Node<T>>) -> Rc<Node<T>> {
+    match level {
+        0 => leaf,
+        _ => Rc::new(Node::Branch(vec![new_path(level - BITS, leaf)])),
+    }
+}
+
+fn push_leaf<T: Clone>(node: &mut Rc<Node<T>>, level: u32, index: usize, leaf: Rc<Node<T>>) {
+    let children = match Rc::make_mut(node) {
+        Node::Branch(children) => children,
+        Node::Leaf(_) => unreachable!("leaves are only at level 0"),
+    };
+
+    let child = (index >> level) & MASK;
+    if level == BITS {
+        children.push(leaf);
+    } else if child < children.len() {
+        push_leaf(&mut children[child], level - BITS, index, leaf);
+    } else {
+        children.push(new_path(level - BITS, leaf));
+    }
+}
+
+fn set_in<T: Clone>(node: &mut Rc<Node<T>>, level: u32, index: usize, value: T) {
+    match Rc::make_mut(node) {
+        Node::Branch(children) => set_in(
+            &mut children[(index >> level) & MASK],
+            level - BITS,
+            index,
+            value,
+        ),
+        Node::Leaf(items) => items[index & MASK] = value,
+    }
+}
+
+impl<T: Trace> Trace for PersistentVector<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.edge(&self.root);
+        tracer.edge(&self.tail);
+    }
+}
+
+impl<T: Trace> Trace for Node<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Node::Branch(children) => {
+                for child in children {
+                    tracer.edge(child);
+                }
+            }
+            Node::Leaf(items) => items.trace(tracer),
+        }
+    }
+}
+
+impl<T> Clone for PersistentVector<T> {
+    fn clone(&self) -> Self {
+        Self {
+            len: self.len,
+            shift: self.shift,
+            root: Rc::clone(&self.root),
+            tail: Rc::clone(&self.tail),
+        }
+    }
+}
+
+impl<T> Default for PersistentVector<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: PartialEq> PartialEq for PersistentVector<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PersistentVector<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: Clone> FromIterator<T> for PersistentVector<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut vector = Self::new();
+        vector.extend(iter);
+        vector
+    }
+}
+
+impl<T: Clone> Extend<T> for PersistentVector<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push_back(value);
+        }
+    }
+}
+
+impl<T: Clone> From<Vec<T>> for PersistentVector<T> {
+    fn from(items: Vec<T>) -> Self {
+        items.into_iter().collect()
+    }
+}
+
+pub struct Iter<'a, T> {
+    vector: &'a PersistentVector<T>,
+    index: usize,
+    /// Rest of the current leaf, so that the trie is walked once per leaf.
+    leaf: &'a [T],
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        if self.leaf.is_empty() {
+            if self.index >= self.vector.len {
+                return None;
+            }
+            self.leaf = &self.vector.leaf_for(self.index)[self.index & MASK..];
+        }
+
+        let (item, rest) = self.leaf.split_first()?;
+        self.leaf = rest;
+        self.index += 1;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.vector.len - self.index;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+impl<'a, T> IntoIterator for &'a PersistentVector<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PersistentVector;
+
+    #[test]
+    fn push_get_set() {
+        let mut v = PersistentVector::new();
+        for i in 0..5000 {
+            v.push_back(i as i64);
+        }
+        let snapshot = v.clone();
+
+        assert_eq!(v.len(), 5000);
+        assert!((0..5000).all(|i| v.get(i) == Some(&(i as i64))));
+        assert_eq!(v.get(5000), None);
+        assert!(v.iter().copied().eq(0..5000));
+
+        for i in (0..5000).step_by(7) {
+            assert!(v.set(i, -1));
+        }
+        v.push_back(5000);
+
+        assert!(snapshot.iter().copied().eq(0..5000));
+        assert_eq!(v.get(7), Some(&-1));
+        assert_eq!(v.get(8), Some(&8));
+        assert_eq!(v.last(), Some(&5000));
+    }
+}
@@ -0,0 +1,393 @@
+//! Serde support for [`Expr`], enabled by the `serde` feature.
+//!
+//! Maps serialize as maps, lists and vectors as sequences and keywords as strings starting
+//! with [`KEYWORD_MARKER`], so they can be told apart from strings when deserializing.
+//! Functions can't be serialized.
+//!
+//! Some values have no counterpart in the serde data model and come back as something
+//! else after a round trip:
+//!
+//! - ratios serialize as the nearest `f64`, so they come back as floats and may lose
+//!   precision
+//! - integers beyond `u64` serialize as strings of their decimal digits, which come back
+//!   as strings, while those within `u64` come back as numbers
+//! - symbols serialize as plain strings
+//! - lists and sets serialize as sequences, which come back as vectors
+//! - metadata and the atom around a value are dropped
+//!
+//! Deserializing Rust types with [`from_expr`] maps these values the same way.
+//!
+//! [`from_expr`] reads any deserializable Rust type straight out of an evaluated value.
+
+use std::{fmt, rc::Rc};
+
+use serde::{
+    de::{self, IntoDeserializer, MapAccess, SeqAccess, Visitor},
+    forward_to_deserialize_any,
+    ser::{self, SerializeMap, SerializeSeq},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::eval::{EvalError, EvalResult};
+
+use super::{number::BigInt, Expr, Keyword, Map, MapKey, Vector};
+
+/// Prefix of serialized keywords, the same one mal uses for keywords internally.
+pub const KEYWORD_MARKER: char = '\u{29e}';
+
+/// Deserializes `T` from an evaluated mal value.
+///
+/// Keywords are accepted wherever strings are expected, using their name without the colon,
+/// so `{:port 8080}` reads into a struct with a `port` field.
+pub fn from_expr<'de, T: Deserialize<'de>>(expr: &'de Expr) -> EvalResult<T> {
+    T::deserialize(expr)
+}
+
+impl de::Error for EvalError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        EvalError::Deserialize(msg.to_string())
+    }
+}
+
+fn marked_keyword(kw: &Keyword) -> String {
+    format!("{KEYWORD_MARKER}{}", kw.name())
+}
+
+impl Serialize for Expr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Expr::Nil => serializer.serialize_unit(),
+            Expr::Bool(b) => serializer.serialize_bool(*b),
+            Expr::Int(i) => serializer.serialize_i64(*i),
+            // too large for an i64, the decimal digits are the only lossless representation
+            Expr::BigInt(b) => match b.to_u64() {
+                Some(u) => serializer.serialize_u64(u),
+                None => serializer.collect_str(b),
+            },
+            Expr::Ratio(r) => serializer.serialize_f64(r.to_f64()),
+            Expr::Float(f) => serializer.serialize_f64(*f),
+            Expr::String(s) => serializer.serialize_str(s),
+            Expr::Symbol(s) => serializer.serialize_str(s),
+            Expr::Keyword(kw) => serializer.serialize_str(&marked_keyword(kw)),
+            Expr::List(_) | Expr::Vector(_) => {
+                let items = self.as_list_like().expect("matched a list or vector");
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items.iter() {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Expr::Set(set) => {
+                let mut seq = serializer.serialize_seq(Some(set.len()))?;
+                for item in set.iter() {
+                    seq.serialize_element(item.as_expr())?;
+                }
+                seq.end()
+            }
+            Expr::Map(map) => {
+                let mut ser = serializer.serialize_map(Some(map.len()))?;
+                for (k, v) in map.iter() {
+                    ser.serialize_entry(k.as_expr(), v)?;
+                }
+                ser.end()
+            }
+            Expr::SortedMap(map) => {
+                let mut ser = serializer.serialize_map(Some(map.len()))?;
+                for (k, v) in map.iter() {
+                    ser.serialize_entry(k, v)?;
+                }
+                ser.end()
+            }
+            Expr::Atom(a) => a.borrow().serialize(serializer),
+            Expr::WithMeta { expr, .. } => expr.serialize(serializer),
+            Expr::Function(_)
+            | Expr::BuiltinFunction(_)
+            | Expr::NativeFunction(_)
+            | Expr::MacroExpand(_) => Err(ser::Error::custom(format_args!(
+                "cannot serialize a {}",
+                self.type_name()
+            ))),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Expr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ExprVisitor)
+    }
+}
+
+struct ExprVisitor;
+
+impl<'de> Visitor<'de> for ExprVisitor {
+    type Value = Expr;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a mal value")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Expr, E> {
+        Ok(Expr::Bool(b))
+    }
+
+    fn visit_i64<E>(self, i: i64) -> Result<Expr, E> {
+        Ok(Expr::Int(i))
+    }
+
+    fn visit_u64<E>(self, u: u64) -> Result<Expr, E> {
+        match i64::try_from(u) {
+            Ok(i) => Ok(Expr::Int(i)),
+            Err(_) => Ok(Expr::BigInt(Rc::new(BigInt::from_u64(u)))),
+        }
+    }
+
+    fn visit_f64<E>(self, f: f64) -> Result<Expr, E> {
+        Ok(Expr::Float(f))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Expr, E> {
+        match s.strip_prefix(KEYWORD_MARKER) {
+            Some(name) => Ok(Expr::Keyword(Keyword::new(name))),
+            None => Ok(Expr::String(s.to_owned())),
+        }
+    }
+
+    fn visit_unit<E>(self) -> Result<Expr, E> {
+        Ok(Expr::Nil)
+    }
+
+    fn visit_none<E>(self) -> Result<Expr, E> {
+        Ok(Expr::Nil)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Expr, D::Error> {
+        Expr::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Expr, A::Error> {
+        let mut items = Vector::new();
+        while let Some(item) = seq.next_element()? {
+            items.push_back(item);
+        }
+        Ok(Expr::Vector(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<Expr, A::Error> {
+        let mut map = Map::default();
+        while let Some((k, v)) = access.next_entry::<Expr, Expr>()? {
+            map.insert(MapKey::new(k), v);
+        }
+        Ok(Expr::Map(Rc::new(map)))
+    }
+}
+
+impl<'de> Deserializer<'de> for &'de Expr {
+    type Error = EvalError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> EvalResult<V::Value> {
+        match self.as_no_meta() {
+            Expr::Nil => visitor.visit_unit(),
+            Expr::Bool(b) => visitor.visit_bool(*b),
+            Expr::Int(i) => visitor.visit_i64(*i),
+            Expr::BigInt(b) => match b.to_u64() {
+                Some(u) => visitor.visit_u64(u),
+                None => visitor.visit_string(b.to_string()),
+            },
+            Expr::Ratio(r) => visitor.visit_f64(r.to_f64()),
+            Expr::Float(f) => visitor.visit_f64(*f),
+            Expr::String(s) => visitor.visit_borrowed_str(s),
+            Expr::Symbol(s) => visitor.visit_borrowed_str(s),
+            Expr::Keyword(kw) => visitor.visit_string(marked_keyword(kw)),
+            Expr::List(items) => visitor.visit_seq(de::value::SeqDeserializer::new(items.iter())),
+            Expr::Vector(items) => visitor.visit_seq(de::value::SeqDeserializer::new(items.iter())),
+            Expr::Set(set) => visitor.visit_seq(de::value::SeqDeserializer::new(set.iter())),
+            Expr::Map(map) => visitor.visit_map(de::value::MapDeserializer::new(map.iter())),
+            Expr::SortedMap(map) => visitor.visit_map(de::value::MapDeserializer::new(map.iter())),
+            expr => Err(EvalError::TypeMismatch {
+                expected: "data",
+                actual: expr.type_name(),
+            }),
+        }
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> EvalResult<V::Value> {
+        match self.as_no_meta() {
+            Expr::Keyword(kw) => visitor.visit_borrowed_str(kw.name()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> EvalResult<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> EvalResult<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> EvalResult<V::Value> {
+        match self.as_no_meta() {
+            Expr::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> EvalResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Unit variants are strings or keywords, others are maps with a single entry.
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> EvalResult<V::Value> {
+        match self.as_no_meta() {
+            Expr::String(s) => visitor.visit_enum(s.as_str().into_deserializer()),
+            Expr::Keyword(kw) => visitor.visit_enum(kw.name().into_deserializer()),
+            Expr::Map(map) if map.len() == 1 => {
+                let (k, v) = map.iter().next().expect("map has one entry");
+                visitor.visit_enum(de::value::MapAccessDeserializer::new(
+                    de::value::MapDeserializer::new(std::iter::once((k, v))),
+                ))
+            }
+            expr => Err(EvalError::TypeMismatch {
+                expected: "string, keyword or map with one entry",
+                actual: expr.type_name(),
+            }),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char bytes byte_buf unit
+        unit_struct seq tuple tuple_struct map struct ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, EvalError> for &'de Expr {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> IntoDeserializer<'de, EvalError> for &'de MapKey {
+    type Deserializer = &'de Expr;
+
+    fn into_deserializer(self) -> &'de Expr {
+        self.as_expr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::from_expr;
+    use crate::{ast::Expr, environment::Environment, eval::eval, parser::parse};
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    enum Mode {
+        Fast,
+        Retry { times: u32 },
+    }
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    #[serde(rename_all = "kebab-case")]
+    struct Config {
+        name: String,
+        port: u16,
+        hosts: Vec<String>,
+        timeout: Option<f64>,
+        mode: Mode,
+        fallback: Mode,
+        labels: HashMap<String, i64>,
+    }
+
+    #[test]
+    fn struct_from_evaluated_value() {
+        let env = Environment::with_builtins();
+        let src = r#"{:name (str "svc-" 1) :port (* 80 100) :hosts ["a" :b] :timeout nil
+                      :mode :fast :fallback {:retry {:times 3}} :labels {:x 1 "y" 2}}"#;
+        let value = eval(&parse(src).unwrap(), &env).unwrap();
+
+        let config: Config = from_expr(&value).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                name: "svc-1".into(),
+                port: 8000,
+                hosts: vec!["a".into(), "b".into()],
+                timeout: None,
+                mode: Mode::Fast,
+                fallback: Mode::Retry { times: 3 },
+                labels: HashMap::from([("x".into(), 1), ("y".into(), 2)]),
+            }
+        );
+    }
+
+    #[test]
+    fn expr_round_trip() {
+        let value =
+            parse(r#"{:a [1 2.5 "s" :kw nil true] "b" {"c" 18446744073709551615}}"#).unwrap();
+        let copy = Expr::deserialize(&value).unwrap();
+        assert!(copy.lenient_eq(&value), "{copy}");
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let round_trip = |src: &str| {
+            let json = serde_json::to_string(&parse(src).unwrap()).unwrap();
+            serde_json::from_str::<Expr>(&json).unwrap()
+        };
+
+        let lossless = r#"{:a [1 -2.5 "s" :kw nil true] "b" {:c 18446744073709551615}}"#;
+        let value = parse(lossless).unwrap();
+        assert!(round_trip(lossless).lenient_eq(&value));
+
+        let lossy = [
+            ("1/3", "0.3333333333333333"),
+            ("-36893488147419103232", r#""-36893488147419103232""#),
+            ("36893488147419103232", r#""36893488147419103232""#),
+            ("'sym", r#""sym""#),
+            ("'(1 (2))", "[1 [2]]"),
+            ("#{1}", "[1]"),
+            ("^{:m 1} [1]", "[1]"),
+        ];
+        let env = Environment::with_builtins();
+        for (src, expected) in lossy {
+            let value = eval(&parse(src).unwrap(), &env).unwrap();
+            let json = serde_json::to_string(&value).unwrap();
+            let copy = serde_json::from_str::<Expr>(&json).unwrap();
+            assert_eq!(format!("{copy:#}"), expected, "{src}");
+        }
+        assert_eq!(from_expr::<f64>(&parse("1/4").unwrap()).unwrap(), 0.25);
+        assert_eq!(from_expr::<String>(&parse("sym").unwrap()).unwrap(), "sym");
+    }
+
+    #[test]
+    fn errors() {
+        let err = from_expr::<u8>(&parse("300").unwrap()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "deserialization error: invalid value: integer `300`, expected u8"
+        );
+
+        let env = Environment::with_builtins();
+        let f = eval(&parse("(fn* [] 1)").unwrap(), &env).unwrap();
+        let err = from_expr::<String>(&f).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "type mismatch: expected data, got function"
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use std::{cmp::Ordering, ops::Range, slice};
+
+use crate::eval::EvalResult;
+
+use super::Expr;
+
+/// Map keeping its entries ordered by key.
+///
+/// Entries are ordered by [`Expr::total_cmp`] unless the map was created with a
+/// comparator function. Calling a comparator needs the evaluator, so every operation that
+/// looks up a key takes the comparison as a parameter instead of storing it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortedMap {
+    comparator: Option<Expr>,
+    entries: Vec<(Expr, Expr)>,
+}
+
+/// Compares two keys of a [`SortedMap`].
+pub type KeyCmp<'a> = dyn FnMut(&Expr, &Expr) -> EvalResult<Ordering> + 'a;
+
+impl SortedMap {
+    pub fn new(comparator: Option<Expr>) -> Self {
+        Self {
+            comparator,
+            entries: vec![],
+        }
+    }
+
+    pub fn comparator(&self) -> Option<&Expr> {
+        self.comparator.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(
+        &self,
+    ) -> impl DoubleEndedIterator<Item = (&Expr, &Expr)> + ExactSizeIterator + Clone {
+        self.entries.iter().map(|(k, v)| (k, v))
+    }
+
+    pub fn keys(&self) -> impl DoubleEndedIterator<Item = &Expr> + ExactSizeIterator {
+        self.entries.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl DoubleEndedIterator<Item = &Expr> + ExactSizeIterator {
+        self.entries.iter().map(|(_, v)| v)
+    }
+
+    /// Entries with indices in `range`, see [`SortedMap::search`].
+    pub fn range(&self, range: Range<usize>) -> slice::Iter<'_, (Expr, Expr)> {
+        self.entries[range].iter()
+    }
+
+    /// Binary search for `key`, returning its index or the index where it would be inserted.
+    pub fn search(&self, key: &Expr, cmp: &mut KeyCmp<'_>) -> EvalResult<Result<usize, usize>> {
+        let (mut low, mut high) = (0, self.entries.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match cmp(&self.entries[mid].0, key)? {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => return Ok(Ok(mid)),
+            }
+        }
+        Ok(Err(low))
+    }
+
+    pub fn get(&self, key: &Expr, cmp: &mut KeyCmp<'_>) -> EvalResult<Option<&Expr>> {
+        Ok(self.search(key, cmp)?.ok().map(|i| &self.entries[i].1))
+    }
+
+    pub fn insert(&mut self, key: Expr, value: Expr, cmp: &mut KeyCmp<'_>) -> EvalResult<()> {
+        match self.search(&key, cmp)? {
+            Ok(i) => self.entries[i].1 = value,
+            Err(i) => self.entries.insert(i, (key.into_no_meta(), value)),
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &Expr, cmp: &mut KeyCmp<'_>) -> EvalResult<Option<Expr>> {
+        Ok(match self.search(key, cmp)? {
+            Ok(i) => Some(self.entries.remove(i).1),
+            Err(_) => None,
+        })
+    }
+}
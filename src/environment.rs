@@ -1,45 +1,180 @@
-use std::{borrow::Cow, cell::RefCell, fmt, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    rc::Rc,
+};
 
 use fnv::FnvHashMap;
 
 use crate::{
-    ast::Expr,
-    eval::builtins::{BUILTINS, THUNK_BUILTINS},
+    ast::{interner::SymbolId, pattern::Pattern, Arity, Expr, NativeFunction},
+    eval::EvalResult,
 };
 
+pub use self::builder::EnvironmentBuilder;
+
+mod builder;
+pub mod gc;
+
 #[derive(Default, PartialEq)]
 pub struct Environment {
-    variables: RefCell<FnvHashMap<Cow<'static, str>, Expr>>,
+    /// Parameters and `let*` or `loop*` bindings in the order they were bound, which
+    /// compiled code knows ahead and reads by index.
+    slots: RefCell<Vec<(SymbolId, Expr)>>,
+    /// Variables of the top level scope, and the ones `def!` or `eval*` add to others.
+    variables: RefCell<FnvHashMap<SymbolId, Expr>>,
     parent: Option<Env>,
+    /// Set in the scope of a `loop*` iteration.
+    recur_target: Option<Rc<Loop>>,
+    /// Whether variables were added after the scope was created, e.g. by `def!` in a
+    /// function body, so they can shadow ones compiled code expects in outer scopes.
+    extended: Cell<bool>,
 }
 
 pub type Env = Rc<Environment>;
 
+/// Bindings and body of a `loop*`, which `recur` evaluates again with new values.
+#[derive(Debug, PartialEq)]
+pub struct Loop {
+    pub bindings: Vec<Pattern>,
+    pub body: Rc<Expr>,
+}
+
 impl Environment {
+    // clippy suggests eliding the lifetimes, which doesn't compile with an `Rc` receiver
+    #[allow(clippy::needless_lifetimes)]
     pub fn top_level_env<'a>(self: &'a Env) -> &'a Env {
         std::iter::successors(Some(self), |env| env.parent.as_ref())
             .last()
             .unwrap()
     }
 
-    pub fn get(&self, name: &str) -> Option<Expr> {
-        self.variables
-            .borrow()
-            .get(name)
-            .cloned()
-            .or_else(|| self.parent.as_ref()?.get(name))
+    pub(crate) fn parent(&self) -> Option<&Env> {
+        self.parent.as_ref()
+    }
+
+    /// The innermost `loop*` that `recur` starts again, with the scope the loop was entered from.
+    pub fn enclosing_loop(&self) -> Option<(Rc<Loop>, Env)> {
+        match &self.recur_target {
+            Some(target) => Some((Rc::clone(target), self.parent.clone()?)),
+            None => self.parent.as_ref()?.enclosing_loop(),
+        }
+    }
+
+    pub fn get(&self, name: impl Into<SymbolId>) -> Option<Expr> {
+        self.lookup(name.into())
+    }
+
+    fn lookup(&self, id: SymbolId) -> Option<Expr> {
+        let mut env = self;
+        loop {
+            if let Some(value) = env.find(id) {
+                return Some(value);
+            }
+            env = env.parent.as_ref()?;
+        }
     }
 
-    pub fn set(&self, name: &str, expr: Expr) {
-        self.set_cow(Cow::Owned(name.to_owned()), expr)
+    /// Value of `id` in this scope, without looking at the parents.
+    fn find(&self, id: SymbolId) -> Option<Expr> {
+        let slots = self.slots.borrow();
+        if let Some((_, value)) = slots.iter().find(|&&(name, _)| name == id) {
+            return Some(value.clone());
+        }
+        let variables = self.variables.borrow();
+        if variables.is_empty() {
+            return None;
+        }
+        variables.get(&id).cloned()
     }
 
-    pub fn set_special(&self, name: &'static str, expr: Expr) {
-        self.set_cow(Cow::Borrowed(name), expr)
+    /// Looks up `id` like [`Environment::get`], skipping the `depth` innermost scopes
+    /// known not to bind it unless variables were added to them since, and reading
+    /// `slot` of the next one if it holds `id`.
+    pub(crate) fn get_skipping(
+        &self,
+        depth: usize,
+        slot: Option<usize>,
+        id: SymbolId,
+    ) -> Option<Expr> {
+        let mut env = self;
+        for _ in 0..depth {
+            if env.extended.get() {
+                return self.lookup(id);
+            }
+            env = env.parent.as_ref()?;
+        }
+        if let Some(slot) = slot {
+            if let Some((name, value)) = env.slots.borrow().get(slot) {
+                if *name == id {
+                    return Some(value.clone());
+                }
+            }
+        }
+        env.lookup(id)
     }
 
-    fn set_cow(&self, name: Cow<'static, str>, expr: Expr) {
-        self.variables.borrow_mut().insert(name, expr);
+    pub fn set(&self, name: impl Into<SymbolId>, expr: Expr) {
+        let id = name.into();
+        self.mark_extended();
+        let mut slots = self.slots.borrow_mut();
+        match slots.iter_mut().find(|(name, _)| *name == id) {
+            Some((_, value)) => *value = expr,
+            None => {
+                drop(slots);
+                self.variables.borrow_mut().insert(id, expr);
+            }
+        }
+    }
+
+    /// Binds a parameter or a `let*` variable in a scope being created, unlike
+    /// [`Environment::set`] it doesn't count as extending the scope.
+    ///
+    /// Names are given slots in the order they are first bound. The top level scope, where
+    /// `def!` binds the names of a pattern, keeps its variables in the hash map instead.
+    pub(crate) fn bind(&self, id: SymbolId, expr: Expr) {
+        if self.parent.is_none() {
+            self.variables.borrow_mut().insert(id, expr);
+            return;
+        }
+        let mut slots = self.slots.borrow_mut();
+        match slots.iter_mut().find(|(name, _)| *name == id) {
+            Some((_, value)) => *value = expr,
+            None => slots.push((id, expr)),
+        }
+    }
+
+    /// Records that variables were added after the scope was created.
+    pub(crate) fn mark_extended(&self) {
+        self.extended.set(true);
+    }
+
+    /// Exposes a Rust closure to mal code as the function `name`.
+    ///
+    /// The closure receives the already evaluated arguments, after their count was checked
+    /// against `arity`.
+    ///
+    /// ```
+    /// use rust2::{ast::Expr, environment::Environment, eval::eval, parser::parse};
+    ///
+    /// let env = Environment::with_builtins();
+    /// env.register_native("twice", 1, |args, _env| {
+    ///     Ok(Expr::List(vec![args[0].clone(), args[0].clone()].into()))
+    /// });
+    ///
+    /// let result = eval(&parse("(twice 21)").unwrap(), &env).unwrap();
+    /// assert_eq!(result.to_string(), "(21 21)");
+    /// ```
+    pub fn register_native(
+        &self,
+        name: &str,
+        arity: impl Into<Arity>,
+        func: impl Fn(&[Expr], &Env) -> EvalResult<Expr> + 'static,
+    ) {
+        self.set(
+            name,
+            Expr::NativeFunction(NativeFunction::new(name, arity, func)),
+        );
     }
 }
 
@@ -49,16 +184,10 @@ impl Environment {
         Rc::new(env)
     }
 
+    /// Creates an environment with all builtins, including the ones accessing the host,
+    /// see [`Environment::builder`] for a restricted one.
     pub fn with_builtins() -> Env {
-        let env = Self::new();
-        for (builtin, _) in BUILTINS {
-            env.set_special(builtin, Expr::BuiltinFunction(builtin));
-        }
-        for (builtin, _) in THUNK_BUILTINS {
-            env.set_special(builtin, Expr::BuiltinFunction(builtin));
-        }
-
-        env
+        Self::builder().all().build()
     }
 
     pub fn with_parent(parent: Env) -> Env {
@@ -68,20 +197,42 @@ impl Environment {
         };
         Rc::new(env)
     }
+
+    /// Creates a scope with `slots` already bound, their names must be distinct.
+    pub(crate) fn with_slots(parent: Env, slots: Vec<(SymbolId, Expr)>) -> Env {
+        let env = Environment {
+            slots: RefCell::new(slots),
+            parent: Some(parent),
+            ..Default::default()
+        };
+        Rc::new(env)
+    }
+
+    /// Creates the scope of one iteration of `target`, in which `recur` starts it again.
+    pub fn for_loop(parent: Env, target: Rc<Loop>) -> Env {
+        let env = Environment {
+            parent: Some(parent),
+            recur_target: Some(target),
+            ..Default::default()
+        };
+        Rc::new(env)
+    }
 }
 
-struct SimpleExprMapDebug<'a>(&'a FnvHashMap<Cow<'static, str>, Expr>);
+struct SimpleExprMapDebug<'a>(&'a Environment);
 
 impl fmt::Debug for SimpleExprMapDebug<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let env = self.0;
+        let slots = self.0.slots.borrow();
+        let variables = self.0.variables.borrow();
         let mut map = f.debug_map();
-        for (k, e) in env {
+        for (k, e) in slots.iter().map(|(k, e)| (k, e)).chain(variables.iter()) {
             match e {
                 Expr::Function(_) => map.entry(k, &"#<function>"),
+                Expr::NativeFunction(_) => map.entry(k, &"#<native-function>"),
                 Expr::List(_) => map.entry(k, &"#<list>"),
                 Expr::Vector(_) => map.entry(k, &"#<vector>"),
-                Expr::Map(_) => map.entry(k, &"#<map>"),
+                Expr::Map(_) | Expr::SortedMap(_) => map.entry(k, &"#<map>"),
                 Expr::Atom(_) => map.entry(k, &"#<atom>"),
                 Expr::BuiltinFunction(_) => continue,
                 _ => map.entry(k, e),
@@ -96,7 +247,7 @@ impl fmt::Debug for Environment {
         let alternate = f.alternate();
         let mut debug = f.debug_struct("Environment");
         if alternate {
-            debug.field("variables", &SimpleExprMapDebug(&*self.variables.borrow()))
+            debug.field("variables", &SimpleExprMapDebug(self))
         } else {
             debug.field("variables", &"#{env}")
         };
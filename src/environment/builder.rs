@@ -0,0 +1,284 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::{
+    ast::{interner::SymbolId, Expr},
+    eval::{
+        builtins::{self, Capability, BUILTINS, THUNK_BUILTINS},
+        EvalError, EvalResult,
+    },
+};
+
+use super::{Env, Environment};
+
+/// Creates an environment with builtins limited to a chosen set of capabilities,
+/// for embedding scripts that shouldn't have access to the host.
+///
+/// Without any capabilities only pure builtins work. Builtins needing a capability
+/// that wasn't granted throw a "permission denied" exception when called.
+///
+/// ```
+/// use rust2::{environment::Environment, eval::eval, parser::parse};
+///
+/// let env = Environment::builder().console().build();
+/// let err = eval(&parse("(slurp \"/etc/passwd\")").unwrap(), &env).unwrap_err();
+/// assert_eq!(
+///     err.to_string(),
+///     "exception occurred: permission denied: slurp needs filesystem access"
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct EnvironmentBuilder {
+    console: bool,
+    clock: bool,
+    filesystem: Option<FileAccess>,
+}
+
+#[derive(Debug, Clone)]
+enum FileAccess {
+    Unrestricted,
+    /// Only files inside the directory can be read.
+    Root(PathBuf),
+}
+
+impl Environment {
+    /// Starts building an environment with only pure builtins.
+    pub fn builder() -> EnvironmentBuilder {
+        EnvironmentBuilder::default()
+    }
+}
+
+impl EnvironmentBuilder {
+    /// Allows `prn`, `println` and `readline`.
+    pub fn console(mut self) -> Self {
+        self.console = true;
+        self
+    }
+
+    /// Allows `time-ms`.
+    pub fn clock(mut self) -> Self {
+        self.clock = true;
+        self
+    }
+
+    /// Allows `slurp` and `load-file` to read any file.
+    pub fn filesystem(mut self) -> Self {
+        self.filesystem = Some(FileAccess::Unrestricted);
+        self
+    }
+
+    /// Allows `slurp` and `load-file` to read files inside `root`.
+    ///
+    /// Paths are resolved relative to `root`, and can't leave it with `..`,
+    /// absolute paths or symbolic links.
+    pub fn filesystem_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.filesystem = Some(FileAccess::Root(root.into()));
+        self
+    }
+
+    /// Grants every capability, like [`Environment::with_builtins`].
+    pub fn all(self) -> Self {
+        self.console().clock().filesystem()
+    }
+
+    fn allows(&self, capability: Capability) -> bool {
+        match capability {
+            Capability::Console => self.console,
+            Capability::Filesystem => self.filesystem.is_some(),
+            Capability::Clock => self.clock,
+        }
+    }
+
+    pub fn build(self) -> Env {
+        let env = Environment::new();
+        let names = BUILTINS.iter().map(|&(name, _)| name);
+        let names = names.chain(THUNK_BUILTINS.iter().map(|&(name, _)| name));
+
+        for name in names {
+            match (Capability::required_by(name), &self.filesystem) {
+                (Some(capability), _) if !self.allows(capability) => {
+                    env.register_native(name, 0.., move |_, _| {
+                        Err(permission_denied(format!(
+                            "{name} needs {capability} access"
+                        )))
+                    });
+                }
+                (Some(Capability::Filesystem), Some(FileAccess::Root(root))) => {
+                    register_restricted(&env, name, root.clone());
+                }
+                _ => {
+                    let id = SymbolId::intern(name);
+                    env.set(id, Expr::BuiltinFunction(id));
+                }
+            }
+        }
+
+        env
+    }
+}
+
+/// Registers a file builtin that only accepts paths inside `root`.
+fn register_restricted(env: &Env, name: &'static str, root: PathBuf) {
+    match name {
+        "slurp" => env.register_native(name, 1, move |args, _| {
+            builtins::slurp(&resolve(&root, &args[0])?)
+        }),
+        "load-file" => env.register_native(name, 1, move |args, env| {
+            let path = resolve(&root, &args[0])?;
+            builtins::load_file(&path, &args[0].to_string(), env)
+        }),
+        _ => unreachable!("{name} doesn't read files"),
+    }
+}
+
+fn resolve(root: &Path, path: &Expr) -> EvalResult<PathBuf> {
+    let path = path
+        .as_no_meta()
+        .as_string()
+        .ok_or_else(|| EvalError::InvalidArgumentTypes(vec![path.to_string()]))?;
+    let outside = || permission_denied(format!("{path} is outside of the allowed directory"));
+
+    // checked before touching the filesystem, so that errors don't reveal which files exist
+    let relative = Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !relative {
+        return Err(outside());
+    }
+
+    let root = root.canonicalize()?;
+    let resolved = root.join(path).canonicalize()?;
+    if !resolved.starts_with(&root) {
+        return Err(outside());
+    }
+    Ok(resolved)
+}
+
+fn permission_denied(reason: String) -> EvalError {
+    EvalError::PermissionDenied(reason).to_exception()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::PathBuf,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use crate::{
+        ast::Expr,
+        environment::{Env, Environment},
+        eval::{self, eval, vm},
+        parser::parse,
+    };
+
+    /// The value of `src`, or the exception it throws.
+    fn run(env: &Env, src: &str) -> String {
+        match eval::run(env, &format!("(try* {src} (catch* e e))")) {
+            Expr::String(s) => s,
+            e => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn capabilities() {
+        let pure = Environment::builder().build();
+        assert_eq!(run(&pure, "(+ 1 2)"), "3");
+        assert_eq!(
+            run(&pure, "(println 1)"),
+            "permission denied: println needs console access"
+        );
+        assert_eq!(
+            run(&pure, "(time-ms)"),
+            "permission denied: time-ms needs clock access"
+        );
+
+        let clock = Environment::builder().clock().build();
+        assert_eq!(run(&clock, "(number? (time-ms))"), "true");
+    }
+
+    #[test]
+    fn builtins_are_only_reached_through_the_environment() {
+        let pure = Environment::builder().build();
+        let errors = |src: &str| {
+            let expr = parse(src).unwrap();
+            [eval(&expr, &pure), vm::eval(&expr, &pure)].map(|r| r.unwrap_err().to_string())
+        };
+        let denied = "exception occurred: permission denied: slurp needs filesystem access";
+        for call in [
+            r#"(slurp "/etc/hostname")"#,
+            r#"(apply slurp ["/etc/hostname"])"#,
+            r#"(first (map slurp ["/etc/hostname"]))"#,
+            r#"(first (map 'slurp ["/etc/hostname"]))"#,
+        ] {
+            assert_eq!(errors(call), [denied; 2], "{call}");
+        }
+        for (call, name) in [
+            (r#"('slurp "/etc/hostname")"#, "slurp"),
+            (r#"((symbol "slurp") "/etc/hostname")"#, "slurp"),
+            (r#"(apply 'slurp ["/etc/hostname"])"#, "slurp"),
+            ("('time-ms)", "time-ms"),
+        ] {
+            let invalid = format!("invalid function name: {name}");
+            assert_eq!(errors(call), [invalid.as_str(); 2], "{call}");
+        }
+
+        for call in ["('+ 1 2)", "(apply '+ [1 2])", "((symbol \"+\") 1 2)"] {
+            let expr = parse(call).unwrap();
+            let values = [eval(&expr, &pure), vm::eval(&expr, &pure)];
+            assert_eq!(values.map(|r| r.unwrap().to_string()), ["3", "3"], "{call}");
+        }
+    }
+
+    /// Directory removed when dropped, even if the test fails.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos();
+            let unique = format!("{name}-{}-{nanos}", std::process::id());
+            let dir = std::env::temp_dir().join(unique);
+            fs::create_dir(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn filesystem_root() {
+        let temp = TempDir::new("mal-sandbox");
+        let dir = &temp.0;
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("sub/inside.txt"), "inside").unwrap();
+        fs::write(dir.join("outside.txt"), "outside").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("outside.txt"), root.join("link.txt")).unwrap();
+
+        let env = Environment::builder().filesystem_root(&root).build();
+        assert_eq!(run(&env, r#"(slurp "sub/inside.txt")"#), "inside");
+        assert_eq!(run(&env, r#"(slurp "./sub/inside.txt")"#), "inside");
+        let absolute = dir.join("outside.txt").display().to_string();
+        let mut outside = vec!["../outside.txt", "sub/../inside.txt", &absolute];
+        if cfg!(unix) {
+            outside.push("link.txt");
+        }
+        for path in outside {
+            assert_eq!(
+                run(&env, &format!("(slurp {path:?})")),
+                format!("permission denied: {path} is outside of the allowed directory")
+            );
+        }
+
+        let err = eval(&parse(r#"('slurp "../outside.txt")"#).unwrap(), &env).unwrap_err();
+        assert_eq!(err.to_string(), "invalid function name: slurp");
+    }
+}
@@ -0,0 +1,476 @@
+//! Collection of reference cycles between environments, closures and atoms.
+//!
+//! `def!` stores functions in the environment they close over, and atoms can hold
+//! themselves, so values form `Rc` cycles that are never freed on their own.
+//! [`collect`] finds the ones no longer reachable from outside using trial deletion:
+//! every `Rc` allocation reachable from the tracked environments and atoms is a node,
+//! and a node whose strong count is higher than the number of references found inside
+//! the graph is held from outside, e.g. by the host or the Rust stack. Nodes not reachable
+//! from such nodes are garbage, and clearing their environments and atoms breaks the cycles.
+
+use std::{
+    cell::RefCell,
+    mem,
+    rc::{Rc, Weak},
+};
+
+use fnv::FnvHashMap;
+
+use crate::{
+    ast::{pattern::Pattern, sorted_map::SortedMap, Expr, FunctionArity, MapKey},
+    eval::{compile::Code, vm::Chunk, STACK_RED_ZONE, STACK_SEGMENT_SIZE},
+    span::Spanned,
+};
+
+use super::{Env, Environment, Loop};
+
+type Atom = Rc<RefCell<Expr>>;
+
+/// Closures and bytecode compiled from a function body.
+type Compiled = (Option<Code>, Option<Rc<Chunk>>);
+
+/// Environments captured by closures and atoms, the only places cycles can go through.
+enum Tracked {
+    Env(Weak<Environment>),
+    Atom(Weak<RefCell<Expr>>),
+}
+
+enum Root {
+    Env(Env),
+    Atom(Atom),
+}
+
+#[derive(Default)]
+struct Registry {
+    /// Keyed by address, which can't be reused while the weak reference is alive.
+    tracked: FnvHashMap<usize, Tracked>,
+    /// Size after the last removal of dead entries.
+    live: usize,
+}
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = RefCell::default();
+}
+
+fn address<T: ?Sized>(rc: &Rc<T>) -> usize {
+    Rc::as_ptr(rc).cast::<()>() as usize
+}
+
+fn track(address: usize, tracked: impl FnOnce() -> Tracked) {
+    REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        registry.tracked.entry(address).or_insert_with(tracked);
+        if registry.tracked.len() > 2 * registry.live + 1024 {
+            registry.tracked.retain(|_, tracked| match tracked {
+                Tracked::Env(env) => env.strong_count() > 0,
+                Tracked::Atom(atom) => atom.strong_count() > 0,
+            });
+            registry.live = registry.tracked.len();
+        }
+    });
+}
+
+/// Registers an environment captured by a closure.
+pub(crate) fn track_env(env: &Env) {
+    track(address(env), || Tracked::Env(Rc::downgrade(env)));
+}
+
+pub(crate) fn track_atom(atom: &Atom) {
+    track(address(atom), || Tracked::Atom(Rc::downgrade(atom)));
+}
+
+/// Frees environments and atoms that are only reachable through reference cycles,
+/// returning how many were freed.
+///
+/// Should be called between evaluations, values referenced only through borrows on the
+/// stack of an ongoing evaluation would look unreachable. Applies to values created
+/// on the current thread. Functions that were compiled are compiled again when called.
+///
+/// ```
+/// use std::rc::Rc;
+///
+/// use rust2::{environment::{gc, Environment}, eval::eval, parser::parse};
+///
+/// let env = Environment::with_builtins();
+/// eval(&parse("(def! f (fn* [] f))").unwrap(), &env).unwrap();
+/// let weak = Rc::downgrade(&env);
+/// drop(env);
+///
+/// assert!(weak.upgrade().is_some());
+/// assert!(gc::collect() > 0);
+/// assert!(weak.upgrade().is_none());
+/// ```
+pub fn collect() -> usize {
+    let roots = REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        let mut roots = vec![];
+        registry.tracked.retain(|_, tracked| {
+            let root = match tracked {
+                Tracked::Env(env) => env.upgrade().map(Root::Env),
+                Tracked::Atom(atom) => atom.upgrade().map(Root::Atom),
+            };
+            let alive = root.is_some();
+            roots.extend(root);
+            alive
+        });
+        registry.live = registry.tracked.len();
+        roots
+    });
+
+    // compiled code holds references the tracer can't see, which would keep the cycles
+    // going through it alive, so it's taken out first and compiled again when called
+    let mut taking = Tracer {
+        taken: Some(vec![]),
+        ..Tracer::default()
+    };
+    for root in &roots {
+        match root {
+            Root::Env(env) => taking.root(env),
+            Root::Atom(atom) => taking.root(atom),
+        };
+    }
+    drop(taking);
+
+    let mut tracer = Tracer::default();
+    let root_ids = roots
+        .iter()
+        .map(|root| match root {
+            Root::Env(env) => tracer.root(env),
+            Root::Atom(atom) => tracer.root(atom),
+        })
+        .collect::<Vec<_>>();
+    // not counting the references in `roots`
+    for &id in &root_ids {
+        tracer.nodes[id].strong -= 1;
+    }
+
+    let live = tracer.live();
+    let mut freed = 0;
+    for (root, id) in roots.iter().zip(root_ids) {
+        if live[id] {
+            continue;
+        }
+        freed += 1;
+        // taken out first and dropped after the borrow ends,
+        // since dropping them can reach other environments and atoms
+        match root {
+            Root::Env(env) => {
+                let slots = env.slots.try_borrow_mut().map(|mut s| mem::take(&mut *s));
+                let variables = env
+                    .variables
+                    .try_borrow_mut()
+                    .map(|mut v| mem::take(&mut *v));
+                drop((slots, variables));
+            }
+            Root::Atom(atom) => {
+                let value = atom
+                    .try_borrow_mut()
+                    .map(|mut atom| mem::replace(&mut *atom, Expr::Nil));
+                drop(value);
+            }
+        }
+    }
+
+    freed
+}
+
+struct Node {
+    strong: usize,
+    /// References from other nodes.
+    internal: usize,
+    edges: Vec<usize>,
+}
+
+#[derive(Default)]
+pub(crate) struct Tracer {
+    ids: FnvHashMap<usize, usize>,
+    nodes: Vec<Node>,
+    current: usize,
+    /// Compiled code taken out of the functions traced, if it's being taken. Dropped
+    /// only after tracing, which could otherwise see new allocations at traced addresses.
+    taken: Option<Vec<Compiled>>,
+}
+
+impl Tracer {
+    fn node<T: Trace + ?Sized>(&mut self, rc: &Rc<T>) -> (usize, bool) {
+        let next = self.nodes.len();
+        let id = *self.ids.entry(address(rc)).or_insert(next);
+        if id != next {
+            return (id, false);
+        }
+        self.nodes.push(Node {
+            strong: Rc::strong_count(rc),
+            internal: 0,
+            edges: vec![],
+        });
+        (id, true)
+    }
+
+    fn trace_node<T: Trace + ?Sized>(&mut self, id: usize, value: &T) {
+        let parent = mem::replace(&mut self.current, id);
+        // values nest as deep as lists built with `cons` are long
+        stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT_SIZE, || value.trace(self));
+        self.current = parent;
+    }
+
+    fn root<T: Trace + ?Sized>(&mut self, rc: &Rc<T>) -> usize {
+        let (id, new) = self.node(rc);
+        if new {
+            self.trace_node(id, &**rc);
+        }
+        id
+    }
+
+    /// Records a reference from the node being traced to `rc`.
+    pub(crate) fn edge<T: Trace + ?Sized>(&mut self, rc: &Rc<T>) {
+        let (id, new) = self.node(rc);
+        self.nodes[id].internal += 1;
+        self.nodes[self.current].edges.push(id);
+        if new {
+            self.trace_node(id, &**rc);
+        }
+    }
+
+    /// Nodes held from outside of the graph, and the ones reachable from them.
+    fn live(&self) -> Vec<bool> {
+        let mut live = vec![false; self.nodes.len()];
+        let mut stack = (0..self.nodes.len())
+            .filter(|&id| self.nodes[id].strong > self.nodes[id].internal)
+            .collect::<Vec<_>>();
+        while let Some(id) = stack.pop() {
+            if !mem::replace(&mut live[id], true) {
+                stack.extend(&self.nodes[id].edges);
+            }
+        }
+        live
+    }
+}
+
+/// Reports the `Rc` allocations a value refers to, see [`Tracer::edge`].
+pub(crate) trait Trace {
+    fn trace(&self, tracer: &mut Tracer);
+}
+
+impl Trace for () {
+    fn trace(&self, _: &mut Tracer) {}
+}
+
+impl<T: Trace> Trace for [T] {
+    fn trace(&self, tracer: &mut Tracer) {
+        for item in self {
+            item.trace(tracer);
+        }
+    }
+}
+
+impl<T: Trace> Trace for Vec<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.as_slice().trace(tracer);
+    }
+}
+
+impl<T: Trace> Trace for Option<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(value) = self {
+            value.trace(tracer);
+        }
+    }
+}
+
+impl<T: Trace> Trace for Spanned<T> {
+    fn trace(&self, tracer: &mut Tracer) {
+        (**self).trace(tracer);
+    }
+}
+
+impl Trace for Expr {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Expr::List(list) => list.trace(tracer),
+            Expr::Vector(vector) => vector.trace(tracer),
+            Expr::Map(map) => tracer.edge(map),
+            Expr::SortedMap(map) => tracer.edge(map),
+            Expr::Set(set) => tracer.edge(set),
+            Expr::Function(f) => {
+                tracer.edge(&f.arities);
+                tracer.edge(&f.closure);
+            }
+            Expr::Atom(atom) => tracer.edge(atom),
+            Expr::MacroExpand(expr) => tracer.edge(expr),
+            Expr::WithMeta { expr, meta } => {
+                tracer.edge(expr);
+                tracer.edge(meta);
+            }
+            // native closures are opaque, so what they capture stays alive
+            Expr::NativeFunction(_)
+            | Expr::Nil
+            | Expr::Bool(_)
+            | Expr::Int(_)
+            | Expr::BigInt(_)
+            | Expr::Ratio(_)
+            | Expr::Float(_)
+            | Expr::String(_)
+            | Expr::Symbol(_)
+            | Expr::Keyword(_)
+            | Expr::BuiltinFunction(_) => {}
+        }
+    }
+}
+
+impl Trace for RefCell<Expr> {
+    fn trace(&self, tracer: &mut Tracer) {
+        // an atom being modified keeps its contents alive
+        if let Ok(expr) = self.try_borrow() {
+            expr.trace(tracer);
+        }
+    }
+}
+
+impl Trace for MapKey {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.as_expr().trace(tracer);
+    }
+}
+
+impl Trace for SortedMap {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(comparator) = self.comparator() {
+            comparator.trace(tracer);
+        }
+        for (key, value) in self.iter() {
+            key.trace(tracer);
+            value.trace(tracer);
+        }
+    }
+}
+
+impl Trace for FunctionArity {
+    fn trace(&self, tracer: &mut Tracer) {
+        if let Some(taken) = &mut tracer.taken {
+            taken.push((self.compiled.take(), self.bytecode.take()));
+        }
+        self.bindings.trace(tracer);
+        self.varargs.trace(tracer);
+        tracer.edge(&self.expr);
+    }
+}
+
+impl Trace for Pattern {
+    fn trace(&self, tracer: &mut Tracer) {
+        match self {
+            Pattern::Symbol(_) => {}
+            Pattern::Sequential { items, rest, .. } => {
+                items.trace(tracer);
+                if let Some(rest) = rest {
+                    rest.trace(tracer);
+                }
+            }
+            Pattern::Associative {
+                entries, defaults, ..
+            } => {
+                for (pattern, key) in entries {
+                    pattern.trace(tracer);
+                    key.trace(tracer);
+                }
+                for (_, default) in defaults {
+                    default.trace(tracer);
+                }
+            }
+        }
+    }
+}
+
+impl Trace for Loop {
+    fn trace(&self, tracer: &mut Tracer) {
+        self.bindings.trace(tracer);
+        tracer.edge(&self.body);
+    }
+}
+
+impl Trace for Environment {
+    fn trace(&self, tracer: &mut Tracer) {
+        // an environment being modified keeps its variables alive
+        if let Ok(slots) = self.slots.try_borrow() {
+            for (_, value) in slots.iter() {
+                value.trace(tracer);
+            }
+        }
+        if let Ok(variables) = self.variables.try_borrow() {
+            for value in variables.values() {
+                value.trace(tracer);
+            }
+        }
+        if let Some(parent) = &self.parent {
+            tracer.edge(parent);
+        }
+        if let Some(target) = &self.recur_target {
+            tracer.edge(target);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::collect;
+    use crate::{
+        ast::Expr,
+        environment::Environment,
+        eval::{run, vm},
+        parser::parse,
+    };
+
+    #[test]
+    fn dropped_env_frees_closures() {
+        let env = Environment::with_builtins();
+        run(&env, "(def! make (fn* [x] (fn* [] x)))");
+        run(&env, "(def! g (make 1))");
+        run(&env, "(def! a (atom nil))");
+        run(&env, "(reset! a [a g])");
+        let Expr::Function(g) = run(&env, "g") else {
+            panic!("expected a function");
+        };
+        let closure = Rc::downgrade(&g.closure);
+        let top = Rc::downgrade(&env);
+        drop((env, g));
+
+        assert!(closure.upgrade().is_some() && top.upgrade().is_some());
+        assert_eq!(collect(), 3);
+        assert!(closure.upgrade().is_none() && top.upgrade().is_none());
+        assert_eq!(collect(), 0);
+    }
+
+    #[test]
+    fn cycles_through_compiled_code_are_freed() {
+        let env = Environment::with_builtins();
+        run(&env, "(def! f (fn* [] 1))");
+        // `g` returns `f` itself, so its compiled code refers to the environment
+        run(&env, "(def! g (eval (list 'fn* [] (list 'quote f))))");
+        run(&env, "(do (g) (g) (g))");
+        vm::eval(&parse("(g)").unwrap(), &env).unwrap();
+        let top = Rc::downgrade(&env);
+        drop(env);
+
+        assert!(top.upgrade().is_some());
+        assert_eq!(collect(), 1);
+        assert!(top.upgrade().is_none());
+    }
+
+    #[test]
+    fn reachable_values_are_kept() {
+        let env = Environment::with_builtins();
+        run(&env, "(def! x 5)");
+        run(&env, "(def! m {:f (fn* [] x)})");
+        // the map is shared between both variables and the host
+        run(&env, "(def! n m)");
+        let m = run(&env, "m");
+        assert_eq!(collect(), 0);
+        drop(env);
+        assert_eq!(collect(), 0);
+
+        let env = Environment::with_builtins();
+        env.set("m", m);
+        assert_eq!(run(&env, "((get m :f))"), Expr::Int(5));
+    }
+}
@@ -1,48 +1,151 @@
-use std::{io, rc::Rc};
+use std::{cell::Cell, io, rc::Rc};
 
 use crate::{
-    ast::{Expr, Function, MapKey},
+    ast::{
+        display::Expected, interner::SymbolId, pattern::Pattern, Arity, Expr, Function,
+        FunctionArity, List, Map, MapKey, NativeFunction, Set,
+    },
     environment::{Env, Environment},
     parser::ParseError,
+    span::Span,
 };
 
-use self::builtins::eval_list_builtin;
+use self::{builtins::eval_list_builtin, compile::Code, limits::Limit, trace::Frame};
 
 pub mod builtins;
+pub mod compile;
+pub mod destructure;
+pub mod limits;
+pub mod trace;
 mod utils;
+pub mod vm;
 
 #[derive(Debug, thiserror::Error)]
 pub enum EvalError {
     #[error("invalid function name: {0}")]
     InvalidFunctionName(String),
-    #[error("invalid number of arguments")]
-    InvalidArgumentCount,
+    /// Carries the argument counts the function accepts, if they are known.
+    #[error("invalid number of arguments{}", Expected(.0))]
+    InvalidArgumentCount(Vec<Arity>),
     #[error("invalid function: {0}")]
     InvalidFunction(String),
     #[error("invalid function arguments: {0:?}")]
     InvalidArgumentTypes(Vec<String>),
+    #[deprecated(note = "malformed parameters are reported as `InvalidPattern`")]
     #[error("invalid vararg arguments")]
     InvalidVarargs,
+    #[error("type mismatch: expected {expected}, got {actual}")]
+    TypeMismatch {
+        expected: &'static str,
+        actual: &'static str,
+    },
+    #[error("{value} does not fit in {target}")]
+    IntegerOutOfRange { value: String, target: &'static str },
+    #[error("deserialization error: {0}")]
+    Deserialize(String),
     #[error("'{0}' not found")]
-    UnknownSymbol(Rc<str>),
+    UnknownSymbol(SymbolId),
     #[error("invalid variable name: {0}")]
     InvalidVariableName(String),
     #[error("invalid variables for let*")]
     InvalidLetVariables,
+    #[error("invalid binding pattern: {0}")]
+    InvalidPattern(String),
+    #[error("invalid recur: {0}")]
+    InvalidRecur(String),
+    /// Not an exception, so that scripts can't catch it with `try*`.
+    #[error("{0} limit exceeded")]
+    LimitExceeded(Limit),
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("stack depth exceeded")]
+    StackDepthExceeded,
     #[error("invalid catch block")]
     InvalidCatchBlock,
     #[error("exception occurred: {0}")]
     Exception(Expr),
     #[error("parsing error: {0}")]
-    ParseError(#[from] ParseError),
+    ParseError(ParseError),
     #[error("IO error: {0}")]
     IOError(#[from] io::Error),
+    #[error("{error}")]
+    WithContext {
+        error: Box<EvalError>,
+        span: Option<Rc<Span>>,
+        trace: Vec<Frame>,
+    },
+}
+
+impl From<ParseError> for EvalError {
+    /// Keeps the location of the error, and lets limits reached while reading code
+    /// propagate like they do during evaluation.
+    fn from(error: ParseError) -> Self {
+        if let ParseError::LimitExceeded(limit) = error.without_span() {
+            return Self::LimitExceeded(*limit);
+        }
+        let span = error.span().cloned();
+        Self::ParseError(error).located(span.as_ref())
+    }
 }
 
 impl EvalError {
     pub fn to_exception(self) -> Self {
         Self::Exception(Expr::String(self.to_string()))
     }
+
+    fn with_context(self, f: impl FnOnce(&mut Option<Rc<Span>>, &mut Vec<Frame>)) -> Self {
+        let (error, mut span, mut trace) = match self {
+            Self::WithContext { error, span, trace } => (error, span, trace),
+            error => (Box::new(error), None, vec![]),
+        };
+        f(&mut span, &mut trace);
+        Self::WithContext { error, span, trace }
+    }
+
+    /// Attaches `span` to the error, unless it already points to a more specific location.
+    pub fn located(self, span: Option<&Rc<Span>>) -> Self {
+        match (self, span) {
+            (error @ Self::WithContext { span: Some(_), .. }, _) | (error, None) => error,
+            (error, Some(span)) => error.with_context(|s, _| *s = Some(Rc::clone(span))),
+        }
+    }
+
+    /// Records that the error propagated out of `frame`.
+    pub fn traced(self, frame: Frame) -> Self {
+        self.with_context(|_, trace| trace.push(frame))
+    }
+
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Self::WithContext { span, .. } => span.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn trace(&self) -> &[Frame] {
+        match self {
+            Self::WithContext { trace, .. } => trace,
+            _ => &[],
+        }
+    }
+
+    pub fn without_context(&self) -> &Self {
+        match self {
+            Self::WithContext { error, .. } => error,
+            error => error,
+        }
+    }
+
+    /// Extracts the thrown value if this is an exception, which `try*` is allowed to catch.
+    pub fn into_exception(self) -> Result<Expr, Self> {
+        match self {
+            Self::Exception(exc) => Ok(exc),
+            Self::WithContext { error, span, trace } => error
+                .into_exception()
+                .map_err(|error| error.with_context(|s, t| (*s, *t) = (span, trace))),
+            error => Err(error),
+        }
+    }
 }
 
 pub type EvalResult<T> = std::result::Result<T, EvalError>;
@@ -51,22 +154,53 @@ pub type EvalResult<T> = std::result::Result<T, EvalError>;
 pub enum Thunk {
     Evaluated(Expr),
     Unevaluated(Rc<Expr>, Env),
+    /// Code compiled from a function body, see [`compile`].
+    Compiled(Code, Env),
 }
 
-use fnv::FnvHashMap;
-use Thunk::{Evaluated, Unevaluated};
+use Thunk::{Compiled, Evaluated, Unevaluated};
+
+/// Stack space kept free before evaluating a nested expression, and the size of the
+/// new stack segment allocated when less than that remains.
+pub(crate) const STACK_RED_ZONE: usize = 256 * 1024;
+pub(crate) const STACK_SEGMENT_SIZE: usize = 4 * 1024 * 1024;
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Counts one level of nested evaluation for as long as it's alive.
+struct DepthGuard(());
+
+impl DepthGuard {
+    fn enter() -> EvalResult<Self> {
+        DEPTH.with(|depth| {
+            if depth.get() >= limits::limits().max_depth {
+                return Err(EvalError::StackDepthExceeded.to_exception());
+            }
+            depth.set(depth.get() + 1);
+            Ok(Self(()))
+        })
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|depth| depth.set(depth.get() - 1));
+    }
+}
 
 pub fn eval(expr: &Expr, env: &Env) -> EvalResult<Expr> {
     eval_maybe_macro(expr, env, true)
 }
 
-fn is_macro(expr: &Expr, env: &Env) -> bool {
+pub(crate) fn is_macro(expr: &Expr, env: &Env) -> bool {
     let l = match expr {
         Expr::List(l) => l,
         _ => return false,
     };
 
-    let name = match l.as_slice() {
+    let name = match &l[..] {
         [name, ..] => name,
         _ => return false,
     };
@@ -84,23 +218,67 @@ fn is_macro(expr: &Expr, env: &Env) -> bool {
     matches!(f, Expr::Function(Function { is_macro: true, .. }))
 }
 
+/// Function call currently being evaluated by one [`eval_maybe_macro`] loop.
+#[derive(Default)]
+struct CallStack {
+    current: Option<Frame>,
+    elided: usize,
+}
+
+impl CallStack {
+    fn enter(&mut self, frame: Frame) {
+        if self.current.replace(frame).is_some() {
+            self.elided += 1;
+        }
+    }
+
+    fn attach_to(self, mut error: EvalError) -> EvalError {
+        if let Some(frame) = self.current {
+            error = error.traced(frame);
+        }
+        if self.elided > 0 {
+            error = error.traced(Frame::Elided(self.elided));
+        }
+        error
+    }
+}
+
 fn eval_maybe_macro(expr: &Expr, env: &Env, expand_macros: bool) -> EvalResult<Expr> {
+    let _depth = DepthGuard::enter()?;
+    stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT_SIZE, || {
+        let mut stack = CallStack::default();
+        eval_loop(expr, env, expand_macros, &mut stack).map_err(|e| stack.attach_to(e))
+    })
+}
+
+fn eval_loop(
+    expr: &Expr,
+    env: &Env,
+    expand_macros: bool,
+    stack: &mut CallStack,
+) -> EvalResult<Expr> {
     let mut expr_owner;
-    let mut expr = &*expr;
+    let mut expr = expr;
     let mut env_owner;
-    let mut env = &*env;
+    let mut env = env;
     let mut last_macro = false;
     loop {
         // eprintln!("last_macro = {last_macro}, expr = {}", expr);
         // eprintln!("{:#?}", env);
+        limits::step()?;
         let evaluated = match expr {
-            Expr::Symbol(sym) => match env.get(&**sym) {
+            Expr::Symbol(sym) => match env.get(sym) {
                 Some(f) => Ok(f),
-                None => Err(EvalError::UnknownSymbol(sym.clone()).to_exception()),
+                None => Err(EvalError::UnknownSymbol(sym.id())
+                    .to_exception()
+                    .located(sym.span())),
             },
             Expr::List(v) => {
-                let thunk = eval_list(v, env)?;
-                match thunk {
+                let (thunk, frame) = eval_list(v, env)?;
+                if let Some(frame) = frame {
+                    stack.enter(frame);
+                }
+                match run_compiled(thunk, stack)? {
                     Evaluated(e) => Ok(e),
                     Unevaluated(e, new_env) => {
                         let (e, new_env) = match &*e {
@@ -122,18 +300,26 @@ fn eval_maybe_macro(expr: &Expr, env: &Env, expand_macros: bool) -> EvalResult<E
                         env = &env_owner;
                         continue;
                     }
+                    Compiled(..) => unreachable!("compiled code was run"),
                 }
             }
-            Expr::Vector(v) => Ok(Expr::Vector(
-                v.iter().map(|e| eval(e, env)).collect::<EvalResult<_>>()?,
-            )),
+            Expr::Vector(v) => {
+                limits::allocate(v.len())?;
+                Ok(Expr::Vector(
+                    v.iter().map(|e| eval(e, env)).collect::<EvalResult<_>>()?,
+                ))
+            }
             Expr::Map(m) => eval_map_literal(m, env),
+            Expr::Set(s) => eval_set_literal(s, env),
             Expr::WithMeta { expr, meta } => match &**expr {
                 expr @ Expr::List(_) => eval(expr, env),
                 expr @ (Expr::Vector(_)
                 | Expr::Map(_)
+                | Expr::SortedMap(_)
+                | Expr::Set(_)
                 | Expr::Function(_)
-                | Expr::BuiltinFunction(_)) => eval(expr, env).map(|expr| Expr::WithMeta {
+                | Expr::BuiltinFunction(_)
+                | Expr::NativeFunction(_)) => eval(expr, env).map(|expr| Expr::WithMeta {
                     expr: Rc::new(expr),
                     meta: meta.clone(),
                 }),
@@ -154,63 +340,220 @@ fn eval_maybe_macro(expr: &Expr, env: &Env, expand_macros: bool) -> EvalResult<E
     }
 }
 
-fn eval_list(exprs: &[Expr], env: &Env) -> EvalResult<Thunk> {
-    let (name, args) = match exprs.split_first() {
+/// Runs compiled code, including the compiled functions it calls in tail position,
+/// until it finishes or continues with code that isn't compiled.
+fn run_compiled(mut thunk: Thunk, stack: &mut CallStack) -> EvalResult<Thunk> {
+    while let Compiled(code, env) = thunk {
+        thunk = code.run(&env, stack)?;
+    }
+    Ok(thunk)
+}
+
+/// Finishes evaluating what compiled code out of tail position continued with.
+fn eval_thunk(thunk: Thunk, stack: &mut CallStack) -> EvalResult<Expr> {
+    let _depth = DepthGuard::enter()?;
+    match run_compiled(thunk, stack)? {
+        Evaluated(e) => Ok(e),
+        Unevaluated(e, env) => eval_loop(&e, &env, true, stack),
+        Compiled(..) => unreachable!("compiled code was run"),
+    }
+}
+
+/// Evaluates a call, also returning the stack frame if it entered a user function.
+fn eval_list(list: &List, env: &Env) -> EvalResult<(Thunk, Option<Frame>)> {
+    let (name, args) = match list.split_first() {
         Some(split) => split,
-        None => return Ok(Evaluated(Expr::List(vec![]))),
+        None => return Ok((Evaluated(Expr::List(List::new())), None)),
     };
 
-    let name = eval(name, env)?.into_no_meta();
+    eval_call(name, args, env).map_err(|e| e.located(list.call_span()))
+}
+
+fn eval_call(name_expr: &Expr, args: &[Expr], env: &Env) -> EvalResult<(Thunk, Option<Frame>)> {
+    let name = eval(name_expr, env)?.into_no_meta();
 
     if let Some(ret) = eval_list_builtin(&name, args, env) {
-        return ret;
+        return ret.map(|thunk| (thunk, None));
     }
 
     let f = match &name {
         Expr::Function(f) => f,
+        Expr::NativeFunction(native) => return eval_native_call(native, name_expr, args, env),
         _ => return Err(EvalError::InvalidFunctionName(name.to_string())),
     };
 
-    use std::cmp::Ordering::*;
-    match args.len().cmp(&f.bindings.len()) {
-        Equal if f.varargs.is_none() => {}
-        Greater | Equal if f.varargs.is_some() => {}
-        _ => return Err(EvalError::InvalidArgumentCount),
-    }
+    let arity = f
+        .find_arity(args.len())
+        .ok_or_else(|| EvalError::InvalidArgumentCount(f.arities()))?;
 
-    let mut args = if f.is_macro {
-        args.to_vec()
+    let arg_count = args.len();
+    let args_env = if f.is_macro {
+        bind_args(f, arity, args.iter().cloned().map(Ok))?
     } else {
-        args.iter()
-            .map(|e| eval(e, env))
-            .collect::<EvalResult<Vec<_>>>()?
+        bind_args(f, arity, args.iter().map(|e| eval(e, env)))?
     };
 
-    let args_env = Environment::with_parent(f.closure.clone());
+    if f.is_macro {
+        let thunk = Unevaluated(Rc::new(Expr::MacroExpand(Rc::clone(&arity.expr))), args_env);
+        Ok((thunk, None))
+    } else {
+        let frame = call_frame(f, arg_count, name_expr);
+        Ok((compile::enter(arity, &f.closure, args_env), Some(frame)))
+    }
+}
+
+/// Creates the scope of a call to `f`, with `args` bound to the parameters of `arity`.
+///
+/// `args` are consumed in order, the ones after the bindings are collected for the
+/// varargs. Parameters get the slots compiled code expects, in the order of
+/// [`compile::parameters`].
+fn bind_args(
+    f: &Function,
+    arity: &FunctionArity,
+    args: impl IntoIterator<Item = EvalResult<Expr>>,
+) -> EvalResult<Env> {
+    let mut args = args.into_iter();
+    let mut bindings = arity.bindings.iter();
 
-    if let Some(varargs_name) = &f.varargs {
-        let varargs = args.split_off(f.bindings.len());
-        args_env.set(varargs_name, Expr::List(varargs));
+    if arity.positional {
+        let mut slots = Vec::with_capacity(arity.bindings.len() + arity.varargs.is_some() as usize);
+        for (binding, arg) in bindings.zip(&mut args) {
+            let Pattern::Symbol(name) = binding else {
+                unreachable!("positional parameters are symbols")
+            };
+            slots.push((*name, arg?));
+        }
+        if let Some(Pattern::Symbol(name)) = &arity.varargs {
+            slots.push((*name, Expr::List(args.collect::<EvalResult<_>>()?)));
+        }
+        return Ok(Environment::with_slots(f.closure.clone(), slots));
     }
 
-    for (binding, arg) in f.bindings.iter().zip(args) {
-        args_env.set(binding, arg);
+    let args_env = Environment::with_parent(f.closure.clone());
+    for (binding, arg) in bindings.by_ref().zip(&mut args) {
+        destructure::bind(binding, arg?, &args_env)?;
+    }
+    if let Some(varargs) = &arity.varargs {
+        let rest = args.collect::<EvalResult<_>>()?;
+        destructure::bind(varargs, Expr::List(rest), &args_env)?;
     }
 
-    if f.is_macro {
-        Ok(Unevaluated(
-            Rc::new(Expr::MacroExpand(Rc::clone(&f.expr))),
-            args_env,
-        ))
-    } else {
-        Ok(Unevaluated(Rc::clone(&f.expr), args_env))
+    Ok(args_env)
+}
+
+fn call_frame(f: &Function, args: usize, name_expr: &Expr) -> Frame {
+    Frame::Call {
+        name: f.name.clone(),
+        args,
+        span: name_expr.symbol_span().cloned(),
     }
 }
 
-fn eval_map_literal(map: &FnvHashMap<MapKey, Expr>, env: &Env) -> EvalResult<Expr> {
+fn eval_set_literal(set: &Set, env: &Env) -> EvalResult<Expr> {
+    limits::allocate(set.len())?;
+    let set = set
+        .iter()
+        .map(|k| Ok(MapKey::new(eval(k.as_expr(), env)?)))
+        .collect::<EvalResult<_>>()?;
+    Ok(Expr::Set(Rc::new(set)))
+}
+
+fn eval_native_call(
+    native: &NativeFunction,
+    name_expr: &Expr,
+    args: &[Expr],
+    env: &Env,
+) -> EvalResult<(Thunk, Option<Frame>)> {
+    check_native_arity(native, args.len())?;
+    let args = args
+        .iter()
+        .map(|e| eval(e, env))
+        .collect::<EvalResult<Vec<_>>>()?;
+    let ret = call_native(native, &args, name_expr, env)?;
+    Ok((Evaluated(ret), None))
+}
+
+fn check_native_arity(native: &NativeFunction, args: usize) -> EvalResult<()> {
+    if !native.arity.accepts(args) {
+        return Err(EvalError::InvalidArgumentCount(vec![native.arity]));
+    }
+    Ok(())
+}
+
+fn call_native(
+    native: &NativeFunction,
+    args: &[Expr],
+    name_expr: &Expr,
+    env: &Env,
+) -> EvalResult<Expr> {
+    (native.func)(args, env).map_err(|e| {
+        e.traced(Frame::Call {
+            name: Some(Rc::clone(&native.name)),
+            args: args.len(),
+            span: name_expr.symbol_span().cloned(),
+        })
+    })
+}
+
+fn eval_map_literal(map: &Map, env: &Env) -> EvalResult<Expr> {
+    limits::allocate(map.len())?;
     let map = map
         .iter()
-        .map(|(k, v)| Ok((k.clone(), eval(v, env)?)))
+        .map(|(k, v)| Ok((MapKey::new(eval(k.as_expr(), env)?), eval(v, env)?)))
         .collect::<EvalResult<_>>()?;
     Ok(Expr::Map(Rc::new(map)))
 }
+
+/// Parses and evaluates `src`, panicking on errors, for tests.
+#[cfg(test)]
+pub(crate) fn run(env: &Env, src: &str) -> Expr {
+    eval(&crate::parser::parse(src).unwrap(), env).unwrap()
+}
+
+/// Evaluates each source in one environment with the builtins and compares the printed
+/// value, or `error: ` followed by the error, with the expected text, for tests.
+#[cfg(test)]
+pub(crate) fn check(cases: &[(&str, &str)]) {
+    let env = Environment::with_builtins();
+    for (src, expected) in cases {
+        let printed = match eval(&crate::parser::parse(src).unwrap(), &env) {
+            Ok(value) => value.to_string(),
+            Err(err) => format!("error: {}", err.without_context()),
+        };
+        assert_eq!(printed, *expected, "{src}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        eval,
+        limits::{with_limits, EvalLimits},
+        EvalError,
+    };
+    use crate::{ast::Expr, environment::Environment, parser::parse};
+
+    #[test]
+    fn deep_recursion() {
+        let env = Environment::with_builtins();
+        let run = |src: &str| eval(&parse(src).unwrap(), &env);
+        run("(def! f (fn* [n] (if (= n 0) 0 (+ 1 (f (- n 1))))))").unwrap();
+
+        // deeper than the native stack of a test thread allows without growing it
+        let deep = EvalLimits {
+            max_depth: 100_000,
+            ..Default::default()
+        };
+        let value = with_limits(deep, || run("(f 20000)"));
+        assert_eq!(value.unwrap(), Expr::Int(20000));
+
+        let err = run("(f 20000)").unwrap_err();
+        assert!(
+            matches!(err.without_context(), EvalError::Exception(Expr::String(s)) if s == "stack depth exceeded"),
+            "{err}"
+        );
+        let caught = run(r#"(try* (f 20000) (catch* e (str "caught: " e)))"#).unwrap();
+        assert_eq!(caught, Expr::String("caught: stack depth exceeded".into()));
+        assert_eq!(run("(f 100)").unwrap(), Expr::Int(100));
+    }
+}
@@ -1,22 +1,33 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use crate::{ast::Expr, environment::Env};
+use crate::{
+    ast::{interner::SymbolId, Expr},
+    environment::Env,
+};
 
 use super::{
     eval,
     utils::*,
-    EvalResult,
+    EvalError, EvalResult,
     Thunk::{self, Evaluated},
 };
 
 mod atoms;
 mod control_flow;
 mod functional;
+mod json;
 mod lists;
+mod loops;
 mod maps;
 mod meta;
+mod numbers;
 mod primitives;
 mod quoting;
+mod sets;
+mod sorted_maps;
 mod strings;
 
 mod prelude {
@@ -24,15 +35,20 @@ mod prelude {
     pub(super) use crate::{
         ast::Expr,
         environment::{Env, Environment},
-        eval::{self, EvalError, EvalResult},
+        eval::{self, limits, EvalError, EvalResult},
     };
 }
 
 use self::{
-    atoms::*, control_flow::*, functional::*, lists::*, maps::*, meta::*, primitives::*,
-    quoting::*, strings::*,
+    atoms::*, control_flow::*, functional::*, json::*, lists::*, loops::*, maps::*, meta::*,
+    numbers::*, primitives::*, quoting::*, sets::*, sorted_maps::*, strings::*,
 };
+pub(crate) use control_flow::{make_closure, parse_fn};
+pub(crate) use loops::parse_loop;
 pub use maps::list_to_hash_map;
+pub(crate) use quoting::{eval_quasiquote_expand, make_quote};
+pub(crate) use sorted_maps::key_cmp;
+pub(crate) use strings::{load_file, slurp};
 
 // const ARITHMETIC_BUILTINS: &[&str] = &["+", "-", "*", "/"];
 // const COMPARISON_BUILTINS: &[&str] = &["<", ">", ">=", "<="];
@@ -41,11 +57,53 @@ pub type BuiltinThunkFn = fn(&[Expr], &Env) -> EvalResult<Thunk>;
 pub type BuiltinFn = fn(&[Expr], &Env) -> EvalResult<Expr>;
 
 macro_rules! number_op {
-    ( $func:ident ( $op:tt ) ) => {
-        |args, env| $func(|a, b| a $op b, args, env)
+    ( $func:ident ( $op:tt $(, $identity:expr)? ) ) => {
+        |args, env| $func(|a, b| a $op b, $($identity,)? args, env)
     };
 }
 
+/// Access to the outside world that some builtins need, see
+/// [`EnvironmentBuilder`](crate::environment::EnvironmentBuilder).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// Reading from `stdin` and printing to `stdout`.
+    Console,
+    /// Reading files.
+    Filesystem,
+    /// Reading the current time.
+    Clock,
+}
+
+impl Capability {
+    /// The capability needed by builtin `name`, or `None` if it's pure.
+    pub fn required_by(name: &str) -> Option<Self> {
+        IO_BUILTINS
+            .iter()
+            .find(|&&(builtin, _)| builtin == name)
+            .map(|&(_, capability)| capability)
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Capability::Console => "console",
+            Capability::Filesystem => "filesystem",
+            Capability::Clock => "clock",
+        })
+    }
+}
+
+/// Builtins from [`BUILTINS`] that need a [`Capability`].
+pub const IO_BUILTINS: &[(&str, Capability)] = &[
+    ("prn", Capability::Console),
+    ("println", Capability::Console),
+    ("readline", Capability::Console),
+    ("slurp", Capability::Filesystem),
+    ("load-file", Capability::Filesystem),
+    ("time-ms", Capability::Clock),
+];
+
 pub const BUILTINS: &[(&str, BuiltinFn)] = &[
     ("def!", eval_def),
     ("defmacro!", eval_def_macro),
@@ -85,14 +143,33 @@ pub const BUILTINS: &[(&str, BuiltinFn)] = &[
     ("assoc", eval_assoc),
     ("dissoc", eval_dissoc),
     ("contains?", eval_contains),
+    // sorted maps
+    ("sorted-map", eval_sorted_map),
+    ("sorted-map-by", eval_sorted_map_by),
+    ("subseq", eval_subseq),
+    ("rsubseq", eval_rsubseq),
+    // sets
+    ("set?", eval_is_set),
+    ("hash-set", eval_hash_set),
+    ("set", eval_set),
+    ("disj", eval_disj),
+    ("union", eval_union),
+    ("intersection", eval_intersection),
+    ("difference", eval_difference),
+    ("subset?", eval_is_subset),
     // strings
     ("pr-str", eval_pr_str),
     ("str", eval_str),
     ("prn", eval_prn),
     ("println", eval_println),
+    ("set-sorted-printing!", eval_set_sorted_printing),
     ("slurp", eval_slurp),
+    ("load-file", eval_load_file),
     ("read-string", eval_read_string),
     ("readline", eval_readline),
+    // json
+    ("json-parse", eval_json_parse),
+    ("json-stringify", eval_json_stringify),
     // quoting
     ("eval", eval_eval),
     ("eval*", eval_eval_local),
@@ -120,46 +197,80 @@ pub const BUILTINS: &[(&str, BuiltinFn)] = &[
     ("keyword?", eval_is_keyword),
     ("keyword", eval_keyword),
     // numbers
-    ("+", number_op!(eval_arithmetic(+))),
-    ("-", number_op!(eval_arithmetic(-))),
-    ("*", number_op!(eval_arithmetic(*))),
-    ("/", number_op!(eval_arithmetic(/))),
+    ("+", number_op!(eval_arithmetic(+, 0))),
+    ("-", eval_sub),
+    ("*", number_op!(eval_arithmetic(*, 1))),
+    ("/", eval_div),
     (">", number_op!(eval_cmp(>))),
     ("<", number_op!(eval_cmp(<))),
     (">=", number_op!(eval_cmp(>=))),
     ("<=", number_op!(eval_cmp(<=))),
+    ("quot", eval_quot),
+    ("rem", eval_rem),
+    ("mod", eval_mod),
+    ("abs", eval_abs),
+    ("min", eval_min),
+    ("max", eval_max),
+    ("inc", eval_inc),
+    ("dec", eval_dec),
+    ("zero?", eval_is_zero),
+    ("pos?", eval_is_pos),
+    ("neg?", eval_is_neg),
+    ("even?", eval_is_even),
+    ("odd?", eval_is_odd),
+    ("float?", eval_is_float),
+    ("int", eval_int),
+    ("float", eval_float),
+    ("floor", eval_floor),
+    ("ceil", eval_ceil),
+    ("round", eval_round),
+    ("ratio?", eval_is_ratio),
+    ("numerator", eval_numerator),
+    ("denominator", eval_denominator),
 ];
 
 pub const THUNK_BUILTINS: &[(&str, BuiltinThunkFn)] = &[
     ("let*", eval_let),
+    ("loop*", eval_loop),
+    ("recur", eval_recur),
     ("do", eval_do),
     ("if", eval_if),
     ("quasiquote", eval_quasiquote),
 ];
 
-pub(super) fn eval_list_builtin(
-    name: &Expr,
-    args: &[Expr],
-    env: &Env,
-) -> Option<EvalResult<Thunk>> {
-    let name: &str = name.as_func_name()?.as_ref();
+/// Calls the builtin `f` evaluated to with unevaluated `args`.
+///
+/// A symbol naming a builtin calls it too, as in `('+ 1 2)`, unless `env` binds the name
+/// to something else. Otherwise a script could call builtins an
+/// [`EnvironmentBuilder`](crate::environment::EnvironmentBuilder) denied with
+/// `('slurp "file")`.
+pub(super) fn eval_list_builtin(f: &Expr, args: &[Expr], env: &Env) -> Option<EvalResult<Thunk>> {
+    let id = match f {
+        Expr::BuiltinFunction(id) => *id,
+        Expr::Symbol(s) => match env.get(s.id()) {
+            Some(value) if value.as_no_meta().as_builtin() != Some(s.id()) => return None,
+            _ => s.id(),
+        },
+        _ => return None,
+    };
 
-    if let Some(thunk_result) = eval_thunk_builtin(name, args, env) {
-        return Some(thunk_result);
+    if let Some(f) = thunk_builtin(id) {
+        return Some(f(args, env));
     }
 
-    BUILTINS
-        .iter()
-        .find(|&&(fname, _)| fname == name)
-        .map(|(_, f)| f(args, env))
-        .map(|res| res.map(Evaluated))
+    builtin(id).map(|f| f(args, env).map(Evaluated))
+}
+
+/// The builtin from [`BUILTINS`] named `id`, whose index is the ID since builtin names
+/// are interned first.
+pub(crate) fn builtin(id: SymbolId) -> Option<BuiltinFn> {
+    BUILTINS.get(id.index()).map(|&(_, f)| f)
 }
 
-fn eval_thunk_builtin(name: &str, args: &[Expr], env: &Env) -> Option<EvalResult<Thunk>> {
-    THUNK_BUILTINS
-        .iter()
-        .find(|&&(fname, _)| fname == name)
-        .map(|(_, f)| f(args, env))
+/// The builtin from [`THUNK_BUILTINS`] named `id`, interned right after [`BUILTINS`].
+pub(crate) fn thunk_builtin(id: SymbolId) -> Option<BuiltinThunkFn> {
+    let index = id.index().checked_sub(BUILTINS.len())?;
+    THUNK_BUILTINS.get(index).map(|&(_, f)| f)
 }
 
 fn eval_eval(args: &[Expr], env: &Env) -> EvalResult<Expr> {
@@ -173,23 +284,14 @@ fn eval_eval_local(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     super::eval(&expr, env)
 }
 
-fn eval_arithmetic(op: impl FnOnce(i64, i64) -> i64, args: &[Expr], env: &Env) -> EvalResult<Expr> {
-    let (a, b) = eval_number_args(args, env)?;
-    let res = op(a, b);
-
-    Ok(Expr::Int(res))
-}
-
-fn eval_cmp(op: impl FnOnce(i64, i64) -> bool, args: &[Expr], env: &Env) -> EvalResult<Expr> {
-    let (a, b) = eval_number_args(args, env)?;
-    let res = op(a, b);
-
-    Ok(Expr::Bool(res))
-}
-
 fn eval_eq(args: &[Expr], env: &Env) -> EvalResult<Expr> {
-    let (a, b) = eval_2(args, env)?;
-    Ok(Expr::Bool(a.lenient_eq(&b)))
+    let args = eval_args(args, env)?;
+    if args.is_empty() {
+        return Err(EvalError::InvalidArgumentCount(vec![]));
+    }
+    Ok(Expr::Bool(
+        args.windows(2).all(|pair| pair[0].lenient_eq(&pair[1])),
+    ))
 }
 
 fn eval_time_ms(args: &[Expr], _env: &Env) -> EvalResult<Expr> {
@@ -35,10 +35,10 @@ pub(super) fn eval_swap(args: &[Expr], env: &Env) -> EvalResult<Expr> {
             *atom = atom_ref.borrow().clone();
             atom_ref
         }
-        _ => return Err(EvalError::InvalidArgumentCount),
+        _ => return Err(EvalError::InvalidArgumentCount(vec![])),
     };
 
-    let value = eval::eval(&Expr::List(args), env)?;
+    let value = eval::eval(&Expr::List(args.into()), env)?;
     *atom_ref.borrow_mut() = value.clone();
     Ok(value)
 }
@@ -1,7 +1,9 @@
 use std::rc::Rc;
 
 use crate::{
-    ast::Function,
+    ast::{pattern::Pattern, Function, FunctionArity},
+    environment::gc,
+    eval::destructure::bind,
     eval::Thunk::{self, Evaluated, Unevaluated},
 };
 
@@ -22,44 +24,96 @@ pub(super) fn eval_do(args: &[Expr], env: &Env) -> EvalResult<Thunk> {
 }
 
 pub(super) fn eval_fn(args: &[Expr], env: &Env) -> EvalResult<Expr> {
-    let [bindings, expr] = args_n(args)?;
-    let bindings = as_type(bindings, Expr::as_list_like)?;
-
-    let mut bindings = bindings
-        .iter()
-        .map(|b| as_type(b, Expr::as_symbol))
-        .collect::<EvalResult<Vec<_>>>()?;
-
-    let varargs = bindings.iter().filter(|&&b| b == "&").count();
-    let varargs = match varargs {
-        0 => None,
-        1 => {
-            let vararg_name = bindings.pop().unwrap();
-            let _sigil = bindings
-                .pop()
-                .filter(|&b| b == "&")
-                .ok_or(EvalError::InvalidVarargs)?;
-            Some(vararg_name.to_owned())
-        }
-        _ => return Err(EvalError::InvalidVarargs),
-    };
+    Ok(make_closure(parse_fn(args)?.into(), env))
+}
 
-    let bindings = bindings.into_iter().map(<str>::to_owned).collect();
-    let expr = Rc::new(expr.clone());
+/// Parses the arguments of `fn*` into the function's arities.
+pub(crate) fn parse_fn(args: &[Expr]) -> EvalResult<Vec<FunctionArity>> {
+    let arities = if is_multi_arity(args) {
+        let arities = args
+            .iter()
+            .map(|clause| parse_arity(as_type!(clause => Expr::List)?))
+            .collect::<EvalResult<Vec<_>>>()?;
+        check_arities(&arities)?;
+        arities
+    } else {
+        vec![parse_arity(args)?]
+    };
+    Ok(arities)
+}
 
-    Ok(Expr::Function(Function {
-        bindings,
-        varargs,
-        expr,
+/// Creates a function closing over `env`.
+pub(crate) fn make_closure(arities: Rc<[FunctionArity]>, env: &Env) -> Expr {
+    gc::track_env(env);
+    Expr::Function(Function {
+        name: None,
+        arities,
         closure: env.clone(),
         is_macro: false,
-    }))
+    })
+}
+
+/// `(fn* ([x] ...) ([x y] ...))`, every argument is a list starting with a parameter vector.
+///
+/// That makes `(fn* ([a b]))` one arity taking `a` and `b` without a body, rather than a
+/// parameter list destructuring its first argument. A list of parameters starting with a
+/// vector only reads as one when a body follows, as in `(fn* ([a b]) a)`.
+fn is_multi_arity(args: &[Expr]) -> bool {
+    !args.is_empty()
+        && args.iter().all(
+            |clause| matches!(clause, Expr::List(l) if matches!(l.first(), Some(Expr::Vector(_)))),
+        )
+}
+
+/// Parses parameters followed by a body, which is `nil` when left out.
+fn parse_arity(clause: &[Expr]) -> EvalResult<FunctionArity> {
+    let (params, expr) = match clause {
+        [params] => (params, &Expr::NIL),
+        [params, expr] => (params, expr),
+        _ => return Err(EvalError::InvalidArgumentCount(vec![])),
+    };
+    let (bindings, varargs) = Pattern::parse_params(params)?;
+    Ok(FunctionArity::new(
+        bindings,
+        varargs.map(|varargs| *varargs),
+        Rc::new(expr.clone()),
+    ))
+}
+
+fn check_arities(arities: &[FunctionArity]) -> EvalResult<()> {
+    let invalid = |reason: String| Err(EvalError::InvalidFunction(reason));
+
+    let mut variadic = arities.iter().filter(|a| a.varargs.is_some());
+    let variadic_min = variadic.next().map(|a| a.bindings.len());
+    if variadic.next().is_some() {
+        return invalid("can't have more than one variadic arity".to_owned());
+    }
+
+    for (i, arity) in arities.iter().enumerate() {
+        let n = arity.bindings.len();
+        if arity.varargs.is_some() {
+            continue;
+        }
+        if arities[..i]
+            .iter()
+            .any(|a| a.varargs.is_none() && a.bindings.len() == n)
+        {
+            return invalid(format!("can't have two arities with {n} parameters"));
+        }
+        if variadic_min.is_some_and(|min| n > min) {
+            return invalid(format!(
+                "fixed arity with {n} parameters has more than the variadic one"
+            ));
+        }
+    }
+
+    Ok(())
 }
 
 pub(super) fn eval_if(args: &[Expr], env: &Env) -> EvalResult<Thunk> {
     let [cond, success, failure] = match args_n(args) {
         Ok([c, s, f]) => [c, s, f],
-        Err(EvalError::InvalidArgumentCount) => {
+        Err(EvalError::InvalidArgumentCount(_)) => {
             let [c, s] = args_n(args)?;
             [c, s, &Expr::NIL]
         }
@@ -80,11 +134,17 @@ fn eval_def_inner(
     modify: impl FnOnce(Expr) -> EvalResult<Expr>,
 ) -> EvalResult<Expr> {
     let [key, val] = args_n(args)?;
-    let key = key
-        .as_symbol()
-        .ok_or_else(|| EvalError::InvalidVariableName(key.to_string()))?;
+    let Expr::Symbol(key) = key else {
+        return Err(EvalError::InvalidVariableName(key.to_string()));
+    };
 
-    let val = super::eval(val, env)?;
+    let val = match super::eval(val, env)? {
+        Expr::Function(f) if f.name.is_none() => Expr::Function(Function {
+            name: Some(key.name().into()),
+            ..f
+        }),
+        val => val,
+    };
     let val = modify(val)?;
     env.set(key, val.clone());
 
@@ -92,6 +152,14 @@ fn eval_def_inner(
 }
 
 pub(super) fn eval_def(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    if let [pattern @ (Expr::List(_) | Expr::Vector(_) | Expr::Map(_)), val] = args {
+        let pattern = Pattern::parse(pattern)?;
+        let val = super::eval(val, env)?;
+        env.mark_extended();
+        bind(&pattern, val.clone(), env)?;
+        return Ok(val);
+    }
+
     eval_def_inner(args, env, Ok)
 }
 
@@ -109,22 +177,17 @@ pub(super) fn eval_def_macro(args: &[Expr], env: &Env) -> EvalResult<Expr> {
 pub(super) fn eval_let(args: &[Expr], env: &Env) -> EvalResult<Thunk> {
     let [vars, expr] = args_n(args)?;
 
-    let vars = match vars {
-        Expr::List(l) if l.len() % 2 == 0 => l,
-        Expr::Vector(l) if l.len() % 2 == 0 => l,
+    let vars = match vars.as_list_like() {
+        Some(l) if l.len() % 2 == 0 => l.to_slice(),
         _ => return Err(EvalError::InvalidLetVariables),
     };
 
     let let_env = Environment::with_parent(env.clone());
 
     for c in vars.chunks_exact(2) {
-        let name = &c[0];
-        let val = &c[1];
-
-        let name = name.as_symbol().ok_or(EvalError::InvalidLetVariables)?;
-
-        let val = super::eval(val, &let_env)?;
-        let_env.set(name, val);
+        let pattern = Pattern::parse(&c[0])?;
+        let val = super::eval(&c[1], &let_env)?;
+        bind(&pattern, val, &let_env)?;
     }
 
     Ok(Unevaluated(Rc::new(expr.clone()), let_env))
@@ -142,25 +205,30 @@ pub(super) fn eval_try(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let invalid = || EvalError::InvalidCatchBlock;
     let [catch, catch_var, catch_expr] = args_n(catch).map_err(|_| invalid())?;
     let catch = as_type(catch, Expr::as_symbol).map_err(|_| invalid())?;
-    let catch_var = as_type(catch_var, Expr::as_symbol).map_err(|_| invalid())?;
+    let Expr::Symbol(catch_var) = catch_var else {
+        return Err(invalid());
+    };
     if catch != "catch*" {
         return Err(invalid());
     }
 
     let exc = match super::eval(expr, env) {
-        Err(EvalError::Exception(exc)) => exc,
+        Err(err) => err.into_exception()?,
         res => return res,
     };
 
     let catch_func = Function {
-        bindings: vec![catch_var.to_owned()],
-        varargs: None,
-        expr: Rc::new(catch_expr.clone()),
+        name: None,
+        arities: Rc::new([FunctionArity::new(
+            vec![Pattern::Symbol(catch_var.id())],
+            None,
+            Rc::new(catch_expr.clone()),
+        )]),
         closure: env.clone(),
         is_macro: false,
     };
     super::eval(
-        &Expr::List(vec![Expr::Function(catch_func), make_quote(exc)]),
+        &Expr::List(vec![Expr::Function(catch_func), make_quote(exc)].into()),
         env,
     )
 }
@@ -169,3 +237,143 @@ pub(super) fn eval_throw(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let expr = eval_1(args, env)?;
     Err(EvalError::Exception(expr))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::eval_fn;
+    use crate::{
+        ast::{Arity, Expr},
+        environment::Environment,
+        eval::{self, check, vm, EvalError},
+        parser::parse,
+    };
+
+    fn make_fn(src: &str) -> Result<Expr, EvalError> {
+        let Expr::List(args) = parse(src).unwrap() else {
+            panic!("expected a list");
+        };
+        eval_fn(&args, &Environment::new())
+    }
+
+    #[test]
+    fn multi_arity() {
+        let Ok(Expr::Function(f)) = make_fn("(([] 0) ([x y & r] r) ([x] x) ([x y] y))") else {
+            panic!("expected a function");
+        };
+        let arity = |n| f.find_arity(n).map(|a| a.arity());
+        assert_eq!(arity(0), Some(Arity::from(0)));
+        assert_eq!(arity(1), Some(Arity::from(1)));
+        assert_eq!(arity(2), Some(Arity::from(2)));
+        assert_eq!(arity(3), Some(Arity::from(2..)));
+
+        for invalid in [
+            "(([x & r] 1) ([& r] 2))",
+            "(([x] 1) ([y] 2))",
+            "(([x y] 1) ([x & r] 2))",
+        ] {
+            let err = make_fn(invalid).unwrap_err();
+            assert!(
+                matches!(err, EvalError::InvalidFunction(_)),
+                "{invalid}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn sequential_destructuring() {
+        check(&[
+            ("(let* [[a b & r] [1 2 3 4]] [a b r])", "[1 2 (3 4)]"),
+            ("(let* [[a & r] '(1)] [a r])", "[1 ()]"),
+            ("(let* [[a b :as all] [1 2 3]] [a b all])", "[1 2 [1 2 3]]"),
+            ("(let* [[a [b c]] [1 [2 3]]] [a b c])", "[1 2 3]"),
+            // missing items are nil
+            ("(let* [[a b] [1]] [a b])", "[1 nil]"),
+            ("(let* [[a b] nil] [a b])", "[nil nil]"),
+            ("(let* [[a & r :as all] []] [a r all])", "[nil () []]"),
+            (
+                "(let* [[a] 1] a)",
+                "error: type mismatch: expected list or vector, got int",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn associative_destructuring() {
+        check(&[
+            (
+                "(let* [{:keys [a b] :as m} {:a 1}] [a b m])",
+                "[1 nil {:a 1}]",
+            ),
+            (r#"(let* [{:strs [a]} {"a" 1}] a)"#, "1"),
+            ("(let* [{:syms [a]} {'a 1}] a)", "1"),
+            (
+                "(let* [{x :x [y z] :p} {:x 1 :p [2 3]}] [x y z])",
+                "[1 2 3]",
+            ),
+            ("(let* [{:keys [a] :or {a 5}} nil] a)", "5"),
+            ("(let* [{:keys [a] :or {a 5}} {:a false}] a)", "false"),
+            // defaults see names bound before the map, not the names in it
+            (
+                "(let* [x 5 {:keys [a b] :or {a x b 2}} {:b 3}] [a b])",
+                "[5 3]",
+            ),
+            ("(let* [[x {:keys [a] :or {a x}}] [7 {}]] a)", "7"),
+            (
+                "(let* [{:keys [a b] :or {a 1 b a}} {}] [a b])",
+                "error: exception occurred: 'a' not found",
+            ),
+            (
+                "(let* [{:keys [a]} [1]] a)",
+                "error: type mismatch: expected map, got vector",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn destructuring_params_and_definitions() {
+        check(&[
+            (
+                "((fn* [[a b] {:keys [c]}] [a b c]) [1 2] {:c 3})",
+                "[1 2 3]",
+            ),
+            ("((fn* [a & [b c]] [a b c]) 1 2)", "[1 2 nil]"),
+            (
+                "((fn* [[a]] a) 5)",
+                "error: type mismatch: expected list or vector, got int",
+            ),
+            ("(do (def! [p q] [1 2]) [p q])", "[1 2]"),
+            ("(do (def! {:keys [u]} {:u 9}) u)", "9"),
+            (
+                "(do (defmacro! swap-args (fn* [[f a b]] (list f b a))) (swap-args (- 1 10)))",
+                "9",
+            ),
+        ]);
+    }
+
+    #[test]
+    fn lists_starting_with_a_vector_are_arities() {
+        check(&[
+            ("((fn* ([a b])) 1 2)", "nil"),
+            ("((fn* ([a b] b)) 1 2)", "2"),
+            ("((fn* ([a b]) ([c] c)) 3)", "3"),
+            // a body that isn't a clause makes it a single parameter list
+            ("((fn* ([a b]) a) [1 2])", "1"),
+            ("((fn* ([a b] & [c]) [a b c]) [1 2] 3)", "[1 2 3]"),
+            ("((fn* (a b) b) 1 2)", "2"),
+        ]);
+    }
+
+    #[test]
+    fn empty_body_returns_nil() {
+        for engine in [eval::eval, vm::eval] {
+            let env = Environment::with_builtins();
+            let run = |src: &str| engine(&parse(src).unwrap(), &env).unwrap();
+            assert_eq!(run("((fn* [x]) 1)"), Expr::Nil);
+            assert_eq!(run("((fn* ([x])) 1)"), Expr::Nil);
+            assert_eq!(run("((fn* ([] 1) ([x])) 2)"), Expr::Nil);
+            assert_eq!(run("((fn* ([] 1) ([x])))"), Expr::Int(1));
+        }
+        assert!(make_fn("([x] 1 2)").is_err());
+        assert!(make_fn("(([x] 1 2))").is_err());
+    }
+}
@@ -6,19 +6,24 @@ pub(super) fn eval_map(args: &[Expr], env: &Env) -> EvalResult<Expr> {
 
     let results = list
         .into_iter()
-        .map(|elem| super::eval(&Expr::List(vec![f.clone(), make_quote(elem)]), env))
-        .collect::<EvalResult<_>>()?;
-    Ok(Expr::List(results))
+        .map(|elem| super::eval(&Expr::List(vec![f.clone(), make_quote(elem)].into()), env))
+        .collect::<EvalResult<Vec<_>>>()?;
+    limits::allocate(results.len())?;
+    Ok(Expr::List(results.into()))
 }
 
 pub(super) fn eval_apply(args: &[Expr], env: &Env) -> EvalResult<Expr> {
-    let (f, args) = args.split_first().ok_or(EvalError::InvalidArgumentCount)?;
-    let (list, args) = args.split_last().ok_or(EvalError::InvalidArgumentCount)?;
+    let (f, args) = args
+        .split_first()
+        .ok_or(EvalError::InvalidArgumentCount(vec![]))?;
+    let (list, args) = args
+        .split_last()
+        .ok_or(EvalError::InvalidArgumentCount(vec![]))?;
     let list = super::eval(list, env)?;
     let list = into_type(list, Expr::into_list_like)?;
     let mut f_args = vec![f.clone()];
     f_args.extend(args.iter().cloned());
     f_args.extend(list.into_iter().map(make_quote));
 
-    super::eval(&Expr::List(f_args), env)
+    super::eval(&Expr::List(f_args.into()), env)
 }
@@ -0,0 +1,498 @@
+use std::{fmt::Write, rc::Rc};
+
+use crate::ast::{
+    number::{format_float, BigInt},
+    Keyword, Map, MapKey,
+};
+
+use super::prelude::*;
+
+/// Deepest nesting of arrays and objects read or written, so that the recursion can't
+/// overflow the native stack.
+const MAX_NESTING: usize = 512;
+
+fn json_error(msg: impl std::fmt::Display) -> EvalError {
+    EvalError::Exception(Expr::String(msg.to_string()))
+}
+
+/// Evaluates the value argument and the optional options map, like `{:pretty true}`.
+fn eval_options(args: &[Expr], env: &Env) -> EvalResult<(Expr, Option<Rc<Map>>)> {
+    match args {
+        [value] => Ok((eval::eval(value, env)?, None)),
+        [value, options] => {
+            let (value, options) = (eval::eval(value, env)?, eval::eval(options, env)?);
+            let options = as_type!(&options => Expr::Map)?;
+            Ok((value, Some(Rc::clone(options))))
+        }
+        _ => Err(EvalError::InvalidArgumentCount(vec![])),
+    }
+}
+
+fn option_set(options: &Option<Rc<Map>>, name: &str) -> bool {
+    let value = options
+        .as_ref()
+        .and_then(|options| options.get(&MapKey::new(Expr::Keyword(Keyword::new(name)))));
+    !matches!(value, None | Some(Expr::Nil | Expr::Bool(false)))
+}
+
+/// `(json-parse s)`, `(json-parse s {:keywordize true})` to turn object keys into keywords.
+pub(super) fn eval_json_parse(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    let (s, options) = eval_options(args, env)?;
+    let s = as_type(&s, Expr::as_string)?;
+
+    let mut parser = JsonParser {
+        src: s,
+        pos: 0,
+        depth: 0,
+        keywordize: option_set(&options, "keywordize"),
+    };
+    parser.skip_whitespace();
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.peek() {
+        None => Ok(value),
+        Some(_) => Err(parser.error("trailing characters")),
+    }
+}
+
+/// `(json-stringify value)`, `(json-stringify value {:pretty true})` to indent the output.
+pub(super) fn eval_json_stringify(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    let (value, options) = eval_options(args, env)?;
+
+    let mut writer = JsonWriter {
+        out: String::new(),
+        pretty: option_set(&options, "pretty"),
+        depth: 0,
+    };
+    writer.value(&value)?;
+    limits::check_string(&writer.out)?;
+    Ok(Expr::String(writer.out))
+}
+
+struct JsonParser<'a> {
+    src: &'a str,
+    pos: usize,
+    /// Arrays and objects being parsed.
+    depth: usize,
+    keywordize: bool,
+}
+
+impl JsonParser<'_> {
+    fn error(&self, msg: &str) -> EvalError {
+        let before = &self.src[..self.pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+        json_error(format_args!("json-parse: {msg} at {line}:{column}"))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str) -> EvalResult<()> {
+        if self.src[self.pos..].starts_with(literal) {
+            self.pos += literal.len();
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected `{literal}`")))
+        }
+    }
+
+    fn value(&mut self) -> EvalResult<Expr> {
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Expr::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b't') => self.expect("true").map(|_| Expr::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Expr::Bool(false)),
+            Some(b'n') => self.expect("null").map(|_| Expr::Nil),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    /// Parses the items between `open` and `close`, separated by commas.
+    fn delimited(
+        &mut self,
+        open: &str,
+        close: u8,
+        mut item: impl FnMut(&mut Self) -> EvalResult<()>,
+    ) -> EvalResult<()> {
+        if self.depth == MAX_NESTING {
+            return Err(self.error(&format!("nesting deeper than {MAX_NESTING}")));
+        }
+        self.expect(open)?;
+        self.skip_whitespace();
+        if self.peek() == Some(close) {
+            self.pos += 1;
+            return Ok(());
+        }
+
+        self.depth += 1;
+        loop {
+            self.skip_whitespace();
+            limits::allocate(1)?;
+            item(self)?;
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(c) if c == close => {
+                    self.pos += 1;
+                    self.depth -= 1;
+                    return Ok(());
+                }
+                _ => return Err(self.error("expected `,` or closing bracket")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> EvalResult<Expr> {
+        let mut items = vec![];
+        self.delimited("[", b']', |p| {
+            items.push(p.value()?);
+            Ok(())
+        })?;
+        Ok(Expr::Vector(items.into()))
+    }
+
+    fn object(&mut self) -> EvalResult<Expr> {
+        let mut map = Map::default();
+        self.delimited("{", b'}', |p| {
+            if p.peek() != Some(b'"') {
+                return Err(p.error("expected string key"));
+            }
+            let key = p.string()?;
+            let key = match p.keywordize {
+                true => Expr::Keyword(Keyword::new(&key)),
+                false => Expr::String(key),
+            };
+            p.skip_whitespace();
+            p.expect(":")?;
+            p.skip_whitespace();
+            map.insert(MapKey::new(key), p.value()?);
+            Ok(())
+        })?;
+        Ok(Expr::Map(Rc::new(map)))
+    }
+
+    fn number(&mut self) -> EvalResult<Expr> {
+        let start = self.pos;
+        let digits = |p: &mut Self| {
+            let start = p.pos;
+            while let Some(b'0'..=b'9') = p.peek() {
+                p.pos += 1;
+            }
+            p.pos - start
+        };
+
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        let int_start = self.pos;
+        match digits(self) {
+            0 => return Err(self.error("expected digits")),
+            n if n > 1 && self.src.as_bytes()[int_start] == b'0' => {
+                return Err(self.error("leading zeros are not allowed"))
+            }
+            _ => {}
+        }
+
+        let mut is_float = false;
+        if self.peek() == Some(b'.') {
+            self.pos += 1;
+            is_float = true;
+            if digits(self) == 0 {
+                return Err(self.error("expected digits after decimal point"));
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.pos += 1;
+            is_float = true;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.pos += 1;
+            }
+            if digits(self) == 0 {
+                return Err(self.error("expected digits in exponent"));
+            }
+        }
+
+        let literal = &self.src[start..self.pos];
+        if is_float {
+            // overflows to infinity, which JSON can't represent
+            return match literal.parse::<f64>() {
+                Ok(f) if f.is_finite() => Ok(Expr::Float(f)),
+                Ok(_) => Err(self.error("number out of range")),
+                Err(_) => Err(self.error("invalid number")),
+            };
+        }
+        match literal.parse() {
+            Ok(i) => Ok(Expr::Int(i)),
+            Err(_) => literal
+                .parse::<BigInt>()
+                .map(|b| Expr::BigInt(Rc::new(b)))
+                .map_err(|_| self.error("invalid number")),
+        }
+    }
+
+    fn string(&mut self) -> EvalResult<String> {
+        self.expect("\"")?;
+        let mut s = String::new();
+        loop {
+            let rest = &self.src[self.pos..];
+            let end = match rest.find(['"', '\\']) {
+                Some(end) => end,
+                None => return Err(self.error("unterminated string")),
+            };
+            let chunk = &rest[..end];
+            if chunk.bytes().any(|b| b < 0x20) {
+                return Err(self.error("unescaped control character in string"));
+            }
+            s.push_str(chunk);
+            self.pos += end + 1;
+
+            if rest.as_bytes()[end] == b'"' {
+                return Ok(s);
+            }
+
+            let escaped = match self.peek() {
+                Some(b'"') => '"',
+                Some(b'\\') => '\\',
+                Some(b'/') => '/',
+                Some(b'b') => '\u{8}',
+                Some(b'f') => '\u{c}',
+                Some(b'n') => '\n',
+                Some(b'r') => '\r',
+                Some(b't') => '\t',
+                Some(b'u') => {
+                    self.pos += 1;
+                    s.push(self.unicode_escape()?);
+                    continue;
+                }
+                _ => return Err(self.error("invalid escape sequence")),
+            };
+            self.pos += 1;
+            s.push(escaped);
+        }
+    }
+
+    fn hex4(&mut self) -> EvalResult<u16> {
+        let hex = self
+            .src
+            .get(self.pos..self.pos + 4)
+            .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| self.error("expected 4 hex digits"))?;
+        self.pos += 4;
+        Ok(u16::from_str_radix(hex, 16).expect("checked hex digits"))
+    }
+
+    /// Decodes the `XXXX` after `\u`, combining UTF-16 surrogate pairs.
+    fn unicode_escape(&mut self) -> EvalResult<char> {
+        let first = self.hex4()?;
+        let units = match first {
+            0xD800..=0xDBFF => {
+                self.expect("\\u")
+                    .map_err(|_| self.error("unpaired surrogate"))?;
+                vec![first, self.hex4()?]
+            }
+            _ => vec![first],
+        };
+        char::decode_utf16(units)
+            .next()
+            .and_then(Result::ok)
+            .ok_or_else(|| self.error("invalid unicode escape"))
+    }
+}
+
+struct JsonWriter {
+    out: String,
+    pretty: bool,
+    depth: usize,
+}
+
+impl JsonWriter {
+    fn newline(&mut self) {
+        if self.pretty {
+            self.out.push('\n');
+            self.out.extend(std::iter::repeat_n("  ", self.depth));
+        }
+    }
+
+    /// Writes the items between `open` and `close`, one per line when pretty-printing.
+    fn delimited<T>(
+        &mut self,
+        [open, close]: [char; 2],
+        items: impl ExactSizeIterator<Item = T>,
+        mut item: impl FnMut(&mut Self, T) -> EvalResult<()>,
+    ) -> EvalResult<()> {
+        if self.depth == MAX_NESTING {
+            return Err(json_error(format_args!(
+                "json-stringify: nesting deeper than {MAX_NESTING}"
+            )));
+        }
+        self.out.push(open);
+        if items.len() == 0 {
+            self.out.push(close);
+            return Ok(());
+        }
+
+        self.depth += 1;
+        for (i, it) in items.enumerate() {
+            if i > 0 {
+                self.out.push(',');
+            }
+            self.newline();
+            item(self, it)?;
+        }
+        self.depth -= 1;
+        self.newline();
+        self.out.push(close);
+        Ok(())
+    }
+
+    fn entry(&mut self, (k, v): (&Expr, &Expr)) -> EvalResult<()> {
+        match k {
+            Expr::String(s) => self.string(s),
+            Expr::Keyword(kw) => self.string(kw.name()),
+            Expr::Symbol(s) => self.string(s),
+            k => {
+                return Err(json_error(format_args!(
+                    "json-stringify: cannot use {} as an object key",
+                    k.type_name()
+                )))
+            }
+        }
+        self.out.push(':');
+        if self.pretty {
+            self.out.push(' ');
+        }
+        self.value(v)
+    }
+
+    fn value(&mut self, expr: &Expr) -> EvalResult<()> {
+        // checked as the output grows, so it can't get much longer than allowed
+        limits::check_string(&self.out)?;
+        match expr {
+            Expr::Nil => self.out.push_str("null"),
+            Expr::Bool(b) => write!(self.out, "{b}").unwrap(),
+            Expr::Int(i) => write!(self.out, "{i}").unwrap(),
+            Expr::BigInt(b) => write!(self.out, "{b}").unwrap(),
+            Expr::Ratio(r) => self.float(r.to_f64())?,
+            Expr::Float(f) => self.float(*f)?,
+            Expr::String(s) => self.string(s),
+            Expr::Keyword(kw) => self.string(kw.name()),
+            Expr::Symbol(s) => self.string(s),
+            Expr::List(items) => self.delimited(['[', ']'], items.iter(), Self::value)?,
+            Expr::Vector(items) => self.delimited(['[', ']'], items.iter(), Self::value)?,
+            Expr::Set(set) => {
+                self.delimited(['[', ']'], set.iter(), |w, k| w.value(k.as_expr()))?
+            }
+            Expr::Map(map) => self.delimited(
+                ['{', '}'],
+                map.iter().map(|(k, v)| (k.as_expr(), v)),
+                Self::entry,
+            )?,
+            Expr::SortedMap(map) => self.delimited(['{', '}'], map.iter(), Self::entry)?,
+            Expr::WithMeta { expr, .. } => self.value(expr)?,
+            expr => {
+                return Err(json_error(format_args!(
+                    "json-stringify: cannot convert {} to JSON",
+                    expr.type_name()
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn float(&mut self, f: f64) -> EvalResult<()> {
+        if !f.is_finite() {
+            return Err(json_error(format_args!(
+                "json-stringify: {} is not valid JSON",
+                format_float(f)
+            )));
+        }
+        self.out.push_str(&format_float(f));
+        Ok(())
+    }
+
+    fn string(&mut self, s: &str) {
+        self.out.push('"');
+        for c in s.chars() {
+            match c {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\r' => self.out.push_str("\\r"),
+                '\t' => self.out.push_str("\\t"),
+                '\u{8}' => self.out.push_str("\\b"),
+                '\u{c}' => self.out.push_str("\\f"),
+                c if c < ' ' => write!(self.out, "\\u{:04x}", c as u32).unwrap(),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{eval_json_parse, eval_json_stringify};
+    use crate::{ast::Expr, environment::Environment};
+
+    #[test]
+    fn string_round_trip() {
+        let env = Environment::new();
+        let cases = [
+            (r#""plain""#, "plain"),
+            (r#""\"\\\/\b\f\n\r\t""#, "\"\\/\u{8}\u{c}\n\r\t"),
+            (r#""é中😀""#, "é中😀"),
+            (r#""\u0000\u001f""#, "\0\u{1f}"),
+        ];
+
+        for (json, expected) in cases {
+            let parsed = eval_json_parse(&[Expr::String(json.into())], &env).unwrap();
+            assert_eq!(parsed, Expr::String(expected.into()), "{json}");
+
+            let written = eval_json_stringify(std::slice::from_ref(&parsed), &env).unwrap();
+            let reparsed = eval_json_parse(&[written], &env).unwrap();
+            assert_eq!(reparsed, parsed, "{json}");
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let env = Environment::new();
+        let parse = |json: String| {
+            eval_json_parse(&[Expr::String(json)], &env)
+                .map_err(|e| e.into_exception().unwrap().to_string())
+        };
+
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        let value = parse(nested(512)).unwrap();
+        assert!(eval_json_stringify(std::slice::from_ref(&value), &env).is_ok());
+        for depth in [513, 100_000] {
+            assert_eq!(
+                parse(nested(depth)),
+                Err("json-parse: nesting deeper than 512 at 1:513".to_owned())
+            );
+        }
+        let deeper = Expr::Vector(vec![value].into());
+        let err = eval_json_stringify(&[deeper], &env).unwrap_err();
+        assert_eq!(
+            err.into_exception().unwrap().to_string(),
+            "json-stringify: nesting deeper than 512"
+        );
+
+        assert_eq!(
+            parse("1e400".to_owned()),
+            Err("json-parse: number out of range at 1:6".to_owned())
+        );
+        assert_eq!(parse("1e-400".to_owned()), Ok(Expr::Float(0.0)));
+    }
+}
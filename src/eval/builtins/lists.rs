@@ -1,19 +1,33 @@
+use std::rc::Rc;
+
 use itertools::Itertools;
 
+use crate::ast::{List, MapKey, Set};
+
 use super::prelude::*;
 
 pub(super) fn eval_list(args: &[Expr], env: &Env) -> EvalResult<Expr> {
-    eval_args(args, env).map(Expr::List)
+    let items = eval_args(args, env)?;
+    limits::allocate(items.len())?;
+    Ok(Expr::List(items.into()))
 }
 
 pub(super) fn eval_vec(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let arg = eval_1(args, env)?;
-    let list = into_list_like(arg)?;
-    Ok(Expr::Vector(list))
+    match arg.into_no_meta() {
+        v @ Expr::Vector(_) => Ok(v),
+        arg => {
+            let items = into_list_like(arg)?;
+            limits::allocate(items.len())?;
+            Ok(Expr::Vector(items.into()))
+        }
+    }
 }
 
 pub(super) fn eval_vector(args: &[Expr], env: &Env) -> EvalResult<Expr> {
-    eval_args(args, env).map(Expr::Vector)
+    let items = eval_args(args, env)?;
+    limits::allocate(items.len())?;
+    Ok(Expr::Vector(items.into()))
 }
 
 pub(super) fn eval_is_list(args: &[Expr], env: &Env) -> EvalResult<Expr> {
@@ -30,28 +44,37 @@ pub(super) fn eval_is_sequential(args: &[Expr], env: &Env) -> EvalResult<Expr> {
 
 pub(super) fn eval_is_empty(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let arg = eval_1(args, env)?;
-    Ok(Expr::Bool(
-        arg.as_no_meta()
-            .as_list_like()
-            .map(|l| l.is_empty())
-            .unwrap_or(false),
-    ))
+    Ok(Expr::Bool(match arg.as_no_meta() {
+        Expr::Set(s) => s.is_empty(),
+        Expr::Map(m) => m.is_empty(),
+        Expr::SortedMap(m) => m.is_empty(),
+        arg => arg.as_list_like().map(|l| l.is_empty()).unwrap_or(false),
+    }))
 }
 
 pub(super) fn eval_count(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let arg = eval_1(args, env)?;
-    Ok(Expr::Int(
-        arg.as_no_meta()
-            .as_list_like()
-            .map(|l| l.len() as i64)
-            .unwrap_or(0),
-    ))
+    Ok(Expr::Int(match arg.as_no_meta() {
+        Expr::Set(s) => s.len() as i64,
+        Expr::Map(m) => m.len() as i64,
+        Expr::SortedMap(m) => m.len() as i64,
+        arg => arg.as_list_like().map(|l| l.len() as i64).unwrap_or(0),
+    }))
 }
 
 pub(super) fn eval_cons(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let (head, tail) = eval_2(args, env)?;
-    let tail = into_list_like(tail)?;
-    Ok(Expr::List(std::iter::once(head).chain(tail).collect()))
+    limits::allocate(1)?;
+    Ok(Expr::List(into_list(tail)?.cons(head).into()))
+}
+
+/// The items of a list or vector as a list, sharing them.
+fn into_list(arg: Expr) -> EvalResult<List> {
+    into_type(arg, |arg| match arg {
+        Expr::List(l) => Ok(l),
+        Expr::Vector(v) => Ok(v.into()),
+        arg => Err(arg),
+    })
 }
 
 pub(super) fn eval_concat(args: &[Expr], env: &Env) -> EvalResult<Expr> {
@@ -62,8 +85,9 @@ pub(super) fn eval_concat(args: &[Expr], env: &Env) -> EvalResult<Expr> {
         .map(into_list_like)
         .flatten_ok()
         .collect::<EvalResult<Vec<_>>>()?;
+    limits::allocate(list.len())?;
 
-    Ok(Expr::List(list))
+    Ok(Expr::List(list.into()))
 }
 
 pub(super) fn eval_first(args: &[Expr], env: &Env) -> EvalResult<Expr> {
@@ -73,35 +97,30 @@ pub(super) fn eval_first(args: &[Expr], env: &Env) -> EvalResult<Expr> {
         return Ok(Expr::Nil);
     }
 
-    let list = into_list_like(list)?;
-    Ok(list.into_iter().next().unwrap_or(Expr::Nil))
+    let list = as_type(&list, Expr::as_list_like)?;
+    Ok(list.get(0).cloned().unwrap_or(Expr::Nil))
 }
 
 pub(super) fn eval_rest(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let list = eval_1(args, env)?;
 
     if let Expr::Nil = list {
-        return Ok(Expr::List(vec![]));
-    }
-
-    let mut list = into_list_like(list)?;
-    if !list.is_empty() {
-        list.remove(0);
+        return Ok(Expr::List(List::new()));
     }
 
-    Ok(Expr::List(list))
+    Ok(Expr::List(into_list(list)?.rest().into()))
 }
 
 pub(super) fn eval_nth(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let (list, idx) = eval_2(args, env)?;
 
     let idx = as_type(&idx, Expr::as_int)?;
-    let list = into_list_like(list)?;
+    let list = as_type(&list, Expr::as_list_like)?;
     let len = list.len();
 
     idx.try_into()
         .ok()
-        .and_then(|idx| list.into_iter().nth(idx))
+        .and_then(|idx| list.get(idx).cloned())
         .ok_or_else(|| {
             EvalError::Exception(Expr::String(format!(
                 "index {idx} out of range for len {}",
@@ -112,31 +131,61 @@ pub(super) fn eval_nth(args: &[Expr], env: &Env) -> EvalResult<Expr> {
 
 pub(super) fn eval_conj(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let args = eval_args(args, env)?;
-    let (seq, args) = args.split_first().ok_or(EvalError::InvalidArgumentCount)?;
+    let (seq, args) = args
+        .split_first()
+        .ok_or(EvalError::InvalidArgumentCount(vec![]))?;
+    limits::allocate(args.len())?;
 
     match seq.as_no_meta() {
         Expr::List(l) => Ok(Expr::List(
             args.iter()
                 .cloned()
-                .rev()
-                .chain(l.iter().cloned())
-                .collect(),
-        )),
-        Expr::Vector(v) => Ok(Expr::Vector(
-            v.iter().cloned().chain(args.iter().cloned()).collect(),
+                .fold(l.clone().into_inner(), |l, item| l.cons(item))
+                .into(),
         )),
+        Expr::Vector(v) => {
+            let mut v = v.clone();
+            v.extend(args.iter().cloned());
+            Ok(Expr::Vector(v))
+        }
+        Expr::Set(s) => {
+            let mut s = Set::clone(s);
+            for item in args {
+                s.insert(item.to_map_key());
+            }
+            Ok(Expr::Set(Rc::new(s)))
+        }
         seq => Err(EvalError::InvalidArgumentTypes(vec![seq.to_string()])),
     }
 }
 
 pub(super) fn eval_seq(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let arg = eval_1(args, env)?;
+    match arg.as_no_meta() {
+        Expr::Set(s) => limits::allocate(s.len())?,
+        Expr::Map(m) => limits::allocate(m.len())?,
+        Expr::SortedMap(m) => limits::allocate(m.len())?,
+        Expr::String(s) => limits::allocate(s.chars().count())?,
+        _ => {}
+    }
     match arg.into_no_meta() {
         Expr::Nil => Ok(Expr::Nil),
         Expr::List(l) if l.is_empty() => Ok(Expr::Nil),
         l @ Expr::List(_) => Ok(l),
         Expr::Vector(v) if v.is_empty() => Ok(Expr::Nil),
-        Expr::Vector(v) => Ok(Expr::List(v)),
+        Expr::Vector(v) => Ok(Expr::List(v.into())),
+        Expr::Set(s) if s.is_empty() => Ok(Expr::Nil),
+        Expr::Set(s) => Ok(Expr::List(s.iter().map(MapKey::to_expr).collect())),
+        Expr::Map(m) if m.is_empty() => Ok(Expr::Nil),
+        Expr::Map(m) => Ok(Expr::List(
+            m.iter()
+                .map(|(k, v)| entry(k.to_expr(), v.clone()))
+                .collect(),
+        )),
+        Expr::SortedMap(m) if m.is_empty() => Ok(Expr::Nil),
+        Expr::SortedMap(m) => Ok(Expr::List(
+            m.iter().map(|(k, v)| entry(k.clone(), v.clone())).collect(),
+        )),
         Expr::String(s) if s.is_empty() => Ok(Expr::Nil),
         Expr::String(s) => Ok(Expr::List(
             s.chars().map(|c| c.to_string()).map(Expr::String).collect(),
@@ -144,3 +193,31 @@ pub(super) fn eval_seq(args: &[Expr], env: &Env) -> EvalResult<Expr> {
         arg => Err(EvalError::InvalidArgumentTypes(vec![arg.to_string()])),
     }
 }
+
+/// A map entry as a `[key value]` vector.
+fn entry(key: Expr, value: Expr) -> Expr {
+    Expr::Vector([key, value].into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::check;
+
+    #[test]
+    fn maps_are_collections() {
+        check(&[
+            ("(count {:a 1 :b 2})", "2"),
+            ("(count (sorted-map 1 2))", "1"),
+            ("(count (sorted-map))", "0"),
+            ("(empty? {})", "true"),
+            ("(empty? {:a 1})", "false"),
+            ("(empty? (sorted-map))", "true"),
+            ("(empty? (sorted-map 1 2))", "false"),
+            ("(seq {})", "nil"),
+            ("(seq {:a 1})", "([:a 1])"),
+            ("(seq (sorted-map))", "nil"),
+            ("(seq (sorted-map 3 4 1 2))", "([1 2] [3 4])"),
+            ("(map first (seq (sorted-map :b 2 :a 1)))", "(:a :b)"),
+        ]);
+    }
+}
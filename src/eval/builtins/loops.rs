@@ -0,0 +1,218 @@
+use std::rc::Rc;
+
+use crate::{
+    ast::{interner::SymbolId, pattern::Pattern},
+    environment::Loop,
+    eval::destructure::bind,
+    eval::Thunk::{self, Unevaluated},
+};
+
+use super::{prelude::*, quoting::eval_quasiquote_expand};
+
+/// `(loop* [name value ...] body)` binds names like `let*`, and `(recur value ...)`
+/// in tail position of `body` evaluates it again with the names bound to new values.
+pub(super) fn eval_loop(args: &[Expr], env: &Env) -> EvalResult<Thunk> {
+    let (target, values) = parse_loop(args, env)?;
+    let target = Rc::new(target);
+    let loop_env = Environment::for_loop(env.clone(), Rc::clone(&target));
+    for (pattern, value) in target.bindings.iter().zip(&values) {
+        let value = super::eval(value, &loop_env)?;
+        bind(pattern, value, &loop_env)?;
+    }
+
+    Ok(Unevaluated(Rc::clone(&target.body), loop_env))
+}
+
+/// Parses the arguments of `loop*` into the loop and the expressions of the initial values
+/// of its bindings, checking that `recur` only appears in tail position of the body.
+pub(crate) fn parse_loop(args: &[Expr], env: &Env) -> EvalResult<(Loop, Vec<Expr>)> {
+    let [vars, body] = args_n(args)?;
+
+    let vars = match vars.as_list_like() {
+        Some(l) if l.len() % 2 == 0 => l.to_slice(),
+        _ => return Err(EvalError::InvalidLetVariables),
+    };
+
+    let bindings = vars
+        .iter()
+        .step_by(2)
+        .map(Pattern::parse)
+        .collect::<EvalResult<Vec<_>>>()?;
+    not_tail(bound_values(&vars), Position::Tail, env)?;
+    check_tail(body, Position::Tail, env)?;
+
+    let target = Loop {
+        bindings,
+        body: Rc::new(body.clone()),
+    };
+    Ok((target, bound_values(&vars).cloned().collect()))
+}
+
+/// Starts the enclosing `loop*` again, in a fresh scope so closures created
+/// by earlier iterations keep their values.
+pub(super) fn eval_recur(args: &[Expr], env: &Env) -> EvalResult<Thunk> {
+    let (target, outer) = env
+        .enclosing_loop()
+        .ok_or_else(|| EvalError::InvalidRecur("used outside of loop*".to_owned()))?;
+    if args.len() != target.bindings.len() {
+        return Err(EvalError::InvalidArgumentCount(vec![target
+            .bindings
+            .len()
+            .into()]));
+    }
+
+    let values = eval_args(args, env)?;
+    let loop_env = Environment::for_loop(outer, Rc::clone(&target));
+    for (pattern, value) in target.bindings.iter().zip(values) {
+        bind(pattern, value, &loop_env)?;
+    }
+
+    Ok(Unevaluated(Rc::clone(&target.body), loop_env))
+}
+
+/// Where an expression is in the body of the `loop*` being checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Position {
+    Tail,
+    NotTail,
+    /// Inside a `fn*`, where `recur` can't reach the loop wherever it is.
+    InFunction,
+}
+
+impl Position {
+    /// Position of the operands of an expression in this position.
+    fn operand(self) -> Self {
+        match self {
+            Position::InFunction => Position::InFunction,
+            _ => Position::NotTail,
+        }
+    }
+}
+
+/// Checks that `recur` only appears in tail position of `expr`, which is itself
+/// at `position` in the loop.
+///
+/// Macro calls are checked after expansion, and bodies of nested `loop*` forms are
+/// left for those loops to check, since their `recur` starts the inner loop.
+fn check_tail(expr: &Expr, position: Position, env: &Env) -> EvalResult<()> {
+    let list = match expr.as_no_meta() {
+        Expr::List(list) => list,
+        Expr::Vector(v) => return not_tail(v.iter(), position, env),
+        Expr::Map(m) => {
+            return not_tail(m.iter().flat_map(|(k, v)| [k.as_expr(), v]), position, env)
+        }
+        Expr::Set(s) => return not_tail(s.iter().map(|k| k.as_expr()), position, env),
+        _ => return Ok(()),
+    };
+    let Some((head, args)) = list.split_first() else {
+        return Ok(());
+    };
+
+    match (head.as_func_name().map(SymbolId::name), args) {
+        (Some("recur"), _) if position == Position::InFunction => Err(EvalError::InvalidRecur(
+            format!("{expr} is inside a fn* in loop*, it can't cross the function boundary"),
+        )),
+        (Some("recur"), _) if position == Position::NotTail => Err(EvalError::InvalidRecur(
+            format!("{expr} is not in tail position of loop*"),
+        )),
+        (Some("quote"), _) => Ok(()),
+        (Some("quasiquote"), _) => {
+            check_tail(&eval_quasiquote_expand(args, env)?, position.operand(), env)
+        }
+        (Some("fn*"), _) => not_tail(args, Position::InFunction, env),
+        (Some("do"), [init @ .., last]) => {
+            not_tail(init, position, env)?;
+            check_tail(last, position, env)
+        }
+        (Some("if"), [cond, branches @ ..]) => {
+            check_tail(cond, position.operand(), env)?;
+            branches
+                .iter()
+                .try_for_each(|branch| check_tail(branch, position, env))
+        }
+        (Some("let*"), [vars, body]) => {
+            check_bindings(vars, position, env)?;
+            check_tail(body, position, env)
+        }
+        (Some("loop*"), [vars, _]) => check_bindings(vars, position, env),
+        _ if eval::is_macro(expr, env) => match eval::eval_maybe_macro(expr, env, false) {
+            Ok(expanded) => check_tail(&expanded, position, env),
+            // reported when the call is evaluated, if it ever is
+            Err(_) => Ok(()),
+        },
+        _ => not_tail(list, position, env),
+    }
+}
+
+/// Checks operands of an expression at `position`.
+fn not_tail<'a>(
+    exprs: impl IntoIterator<Item = &'a Expr>,
+    position: Position,
+    env: &Env,
+) -> EvalResult<()> {
+    exprs
+        .into_iter()
+        .try_for_each(|expr| check_tail(expr, position.operand(), env))
+}
+
+fn check_bindings(vars: &Expr, position: Position, env: &Env) -> EvalResult<()> {
+    match vars.as_list_like() {
+        Some(vars) => not_tail(bound_values(&vars.to_slice()), position, env),
+        None => Ok(()),
+    }
+}
+
+/// Values in `let*`-style bindings, skipping the names.
+fn bound_values(vars: &[Expr]) -> impl Iterator<Item = &Expr> {
+    vars.iter().skip(1).step_by(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_tail, Position};
+    use crate::{environment::Environment, eval::EvalError, parser::parse};
+
+    #[test]
+    fn recur_in_tail_position() {
+        let env = Environment::with_builtins();
+        let check = |src: &str| check_tail(&parse(src).unwrap(), Position::Tail, &env);
+
+        for valid in [
+            "(recur 1)",
+            "(if c (recur 1) (do x (recur 2)))",
+            "(let* [a 1] (recur a))",
+            "(loop* [a 1] (+ 1 (recur a)))",
+            "(quote (+ 1 (recur)))",
+            "(fn* [] (loop* [a 1] (recur a)))",
+        ] {
+            assert!(check(valid).is_ok(), "{valid}");
+        }
+        for invalid in [
+            "(+ 1 (recur 1))",
+            "(if (recur) 1 2)",
+            "(do (recur 1) 2)",
+            "(let* [a (recur)] a)",
+            "(loop* [a (recur)] a)",
+            "[(recur)]",
+        ] {
+            let err = check(invalid).unwrap_err();
+            assert!(
+                matches!(&err, EvalError::InvalidRecur(msg) if msg.contains("not in tail position")),
+                "{invalid}: {err}"
+            );
+        }
+        for invalid in [
+            "(fn* [] (recur))",
+            "((fn* [x] (if x (recur 1) 2)) 1)",
+            "(fn* ([] 1) ([x] (recur x)))",
+            "(+ 1 (fn* [] (+ 1 (recur))))",
+            "(let* [f (fn* [] (recur))] (f))",
+        ] {
+            let err = check(invalid).unwrap_err();
+            assert!(
+                matches!(&err, EvalError::InvalidRecur(msg) if msg.contains("function boundary")),
+                "{invalid}: {err}"
+            );
+        }
+    }
+}
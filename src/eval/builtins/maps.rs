@@ -2,88 +2,129 @@ use std::rc::Rc;
 
 use itertools::Itertools;
 
-use crate::ast::{Map, MapKey};
+use crate::ast::{sorted_map::SortedMap, Map, MapKey};
 
-use super::prelude::*;
+use super::{prelude::*, sorted_maps::key_cmp};
 
 pub(super) fn eval_is_map(args: &[Expr], env: &Env) -> EvalResult<Expr> {
-    is_type!(args, env, Expr::Map(_))
+    is_type!(args, env, Expr::Map(_) | Expr::SortedMap(_))
 }
 
 pub(super) fn eval_hash_map(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let args = eval_args(args, env)?;
+    limits::allocate(args.len() / 2)?;
     list_to_hash_map(&args).map(Rc::new).map(Expr::Map)
 }
 
 pub fn list_to_hash_map(list: &[Expr]) -> EvalResult<Map> {
-    if list.len() % 2 != 0 {
-        return Err(EvalError::InvalidArgumentCount);
+    if !list.len().is_multiple_of(2) {
+        return Err(EvalError::InvalidArgumentCount(vec![]));
     }
 
     let map = list
         .iter()
         .tuples()
-        .map(|(k, v)| Ok((as_type(k, Expr::to_map_key)?, v.clone())))
-        .collect::<EvalResult<_>>()?;
+        .map(|(k, v)| (k.to_map_key(), v.clone()))
+        .collect();
 
     Ok(map)
 }
 
 pub(super) fn eval_keys(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let expr = eval_1(args, env)?;
+    if let Expr::SortedMap(map) = expr.as_no_meta() {
+        limits::allocate(map.len())?;
+        return Ok(Expr::List(map.keys().cloned().collect()));
+    }
     let list = as_type!(&expr => Expr::Map)?;
+    limits::allocate(list.len())?;
     Ok(Expr::List(list.keys().map(MapKey::to_expr).collect()))
 }
 
 pub(super) fn eval_vals(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let expr = eval_1(args, env)?;
+    if let Expr::SortedMap(map) = expr.as_no_meta() {
+        limits::allocate(map.len())?;
+        return Ok(Expr::List(map.values().cloned().collect()));
+    }
     let map = as_type!(&expr => Expr::Map)?;
+    limits::allocate(map.len())?;
     Ok(Expr::List(map.values().cloned().collect()))
 }
 
 pub(super) fn eval_get(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let (map, key) = eval_2(args, env)?;
+    if let Expr::SortedMap(map) = map.as_no_meta() {
+        let value = map.get(&key, &mut key_cmp(map.comparator(), env))?;
+        return Ok(value.cloned().unwrap_or(Expr::Nil));
+    }
     let map = as_type!(&map => Expr::Map)?;
-    let key = match key.to_map_key() {
-        Some(k) => k,
-        None => return Ok(Expr::Nil),
-    };
-
-    Ok(map.get(&key).cloned().unwrap_or(Expr::Nil))
+    Ok(map.get(&key.to_map_key()).cloned().unwrap_or(Expr::Nil))
 }
 
 pub(super) fn eval_contains(args: &[Expr], env: &Env) -> EvalResult<Expr> {
-    let (map, key) = eval_2(args, env)?;
-    let map = as_type!(&map => Expr::Map)?;
-    let key = match key.to_map_key() {
-        Some(k) => k,
-        None => return Ok(Expr::Bool(false)),
-    };
-
-    Ok(Expr::Bool(map.contains_key(&key)))
+    let (coll, key) = eval_2(args, env)?;
+    if let Expr::SortedMap(map) = coll.as_no_meta() {
+        let found = map.search(&key, &mut key_cmp(map.comparator(), env))?;
+        return Ok(Expr::Bool(found.is_ok()));
+    }
+    let key = key.to_map_key();
+    match coll.as_no_meta() {
+        Expr::Set(set) => Ok(Expr::Bool(set.contains(&key))),
+        _ => {
+            let map = as_type!(&coll => Expr::Map)?;
+            Ok(Expr::Bool(map.contains_key(&key)))
+        }
+    }
 }
 
 pub(super) fn eval_assoc(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let args = eval_args(args, env)?;
-    let (map, args) = args.split_first().ok_or(EvalError::InvalidArgumentCount)?;
+    let (map, args) = args
+        .split_first()
+        .ok_or(EvalError::InvalidArgumentCount(vec![]))?;
+    if !args.len().is_multiple_of(2) {
+        return Err(EvalError::InvalidArgumentCount(vec![]));
+    }
+    limits::allocate(args.len() / 2)?;
+
+    if let Expr::SortedMap(map) = map.as_no_meta() {
+        let mut cmp = key_cmp(map.comparator(), env);
+        let mut new_map = SortedMap::clone(map);
+        for (k, v) in args.iter().tuples() {
+            new_map.insert(k.clone(), v.clone(), &mut cmp)?;
+        }
+        return Ok(Expr::SortedMap(Rc::new(new_map)));
+    }
     let map = as_type!(map => Expr::Map)?;
-    let args_map = list_to_hash_map(args)?;
+
     let mut new_map = Map::clone(map);
-    new_map.extend(args_map);
+    new_map.extend(
+        args.iter()
+            .tuples()
+            .map(|(k, v)| (k.to_map_key(), v.clone())),
+    );
 
     Ok(Expr::Map(Rc::new(new_map)))
 }
 
 pub(super) fn eval_dissoc(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let args = eval_args(args, env)?;
-    let (map, args) = args.split_first().ok_or(EvalError::InvalidArgumentCount)?;
+    let (map, args) = args
+        .split_first()
+        .ok_or(EvalError::InvalidArgumentCount(vec![]))?;
+    if let Expr::SortedMap(map) = map.as_no_meta() {
+        let mut cmp = key_cmp(map.comparator(), env);
+        let mut new_map = SortedMap::clone(map);
+        for arg in args {
+            new_map.remove(arg, &mut cmp)?;
+        }
+        return Ok(Expr::SortedMap(Rc::new(new_map)));
+    }
     let map = as_type!(map => Expr::Map)?;
     let mut new_map = Map::clone(map);
     for arg in args {
-        match arg.to_map_key() {
-            Some(k) => new_map.remove(&k),
-            None => continue,
-        };
+        new_map.remove(&arg.to_map_key());
     }
 
     Ok(Expr::Map(Rc::new(new_map)))
@@ -20,8 +20,11 @@ pub(super) fn eval_with_meta(args: &[Expr], env: &Env) -> EvalResult<Expr> {
         Expr::List(_)
         | Expr::Vector(_)
         | Expr::Map(_)
+        | Expr::SortedMap(_)
+        | Expr::Set(_)
         | Expr::Function(_)
-        | Expr::BuiltinFunction(_) => Ok(Expr::WithMeta {
+        | Expr::BuiltinFunction(_)
+        | Expr::NativeFunction(_) => Ok(Expr::WithMeta {
             expr: Rc::new(expr),
             meta: Rc::new(meta),
         }),
@@ -0,0 +1,313 @@
+use crate::ast::number::{BigInt, Number, Ratio};
+
+use super::prelude::*;
+
+fn division_by_zero() -> EvalError {
+    EvalError::Exception(Expr::String("division by zero".into()))
+}
+
+pub(super) fn eval_arithmetic(
+    op: impl Fn(Number, Number) -> Number,
+    identity: i64,
+    args: &[Expr],
+    env: &Env,
+) -> EvalResult<Expr> {
+    let args = eval_numbers(args, env)?;
+    let res = args.into_iter().fold(Number::Int(identity), op);
+
+    Ok(res.to_expr())
+}
+
+/// Folds the arguments with `op`, a single argument is applied to `identity` instead.
+fn eval_inverse_arithmetic(
+    op: impl Fn(Number, Number) -> Option<Number>,
+    identity: i64,
+    args: &[Expr],
+    env: &Env,
+) -> EvalResult<Expr> {
+    let mut args = eval_numbers(args, env)?.into_iter();
+    let first = args.next().ok_or(EvalError::InvalidArgumentCount(vec![]))?;
+    let res = match args.len() {
+        0 => op(Number::Int(identity), first),
+        _ => args.try_fold(first, op),
+    };
+
+    res.map(Number::to_expr).ok_or_else(division_by_zero)
+}
+
+pub(super) fn eval_sub(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_inverse_arithmetic(|a, b| Some(a - b), 0, args, env)
+}
+
+pub(super) fn eval_div(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_inverse_arithmetic(Number::checked_div, 1, args, env)
+}
+
+fn eval_integer_division(
+    op: impl FnOnce(Number, Number) -> Option<Number>,
+    args: &[Expr],
+    env: &Env,
+) -> EvalResult<Expr> {
+    let (a, b) = eval_number_args(args, env)?;
+    op(a, b).map(Number::to_expr).ok_or_else(division_by_zero)
+}
+
+pub(super) fn eval_quot(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_integer_division(Number::checked_quot, args, env)
+}
+
+pub(super) fn eval_rem(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_integer_division(Number::checked_rem, args, env)
+}
+
+pub(super) fn eval_mod(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_integer_division(Number::checked_mod, args, env)
+}
+
+/// Checks that every consecutive pair of arguments satisfies `op`.
+pub(super) fn eval_cmp(
+    op: impl Fn(&Number, &Number) -> bool,
+    args: &[Expr],
+    env: &Env,
+) -> EvalResult<Expr> {
+    let args = eval_numbers(args, env)?;
+    if args.is_empty() {
+        return Err(EvalError::InvalidArgumentCount(vec![]));
+    }
+    let res = args.windows(2).all(|pair| op(&pair[0], &pair[1]));
+
+    Ok(Expr::Bool(res))
+}
+
+/// Keeps the first of each pair for which `keep_first` holds. A NaN argument makes the
+/// result NaN, as it does in arithmetic, wherever it is.
+fn eval_extremum(
+    keep_first: impl Fn(&Number, &Number) -> bool,
+    args: &[Expr],
+    env: &Env,
+) -> EvalResult<Expr> {
+    let mut args = eval_numbers(args, env)?.into_iter();
+    let first = args.next().ok_or(EvalError::InvalidArgumentCount(vec![]))?;
+    let res = args.fold(first, |acc, n| {
+        if acc.is_nan() || !n.is_nan() && keep_first(&acc, &n) {
+            acc
+        } else {
+            n
+        }
+    });
+
+    Ok(res.to_expr())
+}
+
+pub(super) fn eval_min(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_extremum(|a, b| a <= b, args, env)
+}
+
+pub(super) fn eval_max(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_extremum(|a, b| a >= b, args, env)
+}
+
+pub(super) fn eval_abs(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_number(args, env).map(|n| n.abs().to_expr())
+}
+
+pub(super) fn eval_inc(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_number(args, env).map(|n| (n + Number::Int(1)).to_expr())
+}
+
+pub(super) fn eval_dec(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_number(args, env).map(|n| (n - Number::Int(1)).to_expr())
+}
+
+fn eval_sign(
+    op: impl FnOnce(&Number, &Number) -> bool,
+    args: &[Expr],
+    env: &Env,
+) -> EvalResult<Expr> {
+    eval_number(args, env).map(|n| Expr::Bool(op(&n, &Number::Int(0))))
+}
+
+pub(super) fn eval_is_zero(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_sign(|n, zero| n == zero, args, env)
+}
+
+pub(super) fn eval_is_pos(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_sign(|n, zero| n > zero, args, env)
+}
+
+pub(super) fn eval_is_neg(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_sign(|n, zero| n < zero, args, env)
+}
+
+fn eval_parity(args: &[Expr], env: &Env) -> EvalResult<bool> {
+    let arg = eval_1(args, env)?;
+    as_type(&arg, |e| Number::from_expr(e)?.is_even())
+}
+
+pub(super) fn eval_is_even(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_parity(args, env).map(Expr::Bool)
+}
+
+pub(super) fn eval_is_odd(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_parity(args, env).map(|even| Expr::Bool(!even))
+}
+
+fn eval_number(args: &[Expr], env: &Env) -> EvalResult<Number> {
+    let arg = eval_1(args, env)?;
+    as_type(&arg, Number::from_expr)
+}
+
+fn round_with(
+    float_op: impl FnOnce(f64) -> f64,
+    ratio_op: impl FnOnce(&Ratio) -> BigInt,
+    args: &[Expr],
+    env: &Env,
+) -> EvalResult<Expr> {
+    match eval_number(args, env)? {
+        Number::Float(f) => Ok(Expr::Float(float_op(f))),
+        Number::Ratio(r) => Ok(Number::from_big(ratio_op(&r)).to_expr()),
+        n => Ok(n.to_expr()),
+    }
+}
+
+pub(super) fn eval_is_float(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    is_type!(args, env, Expr::Float(_))
+}
+
+pub(super) fn eval_int(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    match eval_number(args, env)? {
+        Number::Float(f) => BigInt::from_f64_trunc(f)
+            .map(|b| Number::from_big(b).to_expr())
+            .ok_or_else(|| EvalError::InvalidArgumentTypes(vec![Expr::Float(f).to_string()])),
+        Number::Ratio(r) => Ok(Number::from_big(r.trunc()).to_expr()),
+        n => Ok(n.to_expr()),
+    }
+}
+
+pub(super) fn eval_float(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    eval_number(args, env).map(|n| Expr::Float(n.to_f64()))
+}
+
+pub(super) fn eval_floor(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    round_with(f64::floor, Ratio::floor, args, env)
+}
+
+pub(super) fn eval_ceil(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    round_with(f64::ceil, Ratio::ceil, args, env)
+}
+
+pub(super) fn eval_round(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    round_with(f64::round, Ratio::round, args, env)
+}
+
+pub(super) fn eval_is_ratio(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    is_type!(args, env, Expr::Ratio(_))
+}
+
+pub(super) fn eval_numerator(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    match eval_number(args, env)? {
+        Number::Ratio(r) => Ok(Number::from_big(r.numer().clone()).to_expr()),
+        n @ (Number::Int(_) | Number::Big(_)) => Ok(n.to_expr()),
+        n => Err(EvalError::InvalidArgumentTypes(vec![n
+            .to_expr()
+            .to_string()])),
+    }
+}
+
+pub(super) fn eval_denominator(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    match eval_number(args, env)? {
+        Number::Ratio(r) => Ok(Number::from_big(r.denom().clone()).to_expr()),
+        Number::Int(_) | Number::Big(_) => Ok(Expr::Int(1)),
+        n => Err(EvalError::InvalidArgumentTypes(vec![n
+            .to_expr()
+            .to_string()])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::check;
+
+    #[test]
+    fn arithmetic() {
+        check(&[
+            ("(+)", "0"),
+            ("(+ 1 2 3 4)", "10"),
+            ("(+ 1 1/2 0.5)", "2.0"),
+            ("(+ 9223372036854775807 1)", "9223372036854775808"),
+            ("(*)", "1"),
+            ("(* 2 3 4)", "24"),
+            ("(- 5)", "-5"),
+            ("(- 10 1 2 3)", "4"),
+            ("(/ 2)", "1/2"),
+            ("(/ 12 2 3)", "2"),
+            ("(/ 1 2 4)", "1/8"),
+            ("(/ 1.0 0)", "##Inf"),
+            ("(/ 1 0)", "error: exception occurred: division by zero"),
+        ]);
+    }
+
+    #[test]
+    fn integer_division() {
+        check(&[
+            ("(mod 7 3)", "1"),
+            ("(mod -7 3)", "2"),
+            ("(mod 7 -3)", "-2"),
+            ("(mod 7.5 2)", "1.5"),
+            ("(rem -7 3)", "-1"),
+            ("(rem 7 -3)", "1"),
+            ("(quot 7 2)", "3"),
+            ("(quot -7 2)", "-3"),
+            ("(mod 7 0)", "error: exception occurred: division by zero"),
+            ("(rem 1/2 0)", "error: exception occurred: division by zero"),
+            ("(quot 7 0)", "error: exception occurred: division by zero"),
+            // like division, floats by zero don't throw
+            ("(mod 7.0 0)", "##NaN"),
+            ("(mod 7 0.0)", "##NaN"),
+            ("(rem 7.5 0)", "##NaN"),
+        ]);
+    }
+
+    #[test]
+    fn min_max() {
+        check(&[
+            ("(min 3 1 2)", "1"),
+            ("(max 3 1 2)", "3"),
+            ("(min 1/2 0.25)", "0.25"),
+            ("(max 1 2.0 3/2)", "2.0"),
+            // NaN wins wherever it is
+            ("(max 1 ##NaN)", "##NaN"),
+            ("(max ##NaN 1)", "##NaN"),
+            ("(min 1 ##NaN)", "##NaN"),
+            ("(min ##NaN 1)", "##NaN"),
+            ("(min 1 ##NaN 0)", "##NaN"),
+            ("(min)", "error: invalid number of arguments"),
+        ]);
+    }
+
+    #[test]
+    fn predicates() {
+        check(&[
+            ("(zero? 0)", "true"),
+            ("(zero? 0.0)", "true"),
+            ("(zero? 1/2)", "false"),
+            ("(pos? 1/2)", "true"),
+            ("(pos? 0)", "false"),
+            ("(neg? -0.5)", "true"),
+            ("(neg? 0)", "false"),
+            ("(even? 4)", "true"),
+            ("(odd? 3)", "true"),
+            ("(even? 100000000000000000000)", "true"),
+            ("(odd? -3)", "true"),
+            (
+                "(even? 1.0)",
+                r#"error: invalid function arguments: ["1.0"]"#,
+            ),
+            (
+                "(odd? 1/2)",
+                r#"error: invalid function arguments: ["1/2"]"#,
+            ),
+            ("(zero? :a)", r#"error: invalid function arguments: [":a"]"#),
+        ]);
+    }
+}
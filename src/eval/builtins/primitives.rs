@@ -26,7 +26,11 @@ pub(super) fn eval_is_string(args: &[Expr], env: &Env) -> EvalResult<Expr> {
 }
 
 pub(super) fn eval_is_number(args: &[Expr], env: &Env) -> EvalResult<Expr> {
-    is_type!(args, env, Expr::Int(_))
+    is_type!(
+        args,
+        env,
+        Expr::Int(_) | Expr::BigInt(_) | Expr::Ratio(_) | Expr::Float(_)
+    )
 }
 
 pub(super) fn eval_is_fn(args: &[Expr], env: &Env) -> EvalResult<Expr> {
@@ -37,6 +41,7 @@ pub(super) fn eval_is_fn(args: &[Expr], env: &Env) -> EvalResult<Expr> {
             is_macro: false,
             ..
         }) | Expr::BuiltinFunction(_)
+            | Expr::NativeFunction(_)
     )
 }
 
@@ -1,11 +1,19 @@
 use std::rc::Rc;
 
-use crate::eval::Thunk;
+use crate::{
+    ast::{interner::SymbolId, List, ListLike},
+    eval::Thunk,
+};
 
 use super::prelude::*;
 
-pub(super) fn make_quote(expr: Expr) -> Expr {
-    Expr::List(vec![Expr::BuiltinFunction("quote"), expr])
+const QUOTE: SymbolId = SymbolId::preinterned("quote");
+const VEC: SymbolId = SymbolId::preinterned("vec");
+const CONS: SymbolId = SymbolId::preinterned("cons");
+const CONCAT: SymbolId = SymbolId::preinterned("concat");
+
+pub(crate) fn make_quote(expr: Expr) -> Expr {
+    Expr::List(vec![Expr::BuiltinFunction(QUOTE), expr].into())
 }
 
 pub(super) fn eval_quote(args: &[Expr], _env: &Env) -> EvalResult<Expr> {
@@ -17,24 +25,25 @@ pub(super) fn eval_quasiquote(args: &[Expr], env: &Env) -> EvalResult<Thunk> {
     Ok(Thunk::Unevaluated(Rc::new(expr), env.clone()))
 }
 
-pub(super) fn eval_quasiquote_expand(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+pub(crate) fn eval_quasiquote_expand(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let [arg] = args_n(args)?;
 
     match arg {
         Expr::List(l) => eval_quasiquote_list(l, env),
-        Expr::Vector(v) => eval_quasiquote_vec(v, env),
+        Expr::Vector(v) => eval_quasiquote_vec(&v.to_vec(), env),
         Expr::Symbol(_) | Expr::Map(_) => Ok(make_quote(arg.clone())),
         _ => Ok(arg.clone()),
     }
 }
 
 fn eval_quasiquote_vec(vec: &[Expr], env: &Env) -> EvalResult<Expr> {
-    eval_quasiquote_list_like(vec, env).map(|l| Expr::List(vec![Expr::BuiltinFunction("vec"), l]))
+    eval_quasiquote_list_like(vec, env)
+        .map(|l| Expr::List(vec![Expr::BuiltinFunction(VEC), l].into()))
 }
 
 fn eval_quasiquote_list(list: &[Expr], env: &Env) -> EvalResult<Expr> {
     match list {
-        [name, args @ ..] if name.as_func_name() == Some("unquote") => {
+        [name, args @ ..] if name.as_func_name() == Some(SymbolId::UNQUOTE) => {
             args_n(args).map(|[e]| e.clone())
         }
         _ => eval_quasiquote_list_like(list, env),
@@ -42,19 +51,19 @@ fn eval_quasiquote_list(list: &[Expr], env: &Env) -> EvalResult<Expr> {
 }
 
 fn eval_quasiquote_list_like(list: &[Expr], env: &Env) -> EvalResult<Expr> {
-    list.iter().try_rfold(Expr::List(vec![]), |acc, elem| {
-        let res = match elem.as_list_like() {
-            Some([name, args @ ..]) if name.as_func_name() == Some("unquote") => {
+    list.iter().try_rfold(Expr::List(List::new()), |acc, elem| {
+        let res = match elem.as_list_like().map(ListLike::to_slice).as_deref() {
+            Some([name, args @ ..]) if name.as_func_name() == Some(SymbolId::UNQUOTE) => {
                 let [expr] = args_n(args)?;
-                Expr::List(vec![Expr::BuiltinFunction("cons"), expr.clone(), acc])
+                Expr::List(vec![Expr::BuiltinFunction(CONS), expr.clone(), acc].into())
             }
-            Some([name, args @ ..]) if name.as_func_name() == Some("splice-unquote") => {
+            Some([name, args @ ..]) if name.as_func_name() == Some(SymbolId::SPLICE_UNQUOTE) => {
                 let [expr] = args_n(args)?;
-                Expr::List(vec![Expr::BuiltinFunction("concat"), expr.clone(), acc])
+                Expr::List(vec![Expr::BuiltinFunction(CONCAT), expr.clone(), acc].into())
             }
             _ => {
-                let expr = eval_quasiquote_expand(&[elem.clone()], env)?;
-                Expr::List(vec![Expr::BuiltinFunction("cons"), expr, acc])
+                let expr = eval_quasiquote_expand(std::slice::from_ref(elem), env)?;
+                Expr::List(vec![Expr::BuiltinFunction(CONS), expr, acc].into())
             }
         };
         Ok(res)
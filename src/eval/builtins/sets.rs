@@ -0,0 +1,145 @@
+use std::rc::Rc;
+
+use crate::ast::{MapKey, Set};
+
+use super::prelude::*;
+
+/// Sets given to the set operations, `nil` counts as an empty set.
+fn eval_sets(args: &[Expr], env: &Env) -> EvalResult<Vec<Rc<Set>>> {
+    eval_args(args, env)?
+        .into_iter()
+        .map(|arg| match arg.into_no_meta() {
+            Expr::Nil => Ok(Rc::default()),
+            arg => into_type!(arg => Expr::Set),
+        })
+        .collect()
+}
+
+pub(super) fn eval_is_set(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    is_type!(args, env, Expr::Set(_))
+}
+
+pub(super) fn eval_hash_set(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    let args = eval_args(args, env)?;
+    limits::allocate(args.len())?;
+    let set = args.into_iter().map(MapKey::new).collect();
+    Ok(Expr::Set(Rc::new(set)))
+}
+
+pub(super) fn eval_set(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    let coll = eval_1(args, env)?;
+    let set = match coll.into_no_meta() {
+        Expr::Nil => Set::default(),
+        set @ Expr::Set(_) => return Ok(set),
+        Expr::List(items) => items.iter().cloned().map(MapKey::new).collect(),
+        Expr::Vector(items) => items.iter().cloned().map(MapKey::new).collect(),
+        Expr::Map(map) => map
+            .iter()
+            .map(|(k, v)| MapKey::new(Expr::Vector([k.to_expr(), v.clone()].into_iter().collect())))
+            .collect(),
+        Expr::SortedMap(map) => map
+            .iter()
+            .map(|(k, v)| MapKey::new(Expr::Vector([k.clone(), v.clone()].into_iter().collect())))
+            .collect(),
+        coll => return Err(EvalError::InvalidArgumentTypes(vec![coll.to_string()])),
+    };
+    limits::allocate(set.len())?;
+    Ok(Expr::Set(Rc::new(set)))
+}
+
+pub(super) fn eval_disj(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    let args = eval_args(args, env)?;
+    let (set, args) = args
+        .split_first()
+        .ok_or(EvalError::InvalidArgumentCount(vec![]))?;
+    let mut set = Set::clone(as_type!(set => Expr::Set)?);
+    for arg in args {
+        set.remove(&arg.to_map_key());
+    }
+    Ok(Expr::Set(Rc::new(set)))
+}
+
+pub(super) fn eval_union(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    let sets = eval_sets(args, env)?;
+    let set: Set = sets.iter().flat_map(|set| set.iter()).cloned().collect();
+    limits::allocate(set.len())?;
+    Ok(Expr::Set(Rc::new(set)))
+}
+
+pub(super) fn eval_intersection(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    let sets = eval_sets(args, env)?;
+    let (first, rest) = sets
+        .split_first()
+        .ok_or(EvalError::InvalidArgumentCount(vec![]))?;
+    let set: Set = first
+        .iter()
+        .filter(|k| rest.iter().all(|set| set.contains(k)))
+        .cloned()
+        .collect();
+    limits::allocate(set.len())?;
+    Ok(Expr::Set(Rc::new(set)))
+}
+
+pub(super) fn eval_difference(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    let sets = eval_sets(args, env)?;
+    let (first, rest) = sets
+        .split_first()
+        .ok_or(EvalError::InvalidArgumentCount(vec![]))?;
+    let set: Set = first
+        .iter()
+        .filter(|k| !rest.iter().any(|set| set.contains(k)))
+        .cloned()
+        .collect();
+    limits::allocate(set.len())?;
+    Ok(Expr::Set(Rc::new(set)))
+}
+
+pub(super) fn eval_is_subset(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    args_n::<2>(args)?;
+    let sets = eval_sets(args, env)?;
+    Ok(Expr::Bool(sets[0].is_subset(&sets[1])))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{eval::check, parser::parse};
+
+    #[test]
+    fn literals() {
+        for src in ["#{}", "#{1}", r#"#{"a"}"#, "#{[1 2]}"] {
+            assert_eq!(format!("{:#}", parse(src).unwrap()), src);
+        }
+        check(&[
+            ("(set? #{})", "true"),
+            (r#"(pr-str #{"a"})"#, r#"#{"a"}"#),
+            // duplicates in a literal collapse into one element
+            ("(count #{1 2 1})", "2"),
+            ("(= #{1 2 1} #{1 2})", "true"),
+        ]);
+    }
+
+    #[test]
+    fn operations() {
+        check(&[
+            ("(count (hash-set 1 2 2))", "2"),
+            ("(count (set [1 2 1]))", "2"),
+            ("(conj #{} 1 1)", "#{1}"),
+            ("(= (conj #{1} 2 3) #{1 2 3})", "true"),
+            ("(= (disj #{1 2 3} 2 4) #{1 3})", "true"),
+            ("(disj #{1} 1)", "#{}"),
+            ("(contains? #{1 2} 2)", "true"),
+            ("(contains? #{1 2} 3)", "false"),
+            ("(contains? #{[1 2]} [1 2])", "true"),
+        ]);
+    }
+
+    #[test]
+    fn equality() {
+        check(&[
+            ("(= #{1 2} #{2 1})", "true"),
+            ("(= #{1 2} #{1})", "false"),
+            ("(= #{} #{})", "true"),
+            ("(= #{1} [1])", "false"),
+        ]);
+    }
+}
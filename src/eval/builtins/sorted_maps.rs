@@ -0,0 +1,119 @@
+use std::{cmp::Ordering, rc::Rc};
+
+use itertools::Itertools;
+
+use crate::ast::{interner::SymbolId, sorted_map::SortedMap};
+
+use super::{prelude::*, quoting::make_quote};
+
+pub(super) fn eval_sorted_map(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    let args = eval_args(args, env)?;
+    list_to_sorted_map(None, &args, env)
+}
+
+pub(super) fn eval_sorted_map_by(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    let args = eval_args(args, env)?;
+    let (comparator, args) = args
+        .split_first()
+        .ok_or(EvalError::InvalidArgumentCount(vec![]))?;
+    list_to_sorted_map(Some(comparator.clone()), args, env)
+}
+
+fn list_to_sorted_map(comparator: Option<Expr>, list: &[Expr], env: &Env) -> EvalResult<Expr> {
+    if !list.len().is_multiple_of(2) {
+        return Err(EvalError::InvalidArgumentCount(vec![]));
+    }
+    limits::allocate(list.len() / 2)?;
+
+    let mut cmp = key_cmp(comparator.as_ref(), env);
+    let mut map = SortedMap::new(comparator.clone());
+    for (k, v) in list.iter().tuples() {
+        map.insert(k.clone(), v.clone(), &mut cmp)?;
+    }
+
+    Ok(Expr::SortedMap(Rc::new(map)))
+}
+
+/// Compares keys of a sorted map, with its comparator function if it has one.
+///
+/// Like in Clojure, the comparator can either return a number (negative, zero or positive)
+/// or a boolean saying whether the first argument is less than the second one.
+pub(crate) fn key_cmp<'a>(
+    comparator: Option<&Expr>,
+    env: &'a Env,
+) -> impl FnMut(&Expr, &Expr) -> EvalResult<Ordering> + 'a {
+    let comparator = comparator.cloned();
+    move |a, b| {
+        let Some(f) = &comparator else {
+            return Ok(a.total_cmp(b));
+        };
+        let call = |x: &Expr, y: &Expr| {
+            super::eval(
+                &Expr::List(vec![f.clone(), make_quote(x.clone()), make_quote(y.clone())].into()),
+                env,
+            )
+        };
+
+        match call(a, b)? {
+            Expr::Int(n) => Ok(n.cmp(&0)),
+            Expr::Bool(true) => Ok(Ordering::Less),
+            Expr::Bool(false) => match call(b, a)? {
+                Expr::Bool(true) => Ok(Ordering::Greater),
+                _ => Ok(Ordering::Equal),
+            },
+            res => Err(EvalError::TypeMismatch {
+                expected: "int or bool",
+                actual: res.type_name(),
+            }),
+        }
+    }
+}
+
+pub(super) fn eval_subseq(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    subseq(args, env, false)
+}
+
+pub(super) fn eval_rsubseq(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    subseq(args, env, true)
+}
+
+/// `(subseq map test key)` or `(subseq map start-test start-key end-test end-key)`,
+/// where the tests are `<`, `<=`, `>` or `>=`, returns the entries with keys satisfying
+/// the tests as `[key value]` vectors.
+fn subseq(args: &[Expr], env: &Env, reverse: bool) -> EvalResult<Expr> {
+    let args = eval_args(args, env)?;
+    let (map, bounds) = args
+        .split_first()
+        .ok_or(EvalError::InvalidArgumentCount(vec![]))?;
+    let map = as_type!(map => Expr::SortedMap)?;
+    if !matches!(bounds.len(), 2 | 4) {
+        return Err(EvalError::InvalidArgumentCount(vec![]));
+    }
+
+    let mut cmp = key_cmp(map.comparator(), env);
+    let mut range = 0..map.len();
+    for (test, key) in bounds.iter().tuples() {
+        let (at, after) = match map.search(key, &mut cmp)? {
+            Ok(i) => (i, i + 1),
+            Err(i) => (i, i),
+        };
+        match test.as_builtin().map(SymbolId::name) {
+            Some(">") => range.start = range.start.max(after),
+            Some(">=") => range.start = range.start.max(at),
+            Some("<") => range.end = range.end.min(at),
+            Some("<=") => range.end = range.end.min(after),
+            _ => return Err(EvalError::InvalidArgumentTypes(vec![test.to_string()])),
+        }
+    }
+    range.end = range.end.max(range.start);
+    limits::allocate(range.len())?;
+
+    let entries = map
+        .range(range)
+        .map(|(k, v)| Expr::Vector([k.clone(), v.clone()].into_iter().collect()));
+    Ok(Expr::List(if reverse {
+        entries.rev().collect()
+    } else {
+        entries.collect()
+    }))
+}
@@ -1,17 +1,24 @@
-use crate::{ast::display::Join, parser, repl};
+use std::{
+    fs::File,
+    io::{self, BufRead},
+    path::Path,
+};
+
+use crate::{
+    ast::display::{self, Join},
+    parser, repl,
+};
 
 use super::prelude::*;
 
 pub(super) fn eval_pr_str(args: &[Expr], env: &Env) -> EvalResult<Expr> {
-    eval_args(args, env)
-        .map(|args| format!("{:#}", Join(&args, " ")))
-        .map(Expr::String)
+    let args = eval_args(args, env)?;
+    limits::format_string(format_args!("{:#}", Join(&args, " "))).map(Expr::String)
 }
 
 pub(super) fn eval_str(args: &[Expr], env: &Env) -> EvalResult<Expr> {
-    eval_args(args, env)
-        .map(|args| format!("{}", Join(&args, "")))
-        .map(Expr::String)
+    let args = eval_args(args, env)?;
+    limits::format_string(format_args!("{}", Join(&args, ""))).map(Expr::String)
 }
 
 pub(super) fn eval_prn(args: &[Expr], env: &Env) -> EvalResult<Expr> {
@@ -26,6 +33,13 @@ pub(super) fn eval_println(args: &[Expr], env: &Env) -> EvalResult<Expr> {
         .map(|_| Expr::Nil)
 }
 
+pub(super) fn eval_set_sorted_printing(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    let enabled = eval_1(args, env)?;
+    let enabled = as_type!(&enabled => Expr::Bool)?;
+    display::set_sorted_printing(*enabled);
+    Ok(Expr::Nil)
+}
+
 pub(super) fn eval_read_string(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let arg = eval_1(args, env)?;
     let arg = as_type(&arg, Expr::as_string)?;
@@ -37,18 +51,42 @@ pub(super) fn eval_slurp(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let arg = eval_1(args, env)?;
     let arg = as_type(&arg, Expr::as_string)?;
 
-    let content = std::fs::read_to_string(arg)?;
-    Ok(Expr::String(content))
+    slurp(Path::new(arg))
+}
+
+pub(crate) fn slurp(path: &Path) -> EvalResult<Expr> {
+    limits::read_string(File::open(path)?).map(Expr::String)
+}
+
+pub(super) fn eval_load_file(args: &[Expr], env: &Env) -> EvalResult<Expr> {
+    let arg = eval_1(args, env)?;
+    let path = as_type(&arg, Expr::as_string)?;
+
+    load_file(Path::new(path), path, env)
+}
+
+/// Evaluates the file at `path` in the top-level environment, `name` is used in error locations.
+pub(crate) fn load_file(path: &Path, name: &str, env: &Env) -> EvalResult<Expr> {
+    let content = limits::read_string(File::open(path)?)?;
+    let env = env.top_level_env();
+    for expr in parser::parse_all(&content, name)? {
+        eval::eval(&expr, env)?;
+    }
+    Ok(Expr::Nil)
 }
 
 pub(super) fn eval_readline(args: &[Expr], env: &Env) -> EvalResult<Expr> {
     let pr = eval_1(args, env)?;
     let pr = as_type(&pr, Expr::as_string)?;
 
-    match repl::read(Some(&format!("\n{}", pr))) {
-        Ok(s) if s.is_empty() => Ok(Expr::Nil),
-        Ok(s) => Ok(Expr::String(s)),
-        Err(repl::Error::IO(err)) => Err(EvalError::IOError(err)),
-        Err(_) => unreachable!("repl::read doesn't have other error conditions"),
+    repl::prompt(Some(&format!("\n{}", pr)));
+    let mut line = vec![];
+    limits::bounded(io::stdin().lock()).read_until(b'\n', &mut line)?;
+    while let Some(b'\n' | b'\r') = line.last() {
+        line.pop();
+    }
+    match line.is_empty() {
+        true => Ok(Expr::Nil),
+        false => limits::into_string(line).map(Expr::String),
     }
 }
@@ -0,0 +1,636 @@
+//! Compilation of function bodies into closures.
+//!
+//! The tree walker dispatches on the shape of an expression every time it's evaluated,
+//! and looks up variables and builtins through every scope.
+//! Once a function was called a few times, its body is converted into a tree of closures
+//! with most of that work done ahead of time:
+//!
+//! - variables know how many of the function's own scopes they can skip, and the slot
+//!   of the scope that binds them,
+//! - builtins are called through their Rust function, once the name was checked to still
+//!   refer to the same builtin,
+//! - `if`, `do`, `let*`, `fn*` and `quote` are evaluated directly, calls in tail position
+//!   are returned as thunks like the tree walker does.
+//!
+//! Everything else, like macro calls or a redefined `if`, is left to the tree walker, so
+//! macros are still expanded on every evaluation and redefining a name takes effect.
+
+use std::{
+    cell::{Cell, RefCell},
+    fmt,
+    rc::Rc,
+};
+
+use crate::{
+    ast::{interner::SymbolId, pattern::Pattern, Expr, FunctionArity, List, Symbol, Vector},
+    environment::{Env, Environment},
+};
+
+use super::{
+    bind_args,
+    builtins::{builtin, eval_list_builtin, make_closure, parse_fn, thunk_builtin},
+    call_frame, call_native, check_native_arity,
+    destructure::bind,
+    eval_thunk, limits, CallStack, EvalError, EvalResult,
+    Thunk::{self, Compiled, Evaluated, Unevaluated},
+    STACK_RED_ZONE, STACK_SEGMENT_SIZE,
+};
+
+/// Calls after which a function body is compiled, running it once with the tree walker
+/// is cheaper than compiling it.
+const HOT_CALLS: u32 = 2;
+
+type Run = dyn Fn(&Env, &mut CallStack) -> EvalResult<Thunk>;
+type Value = dyn Fn(&Env) -> EvalResult<Expr>;
+
+/// Expression compiled into a closure, which evaluates it in the scope it's given.
+#[derive(Clone)]
+pub struct Code(Kind);
+
+#[derive(Clone)]
+enum Kind {
+    /// Always evaluates to a value, without continuing with other code.
+    Value(Rc<Value>),
+    Run(Rc<Run>),
+}
+
+impl Code {
+    fn new(run: impl Fn(&Env, &mut CallStack) -> EvalResult<Thunk> + 'static) -> Self {
+        Self(Kind::Run(Rc::new(run)))
+    }
+
+    fn value(value: impl Fn(&Env) -> EvalResult<Expr> + 'static) -> Self {
+        Self(Kind::Value(Rc::new(value)))
+    }
+
+    /// Evaluates the code in tail position, entering the functions it calls into `stack`.
+    pub(super) fn run(&self, env: &Env, stack: &mut CallStack) -> EvalResult<Thunk> {
+        match &self.0 {
+            Kind::Value(value) => value(env).map(Evaluated),
+            Kind::Run(run) => run(env, stack),
+        }
+    }
+
+    /// Evaluates the code out of tail position, like [`eval`](super::eval).
+    fn eval(&self, env: &Env) -> EvalResult<Expr> {
+        let run = match &self.0 {
+            Kind::Value(value) => return value(env),
+            Kind::Run(run) => run,
+        };
+        stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT_SIZE, || {
+            let mut stack = CallStack::default();
+            let result = match run(env, &mut stack) {
+                Ok(Evaluated(e)) => Ok(e),
+                Ok(thunk) => eval_thunk(thunk, &mut stack),
+                Err(e) => Err(e),
+            };
+            result.map_err(|e| stack.attach_to(e))
+        })
+    }
+}
+
+impl fmt::Debug for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Code").finish_non_exhaustive()
+    }
+}
+
+/// Compiled body of a [`FunctionArity`], shared by the functions created from it.
+#[derive(Default)]
+pub(crate) struct Cache {
+    calls: Cell<u32>,
+    code: RefCell<Option<Code>>,
+}
+
+impl Cache {
+    fn get(&self, compile: impl FnOnce() -> Code) -> Option<Code> {
+        if let Some(code) = &*self.code.borrow() {
+            return Some(code.clone());
+        }
+        self.calls.set(self.calls.get() + 1);
+        if self.calls.get() < HOT_CALLS {
+            return None;
+        }
+        let code = compile();
+        *self.code.borrow_mut() = Some(code.clone());
+        Some(code)
+    }
+
+    /// Takes out the compiled code, the next call compiles the body again.
+    pub(crate) fn take(&self) -> Option<Code> {
+        self.code.take()
+    }
+}
+
+/// A copy starts counting calls again.
+impl Clone for Cache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+/// Doesn't take part in comparisons of functions.
+impl PartialEq for Cache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cache")
+            .field("calls", &self.calls.get())
+            .field("compiled", &self.code.borrow().is_some())
+            .finish()
+    }
+}
+
+/// Starts evaluating the body of `arity` in `env`, the scope with its arguments bound,
+/// compiling it first if the function is called often enough.
+pub(crate) fn enter(arity: &FunctionArity, closure: &Env, env: Env) -> Thunk {
+    match arity.compiled.get(|| compile_body(arity, closure)) {
+        Some(code) => Compiled(code, env),
+        None => Unevaluated(Rc::clone(&arity.expr), env),
+    }
+}
+
+fn compile_body(arity: &FunctionArity, closure: &Env) -> Code {
+    Compiler {
+        closure,
+        scopes: vec![parameters(arity)],
+    }
+    .compile(&arity.expr)
+}
+
+/// Names the parameters of `arity` bind, in the order of their slots in the scope of a call.
+pub(super) fn parameters(arity: &FunctionArity) -> Vec<SymbolId> {
+    let mut scope = vec![];
+    let params = arity.bindings.iter().chain(&arity.varargs);
+    declare(&mut scope, params.flat_map(Pattern::names));
+    scope
+}
+
+/// Adds the names bound in a scope to the ones known while compiling, each name gets
+/// a slot when it's first bound.
+pub(super) fn declare(scope: &mut Vec<SymbolId>, names: impl IntoIterator<Item = SymbolId>) {
+    for name in names {
+        if !scope.contains(&name) {
+            scope.push(name);
+        }
+    }
+}
+
+struct Compiler<'a> {
+    /// Scope of the function, telling what names not bound by the function refer to.
+    /// Only a hint, since they can be redefined after compiling.
+    closure: &'a Env,
+    /// Names bound by the scopes the function creates, from its parameters to the innermost.
+    scopes: Vec<Vec<SymbolId>>,
+}
+
+impl Compiler<'_> {
+    fn compile(&mut self, expr: &Expr) -> Code {
+        match expr {
+            Expr::Symbol(symbol) => self.variable(symbol),
+            Expr::List(list) if !list.is_empty() => self.call(expr, list),
+            Expr::Vector(vector) => self.vector(vector),
+            Expr::Map(_) | Expr::Set(_) | Expr::WithMeta { .. } => fallback(expr),
+            expr => constant(expr.clone()),
+        }
+    }
+
+    /// How many of the function's scopes, innermost first, don't bind `name`.
+    fn depth(&self, name: SymbolId) -> usize {
+        self.scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains(&name))
+            .unwrap_or(self.scopes.len())
+    }
+
+    /// What `name` currently refers to, if it isn't bound by the function.
+    fn resolve(&self, name: SymbolId) -> Option<Expr> {
+        if self.depth(name) < self.scopes.len() {
+            return None;
+        }
+        self.closure.get(name)
+    }
+
+    fn variable(&self, symbol: &Symbol) -> Code {
+        let variable = Variable::new(symbol, &self.scopes);
+        Code::value(move |env| {
+            limits::step()?;
+            variable.get(env)
+        })
+    }
+
+    fn vector(&mut self, vector: &Vector) -> Code {
+        let items = vector.iter().map(|e| self.compile(e)).collect::<Vec<_>>();
+        Code::value(move |env| {
+            limits::step()?;
+            limits::allocate(items.len())?;
+            Ok(Expr::Vector(
+                items
+                    .iter()
+                    .map(|e| e.eval(env))
+                    .collect::<EvalResult<_>>()?,
+            ))
+        })
+    }
+
+    fn call(&mut self, expr: &Expr, list: &List) -> Code {
+        let (head, args) = list.split_first().expect("calls aren't empty");
+        if let Expr::Symbol(symbol) = head {
+            match self.resolve(symbol.id()) {
+                Some(Expr::BuiltinFunction(name)) => {
+                    if let Some(code) = self.builtin(expr, symbol, name, args) {
+                        return code;
+                    }
+                }
+                Some(Expr::Function(f)) if f.is_macro => return fallback(expr),
+                _ => {}
+            }
+        }
+
+        let call = Call {
+            expr: Rc::new(expr.clone()),
+            head: self.compile(head),
+            args: args.iter().map(|arg| self.compile(arg)).collect(),
+        };
+        let span = list.call_span().cloned();
+        Code::new(move |env, stack| call.run(env, stack).map_err(|e| e.located(span.as_ref())))
+    }
+
+    /// Compiles a call to the builtin `name`, `symbol` currently refers to.
+    fn builtin(
+        &mut self,
+        expr: &Expr,
+        symbol: &Symbol,
+        name: SymbolId,
+        args: &[Expr],
+    ) -> Option<Code> {
+        let head = Head {
+            variable: Variable {
+                symbol: symbol.clone(),
+                depth: self.scopes.len(),
+                slot: None,
+            },
+            builtin: name,
+        };
+
+        let code = match (name.name(), args) {
+            ("if", [cond, then, otherwise @ ..]) if otherwise.len() <= 1 => {
+                let cond = self.compile(cond);
+                let then = self.compile(then);
+                let otherwise = match otherwise {
+                    [otherwise] => self.compile(otherwise),
+                    _ => constant(Expr::Nil),
+                };
+                guarded(expr, head, move |head, env, stack| {
+                    match cond.eval(env).map_err(|e| head.located(e))? {
+                        Expr::Nil | Expr::Bool(false) => otherwise.run(env, stack),
+                        _ => then.run(env, stack),
+                    }
+                })
+            }
+            ("do", [init @ .., last]) => {
+                let init = init.iter().map(|e| self.compile(e)).collect::<Vec<_>>();
+                let last = self.compile(last);
+                guarded(expr, head, move |head, env, stack| {
+                    for e in &init {
+                        e.eval(env).map_err(|e| head.located(e))?;
+                    }
+                    last.run(env, stack)
+                })
+            }
+            ("do", []) => guarded(expr, head, |_, _, _| Ok(Evaluated(Expr::Nil))),
+            ("let*", [vars, body]) => return self.let_form(expr, head, vars, body),
+            ("fn*", _) => {
+                let arities: Rc<[FunctionArity]> = parse_fn(args).ok()?.into();
+                guarded(expr, head, move |_, env, _| {
+                    Ok(Evaluated(make_closure(Rc::clone(&arities), env)))
+                })
+            }
+            ("quote", [quoted]) => {
+                let quoted = quoted.clone();
+                guarded(expr, head, move |_, _, _| Ok(Evaluated(quoted.clone())))
+            }
+            _ => {
+                // builtins evaluate their arguments themselves
+                let args = args.to_vec();
+                if let Some(f) = builtin(name) {
+                    guarded(expr, head, move |head, env, _| {
+                        f(&args, env).map(Evaluated).map_err(|e| head.located(e))
+                    })
+                } else {
+                    let f = thunk_builtin(name)?;
+                    guarded(expr, head, move |head, env, _| {
+                        f(&args, env).map_err(|e| head.located(e))
+                    })
+                }
+            }
+        };
+        Some(code)
+    }
+
+    fn let_form(&mut self, expr: &Expr, head: Head, vars: &Expr, body: &Expr) -> Option<Code> {
+        let vars = vars.as_list_like().filter(|vars| vars.len() % 2 == 0)?;
+        let vars = vars.to_slice();
+
+        self.scopes.push(vec![]);
+        let mut bindings = vec![];
+        for pair in vars.chunks_exact(2) {
+            let Ok(pattern) = Pattern::parse(&pair[0]) else {
+                self.scopes.pop();
+                return None;
+            };
+            // the value can only see the names bound before it
+            let value = self.compile(&pair[1]);
+            let scope = self.scopes.last_mut().expect("scope was pushed");
+            declare(scope, pattern.names());
+            bindings.push((pattern, value));
+        }
+        let body = self.compile(body);
+        self.scopes.pop();
+
+        Some(guarded(expr, head, move |head, env, stack| {
+            let let_env = Environment::with_parent(env.clone());
+            for (pattern, value) in &bindings {
+                let value = value.eval(&let_env).map_err(|e| head.located(e))?;
+                bind(pattern, value, &let_env).map_err(|e| head.located(e))?;
+            }
+            body.run(&let_env, stack)
+        }))
+    }
+}
+
+fn constant(expr: Expr) -> Code {
+    Code::value(move |_| {
+        limits::step()?;
+        Ok(expr.clone())
+    })
+}
+
+/// Leaves `expr` to the tree walker.
+fn fallback(expr: &Expr) -> Code {
+    let expr = Rc::new(expr.clone());
+    Code::new(move |env, _| Ok(Unevaluated(Rc::clone(&expr), env.clone())))
+}
+
+/// Code for a call to a builtin that checks `head` still refers to it first,
+/// and leaves the call to the tree walker otherwise.
+fn guarded(
+    expr: &Expr,
+    head: Head,
+    run: impl Fn(&Head, &Env, &mut CallStack) -> EvalResult<Thunk> + 'static,
+) -> Code {
+    let expr = Rc::new(expr.clone());
+    Code::new(move |env, stack| {
+        limits::step()?;
+        if !head.holds(env)? {
+            return Ok(Unevaluated(Rc::clone(&expr), env.clone()));
+        }
+        run(&head, env, stack)
+    })
+}
+
+pub(super) struct Variable {
+    pub(super) symbol: Symbol,
+    /// Scopes of the function known not to bind the variable.
+    pub(super) depth: usize,
+    /// Index of the variable in the slots of the next scope, if the function binds it.
+    pub(super) slot: Option<usize>,
+}
+
+impl Variable {
+    /// Resolves `symbol` against the names bound by the function's scopes, from
+    /// the innermost to the outermost.
+    pub(super) fn new(symbol: &Symbol, scopes: &[Vec<SymbolId>]) -> Self {
+        let id = symbol.id();
+        let (depth, slot) = scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .find_map(|(depth, scope)| Some((depth, scope.iter().position(|&n| n == id)?)))
+            .map_or((scopes.len(), None), |(depth, slot)| (depth, Some(slot)));
+        Self {
+            symbol: symbol.clone(),
+            depth,
+            slot,
+        }
+    }
+
+    pub(super) fn get(&self, env: &Env) -> EvalResult<Expr> {
+        env.get_skipping(self.depth, self.slot, self.symbol.id())
+            .ok_or_else(|| {
+                EvalError::UnknownSymbol(self.symbol.id())
+                    .to_exception()
+                    .located(self.symbol.span())
+            })
+    }
+}
+
+/// Name of a builtin at the start of a call.
+struct Head {
+    variable: Variable,
+    builtin: SymbolId,
+}
+
+impl Head {
+    fn holds(&self, env: &Env) -> EvalResult<bool> {
+        limits::step()?;
+        let value = env.get_skipping(self.variable.depth, None, self.variable.symbol.id());
+        Ok(matches!(value, Some(f) if f.as_no_meta().as_builtin() == Some(self.builtin)))
+    }
+
+    fn located(&self, error: EvalError) -> EvalError {
+        error.located(self.variable.symbol.span())
+    }
+}
+
+/// Call of anything other than a builtin known when compiling.
+struct Call {
+    /// The whole call, for the tree walker to evaluate if it's a macro call.
+    expr: Rc<Expr>,
+    head: Code,
+    args: Vec<Code>,
+}
+
+impl Call {
+    fn run(&self, env: &Env, stack: &mut CallStack) -> EvalResult<Thunk> {
+        limits::step()?;
+        let f = match self.head.eval(env)?.into_no_meta() {
+            Expr::Function(f) if !f.is_macro => f,
+            Expr::NativeFunction(native) => {
+                check_native_arity(&native, self.args.len())?;
+                let args = self.eval_args(env)?;
+                return call_native(&native, &args, self.head_expr(), env).map(Evaluated);
+            }
+            macro_ @ Expr::Function(_) => return Ok(self.expand(macro_, env)),
+            f => {
+                return eval_list_builtin(&f, self.raw_args(), env)
+                    .unwrap_or_else(|| Err(EvalError::InvalidFunctionName(f.to_string())))
+            }
+        };
+
+        let arity = f
+            .find_arity(self.args.len())
+            .ok_or_else(|| EvalError::InvalidArgumentCount(f.arities()))?;
+        let args_env = bind_args(&f, arity, self.args.iter().map(|arg| arg.eval(env)))?;
+        stack.enter(call_frame(&f, self.args.len(), self.head_expr()));
+        Ok(enter(arity, &f.closure, args_env))
+    }
+
+    fn eval_args(&self, env: &Env) -> EvalResult<Vec<Expr>> {
+        self.args.iter().map(|arg| arg.eval(env)).collect()
+    }
+
+    fn list(&self) -> &[Expr] {
+        match &*self.expr {
+            Expr::List(list) => list,
+            _ => unreachable!("calls are lists"),
+        }
+    }
+
+    fn head_expr(&self) -> &Expr {
+        &self.list()[0]
+    }
+
+    fn raw_args(&self) -> &[Expr] {
+        &self.list()[1..]
+    }
+
+    /// Leaves a macro call to the tree walker, without evaluating the head again
+    /// unless it's just a name.
+    fn expand(&self, macro_: Expr, env: &Env) -> Thunk {
+        let expr = match self.head_expr() {
+            Expr::Symbol(_) => Rc::clone(&self.expr),
+            _ => Rc::new(Expr::List(
+                std::iter::once(macro_)
+                    .chain(self.raw_args().iter().cloned())
+                    .collect(),
+            )),
+        };
+        Unevaluated(expr, env.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ast::Expr,
+        environment::{Env, Environment},
+        eval::{eval, run},
+        parser::parse,
+    };
+
+    fn is_compiled(env: &Env, name: &str) -> bool {
+        let Expr::Function(f) = run(env, name) else {
+            panic!("{name} isn't a function");
+        };
+        let compiled = f.arities[0].compiled.code.borrow().is_some();
+        compiled
+    }
+
+    #[test]
+    fn compiled_functions_keep_semantics() {
+        let env = Environment::with_builtins();
+        run(&env, "(def! x 1)");
+        run(
+            &env,
+            "(def! sum (fn* [n acc] (if (= n 0) acc (let* [m (- n 1)] (sum m (+ n acc))))))",
+        );
+        // deeper than the depth limit, so only works with tail calls
+        assert_eq!(run(&env, "(sum 20000 0)"), Expr::Int(200010000));
+        assert!(is_compiled(&env, "sum"));
+
+        run(&env, "(def! add (fn* [a b] (+ a b x)))");
+        run(&env, "(defmacro! m (fn* [a] a))");
+        run(&env, "(def! f (fn* [a] (m a)))");
+        for _ in 0..3 {
+            assert_eq!(run(&env, "(add 2 3)"), Expr::Int(6));
+            assert_eq!(run(&env, "(f 2)"), Expr::Int(2));
+        }
+        assert!(is_compiled(&env, "add") && is_compiled(&env, "f"));
+
+        // redefinitions after compiling take effect
+        run(&env, "(def! x 10)");
+        run(&env, "(def! + -)");
+        assert_eq!(run(&env, "(add 2 3)"), Expr::Int(-11));
+        run(&env, "(defmacro! m (fn* [a] (list '* a a)))");
+        assert_eq!(run(&env, "(f 3)"), Expr::Int(9));
+
+        // variables defined at runtime shadow the ones the compiled code expected
+        run(
+            &env,
+            "(def! g (fn* [a] (let* [b 1] (do (eval* '(def! a 5)) a))))",
+        );
+        for _ in 0..3 {
+            assert_eq!(run(&env, "(g 2)"), Expr::Int(5));
+        }
+        assert!(is_compiled(&env, "g"));
+    }
+
+    #[test]
+    fn compiled_variables_read_their_slots() {
+        let env = Environment::with_builtins();
+        let cases = [
+            (
+                "(fn* [a b] (let* [a (+ a 1) c (* a b)] [a b c]))",
+                "1 2",
+                "[2 2 4]",
+            ),
+            ("(fn* [a a] a)", "1 2", "2"),
+            ("(fn* [a & a] a)", "1 2", "(2)"),
+            (
+                "(fn* [[a b] {:keys [c] :or {c a}}] [a b c])",
+                "[1 2] {}",
+                "[1 2 1]",
+            ),
+            (
+                "(fn* [x] (let* [x 1 x (+ x 1) y x] (loop* [y y] [x y])))",
+                "0",
+                "[2 2]",
+            ),
+            ("(fn* [a] (do (def! b a) (let* [a 3] [a b])))", "5", "[3 5]"),
+            (
+                "(fn* [a] (fn* [b] (let* [c (+ a b)] [a b c])))",
+                "1",
+                "#<function>",
+            ),
+        ];
+        for (f, args, expected) in cases {
+            run(&env, &format!("(def! f {f})"));
+            for _ in 0..3 {
+                assert_eq!(
+                    run(&env, &format!("(f {args})")).to_string(),
+                    expected,
+                    "{f}"
+                );
+            }
+            assert!(is_compiled(&env, "f"), "{f}");
+        }
+        assert_eq!(run(&env, "((f 1) 2)").to_string(), "[1 2 3]");
+    }
+
+    #[test]
+    fn compiled_errors_match_interpreted() {
+        let env = Environment::with_builtins();
+        run(&env, "(def! inner (fn* [a] (nope a)))");
+        run(&env, "(def! outer (fn* [a] (inner (+ a 1))))");
+        let error = || {
+            let err = eval(&parse("(outer 1)").unwrap(), &env).unwrap_err();
+            let trace = err
+                .trace()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            (err.to_string(), err.span().map(ToString::to_string), trace)
+        };
+        let interpreted = error();
+        for _ in 0..2 {
+            assert_eq!(error(), interpreted);
+        }
+        assert!(is_compiled(&env, "outer"));
+    }
+}
@@ -0,0 +1,86 @@
+use crate::{
+    ast::{pattern::Pattern, Expr, List, ListLike},
+    environment::Env,
+};
+
+use super::{builtins::key_cmp, eval, EvalError, EvalResult};
+
+/// Binds the names in `pattern` to the matching parts of `value` in `env`.
+///
+/// Missing items and keys are bound to `nil`. `:or` defaults are evaluated in `env` before
+/// any name of their map pattern is bound, so they can refer to names bound before the
+/// map but not to the other names in it.
+pub fn bind(pattern: &Pattern, value: Expr, env: &Env) -> EvalResult<()> {
+    match pattern {
+        Pattern::Symbol(name) => env.bind(*name, value),
+        Pattern::Sequential { items, rest, whole } => {
+            let empty = List::new();
+            let list = match value.as_no_meta() {
+                Expr::Nil => ListLike::List(&empty),
+                value => value
+                    .as_list_like()
+                    .ok_or_else(|| EvalError::TypeMismatch {
+                        expected: "list or vector",
+                        actual: value.type_name(),
+                    })?,
+            };
+
+            for (i, item) in items.iter().enumerate() {
+                bind(item, list.get(i).cloned().unwrap_or(Expr::Nil), env)?;
+            }
+            if let Some(rest) = rest {
+                let rest_items = list.iter().skip(items.len()).cloned().collect();
+                bind(rest, Expr::List(rest_items), env)?;
+            }
+            if let Some(whole) = whole {
+                env.bind(*whole, value);
+            }
+        }
+        Pattern::Associative {
+            entries,
+            defaults,
+            whole,
+        } => {
+            // only held back if defaults could see them, so that binding usually doesn't allocate
+            let mut found_values = vec![];
+            for (pattern, key) in entries {
+                let found = match value.as_no_meta() {
+                    Expr::Nil => None,
+                    Expr::Map(map) => map.get(&key.to_map_key()).cloned(),
+                    Expr::SortedMap(map) => {
+                        map.get(key, &mut key_cmp(map.comparator(), env))?.cloned()
+                    }
+                    value => {
+                        return Err(EvalError::TypeMismatch {
+                            expected: "map",
+                            actual: value.type_name(),
+                        })
+                    }
+                };
+
+                let default = match pattern {
+                    Pattern::Symbol(name) => defaults.iter().find(|(n, _)| n == name),
+                    _ => None,
+                };
+                let found = match (found, default) {
+                    (Some(found), _) => found,
+                    (None, Some((_, default))) => eval(default, env)?,
+                    (None, None) => Expr::Nil,
+                };
+                if defaults.is_empty() {
+                    bind(pattern, found, env)?;
+                } else {
+                    found_values.push((pattern, found));
+                }
+            }
+            for (pattern, found) in found_values {
+                bind(pattern, found, env)?;
+            }
+            if let Some(whole) = whole {
+                env.bind(*whole, value);
+            }
+        }
+    }
+
+    Ok(())
+}
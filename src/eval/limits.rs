@@ -0,0 +1,370 @@
+use std::{
+    cell::Cell,
+    fmt,
+    io::{self, Read},
+    time::Instant,
+};
+
+use super::{EvalError, EvalResult};
+
+/// Bounds on the resources evaluation can use, for running untrusted code.
+///
+/// Exceeding any of them except [`max_depth`](Self::max_depth) fails with
+/// [`EvalError::LimitExceeded`], which `try*` can't catch, so the error always reaches
+/// the host.
+///
+/// Limits apply to every evaluation on the current thread until replaced with
+/// [`set_limits`], and steps and elements count across evaluations. [`with_limits`]
+/// applies them to a single evaluation instead.
+///
+/// ```
+/// use std::time::{Duration, Instant};
+///
+/// use rust2::{
+///     environment::Environment,
+///     eval::{eval, limits::{self, EvalLimits, Limit}, EvalError},
+///     parser::parse,
+/// };
+///
+/// let limits = EvalLimits {
+///     max_steps: Some(10_000),
+///     deadline: Some(Instant::now() + Duration::from_secs(1)),
+///     ..Default::default()
+/// };
+///
+/// let env = Environment::with_builtins();
+/// let endless = parse("(try* (loop* [] (recur)) (catch* e :caught))").unwrap();
+/// let err = limits::with_limits(limits, || eval(&endless, &env)).unwrap_err();
+/// assert!(matches!(err.without_context(), EvalError::LimitExceeded(Limit::Steps)));
+/// assert_eq!(limits::limits().max_steps, None);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct EvalLimits {
+    /// Maximum number of evaluation steps, each one being a symbol lookup, a call or a literal.
+    pub max_steps: Option<u64>,
+    /// Maximum total number of elements put into newly created collections, including
+    /// symbol names read for the first time, which stay interned.
+    pub max_elements: Option<usize>,
+    /// Point in time after which evaluation stops.
+    pub deadline: Option<Instant>,
+    /// Maximum length in bytes of any string created during evaluation.
+    pub max_string_len: Option<usize>,
+    /// Maximum nesting of evaluation, e.g. in non-tail recursive calls. Unlike the other
+    /// limits, going deeper throws a "stack depth exceeded" exception that `try*` can
+    /// catch. The native stack grows on the heap as needed, so the depth is only bounded
+    /// by this and available memory.
+    pub max_depth: usize,
+}
+
+/// Default for [`EvalLimits::max_depth`].
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+impl Default for EvalLimits {
+    fn default() -> Self {
+        Self {
+            max_steps: None,
+            max_elements: None,
+            deadline: None,
+            max_string_len: None,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+}
+
+/// Which of the [`EvalLimits`] was exceeded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Steps,
+    Elements,
+    Deadline,
+    StringLength,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Steps => "step",
+            Limit::Elements => "collection size",
+            Limit::Deadline => "time",
+            Limit::StringLength => "string length",
+        })
+    }
+}
+
+/// Reading the clock on every step would be slow, so the deadline is only checked
+/// once in this many steps.
+const DEADLINE_CHECK_INTERVAL: u64 = 64;
+
+thread_local! {
+    static LIMITS: Cell<EvalLimits> = const {
+        Cell::new(EvalLimits {
+            max_steps: None,
+            max_elements: None,
+            deadline: None,
+            max_string_len: None,
+            max_depth: DEFAULT_MAX_DEPTH,
+        })
+    };
+    static STEPS: Cell<u64> = const { Cell::new(0) };
+    static ELEMENTS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Applies `limits` to evaluation on the current thread, counting steps and
+/// collection elements from zero again.
+pub fn set_limits(limits: EvalLimits) {
+    LIMITS.with(|l| l.set(limits));
+    STEPS.with(|steps| steps.set(0));
+    ELEMENTS.with(|elements| elements.set(0));
+}
+
+/// Runs `f` with `limits` applied to the current thread, then restores the previous
+/// limits and counts, also if `f` panics.
+pub fn with_limits<T>(limits: EvalLimits, f: impl FnOnce() -> T) -> T {
+    struct Restore(EvalLimits, u64, usize);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            LIMITS.with(|l| l.set(self.0));
+            STEPS.with(|steps| steps.set(self.1));
+            ELEMENTS.with(|elements| elements.set(self.2));
+        }
+    }
+
+    let _restore = Restore(
+        self::limits(),
+        STEPS.with(Cell::get),
+        ELEMENTS.with(Cell::get),
+    );
+    set_limits(limits);
+    f()
+}
+
+pub fn limits() -> EvalLimits {
+    LIMITS.with(Cell::get)
+}
+
+/// Counts one evaluation step.
+pub(crate) fn step() -> EvalResult<()> {
+    let limits = limits();
+    let steps = STEPS.with(|steps| {
+        steps.set(steps.get() + 1);
+        steps.get()
+    });
+
+    if limits.max_steps.is_some_and(|max| steps > max) {
+        return Err(EvalError::LimitExceeded(Limit::Steps));
+    }
+    if steps.is_multiple_of(DEADLINE_CHECK_INTERVAL)
+        && limits
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    {
+        return Err(EvalError::LimitExceeded(Limit::Deadline));
+    }
+    Ok(())
+}
+
+/// Counts `count` elements put into a new collection.
+pub(crate) fn allocate(count: usize) -> EvalResult<()> {
+    count_elements(count).map_err(EvalError::LimitExceeded)
+}
+
+/// Counts `count` elements, also outside of evaluation like when reading code.
+pub(crate) fn count_elements(count: usize) -> Result<(), Limit> {
+    let elements = ELEMENTS.with(|elements| {
+        elements.set(elements.get().saturating_add(count));
+        elements.get()
+    });
+
+    match limits().max_elements {
+        Some(max) if elements > max => Err(Limit::Elements),
+        _ => Ok(()),
+    }
+}
+
+/// Checks the length of a newly created string.
+pub(crate) fn check_string(s: &str) -> EvalResult<()> {
+    match limits().max_string_len {
+        Some(max) if s.len() > max => Err(EvalError::LimitExceeded(Limit::StringLength)),
+        _ => Ok(()),
+    }
+}
+
+/// Formats `args` into a new string, stopping as soon as it gets longer than allowed.
+pub(crate) fn format_string(args: fmt::Arguments<'_>) -> EvalResult<String> {
+    struct Bounded {
+        out: String,
+        max: Option<usize>,
+    }
+
+    impl fmt::Write for Bounded {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            if self.max.is_some_and(|max| self.out.len() + s.len() > max) {
+                return Err(fmt::Error);
+            }
+            self.out.push_str(s);
+            Ok(())
+        }
+    }
+
+    let mut bounded = Bounded {
+        out: String::new(),
+        max: limits().max_string_len,
+    };
+    match fmt::write(&mut bounded, args) {
+        Ok(()) => Ok(bounded.out),
+        Err(_) => Err(EvalError::LimitExceeded(Limit::StringLength)),
+    }
+}
+
+/// Reads `reader` into a new string, stopping as soon as it gets longer than allowed.
+pub(crate) fn read_string(reader: impl Read) -> EvalResult<String> {
+    let mut bytes = vec![];
+    bounded(reader).read_to_end(&mut bytes)?;
+    into_string(bytes)
+}
+
+/// Stops reading one byte after the longest string allowed, which shows the input is
+/// too long without reading all of it.
+pub(crate) fn bounded<R: Read>(reader: R) -> io::Take<R> {
+    let max = limits().max_string_len;
+    reader.take(max.map_or(u64::MAX, |max| max as u64 + 1))
+}
+
+/// Checks the length of bytes read from a [`bounded`] reader and decodes them.
+pub(crate) fn into_string(bytes: Vec<u8>) -> EvalResult<String> {
+    if limits().max_string_len.is_some_and(|max| bytes.len() > max) {
+        return Err(EvalError::LimitExceeded(Limit::StringLength));
+    }
+    String::from_utf8(bytes)
+        .map_err(|e| EvalError::IOError(io::Error::new(io::ErrorKind::InvalidData, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        time::{Duration, Instant},
+    };
+
+    use super::{limits, set_limits, with_limits, EvalLimits, Limit, STEPS};
+    use crate::{
+        ast::Expr,
+        environment::Environment,
+        eval::{eval, EvalError},
+        parser::parse,
+    };
+
+    fn exceeded(limits: EvalLimits, src: &str) -> Option<Limit> {
+        let env = Environment::with_builtins();
+        let src = format!("(try* {src} (catch* e :caught))");
+        match with_limits(limits, || eval(&parse(&src).unwrap(), &env)) {
+            Err(err) => match err.without_context() {
+                EvalError::LimitExceeded(limit) => Some(*limit),
+                err => panic!("{err}"),
+            },
+            Ok(_) => None,
+        }
+    }
+
+    #[test]
+    fn limits_are_not_catchable() {
+        let steps = EvalLimits {
+            max_steps: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(exceeded(steps, "(loop* [] (recur))"), Some(Limit::Steps));
+        assert_eq!(exceeded(steps, "(+ 1 2)"), None);
+
+        let elements = EvalLimits {
+            max_elements: Some(100),
+            ..Default::default()
+        };
+        let grow = "(loop* [v []] (recur (conj v 1)))";
+        assert_eq!(exceeded(elements, grow), Some(Limit::Elements));
+        assert_eq!(exceeded(elements, "(vector 1 2 3)"), None);
+        // names read for the first time stay interned, known ones only count as items
+        let names = (0..60).map(|i| format!("fresh-{i}")).collect::<Vec<_>>();
+        let fresh = format!(r#"(read-string "({})")"#, names.join(" "));
+        assert_eq!(exceeded(elements, &fresh), Some(Limit::Elements));
+        let known = format!(r#"(read-string "({})")"#, ["+"; 60].join(" "));
+        assert_eq!(exceeded(elements, &known), None);
+        let items = format!(r#"(read-string "[{}]")"#, ["1"; 200].join(" "));
+        assert_eq!(exceeded(elements, &items), Some(Limit::Elements));
+        let json = format!(r#"(json-parse "[{}]")"#, ["1"; 200].join(","));
+        assert_eq!(exceeded(elements, &json), Some(Limit::Elements));
+        let json = format!(r#"(json-parse "{{{}}}")"#, [r#"\"a\": 1"#; 200].join(","));
+        assert_eq!(exceeded(elements, &json), Some(Limit::Elements));
+        assert_eq!(exceeded(elements, r#"(json-parse "[1, 2]")"#), None);
+
+        let deadline = EvalLimits {
+            deadline: Some(Instant::now() + Duration::from_millis(50)),
+            ..Default::default()
+        };
+        assert_eq!(
+            exceeded(deadline, "(loop* [] (recur))"),
+            Some(Limit::Deadline)
+        );
+
+        let strings = EvalLimits {
+            max_string_len: Some(1000),
+            ..Default::default()
+        };
+        let double = r#"(loop* [s "a"] (recur (str s s)))"#;
+        assert_eq!(exceeded(strings, double), Some(Limit::StringLength));
+        assert_eq!(exceeded(strings, r#"(str "a" "b")"#), None);
+        let long = format!("[{}]", [r#""aaaaaaaaaaaaaaaaaaaa""#; 100].join(" "));
+        for f in ["str", "pr-str", "json-stringify"] {
+            let src = format!("({f} {long})");
+            assert_eq!(exceeded(strings, &src), Some(Limit::StringLength), "{f}");
+        }
+
+        let path = std::env::temp_dir().join(format!("mal-limits-{}", std::process::id()));
+        std::fs::write(&path, "a".repeat(2000)).unwrap();
+        let slurp = format!("(slurp {:?})", path.display().to_string());
+        assert_eq!(exceeded(strings, &slurp), Some(Limit::StringLength));
+        std::fs::write(&path, "a".repeat(1000)).unwrap();
+        assert_eq!(exceeded(strings, &slurp), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn depth_is_a_limit() {
+        let depth = EvalLimits {
+            max_depth: 50,
+            ..Default::default()
+        };
+        let env = Environment::with_builtins();
+        let run = |src: &str| with_limits(depth, || eval(&parse(src).unwrap(), &env));
+        run("(def! f (fn* [n] (if (= n 0) 0 (+ 1 (f (- n 1))))))").unwrap();
+        let caught = run("(try* (f 100) (catch* e e))");
+        assert_eq!(caught.unwrap(), Expr::String("stack depth exceeded".into()));
+        assert_eq!(run("(f 10)").unwrap(), Expr::Int(10));
+    }
+
+    #[test]
+    fn with_limits_restores_the_previous_limits() {
+        let outer = EvalLimits {
+            max_steps: Some(1000),
+            ..Default::default()
+        };
+        let inner = EvalLimits {
+            max_steps: Some(10),
+            ..Default::default()
+        };
+        set_limits(outer);
+        let env = Environment::with_builtins();
+        eval(&parse("(+ 1 2)").unwrap(), &env).unwrap();
+        let steps = STEPS.with(Cell::get);
+
+        let endless = parse("(loop* [] (recur))").unwrap();
+        assert!(with_limits(inner, || eval(&endless, &env)).is_err());
+        assert_eq!(limits().max_steps, Some(1000));
+        assert_eq!(STEPS.with(Cell::get), steps);
+
+        let panicked = std::panic::catch_unwind(|| with_limits(inner, || panic!()));
+        assert!(panicked.is_err());
+        assert_eq!(limits().max_steps, Some(1000));
+        set_limits(EvalLimits::default());
+    }
+}
@@ -0,0 +1,113 @@
+use std::{fmt, rc::Rc};
+
+use itertools::Itertools;
+
+use crate::span::Span;
+
+/// Entry in the Lisp-level call stack attached to errors, innermost first.
+#[derive(Debug, Clone)]
+pub enum Frame {
+    Call {
+        name: Option<Rc<str>>,
+        args: usize,
+        span: Option<Rc<Span>>,
+    },
+    /// Calls that were replaced by tail calls before the error happened.
+    Elided(usize),
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Frame::Call { name, args, span } => {
+                let name = name.as_deref().unwrap_or("<anonymous>");
+                let plural = if *args == 1 { "" } else { "s" };
+                write!(f, "at {name} ({args} arg{plural})")?;
+                if let Some(span) = span {
+                    write!(f, " {span}")?;
+                }
+                Ok(())
+            }
+            Frame::Elided(1) => f.write_str("... 1 frame elided by tail call"),
+            Frame::Elided(n) => write!(f, "... {n} frames elided by tail calls"),
+        }
+    }
+}
+
+pub struct StackTrace<'a>(pub &'a [Frame]);
+
+impl fmt::Display for StackTrace<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "stack trace (most recent call first):")?;
+        // deep recursion produces long runs of the same frame, printed only once
+        let frames = self.0.iter().map(Frame::to_string).dedup_with_count();
+        for (count, frame) in frames {
+            writeln!(f, "    {frame}")?;
+            match count - 1 {
+                0 => {}
+                1 => writeln!(f, "    ... repeated 1 more time")?,
+                n => writeln!(f, "    ... repeated {n} more times")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Frame, StackTrace};
+    use crate::{environment::Environment, eval::eval, parser::parse};
+
+    const DEFINITIONS: &[&str] = &[
+        r#"(def! f (fn* [n] (if (= n 0) (throw "boom") (+ 1 (f (- n 1))))))"#,
+        r#"(def! g (fn* [n] (if (= n 0) (throw "boom") (g (- n 1)))))"#,
+        "(def! h (fn* [] (+ 1 (g 5))))",
+    ];
+
+    /// The frames of the error `src` fails with, and the trace printed from them.
+    fn trace(src: &str) -> (Vec<Frame>, String) {
+        let env = Environment::with_builtins();
+        for definition in DEFINITIONS {
+            eval(&parse(definition).unwrap(), &env).unwrap();
+        }
+        let err = eval(&parse(src).unwrap(), &env).unwrap_err();
+        (err.trace().to_vec(), StackTrace(err.trace()).to_string())
+    }
+
+    #[test]
+    fn repeated_frames_are_collapsed() {
+        let (frames, printed) = trace("(f 50)");
+        assert_eq!(frames.len(), 51);
+        assert!(frames.iter().all(|frame| matches!(
+            frame,
+            Frame::Call { name: Some(name), args: 1, .. } if &**name == "f"
+        )));
+        assert_eq!(
+            printed,
+            "stack trace (most recent call first):\n    \
+             at f (1 arg) <input>:1:51\n    \
+             ... repeated 49 more times\n    \
+             at f (1 arg) <input>:1:2\n"
+        );
+    }
+
+    #[test]
+    fn tail_calls_are_elided() {
+        let (frames, printed) = trace("(h)");
+        assert!(matches!(
+            &frames[..],
+            [
+                Frame::Call { .. },
+                Frame::Elided(5),
+                Frame::Call { args: 0, .. }
+            ]
+        ));
+        assert_eq!(
+            printed,
+            "stack trace (most recent call first):\n    \
+             at g (1 arg) <input>:1:46\n    \
+             ... 5 frames elided by tail calls\n    \
+             at h (0 args) <input>:1:2\n"
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{ast::Expr, environment::Env};
+use crate::{
+    ast::{number::Number, Expr},
+    environment::Env,
+};
 
 use super::{EvalError, EvalResult};
 
@@ -6,14 +9,9 @@ pub(super) fn eval_args(args: &[Expr], env: &Env) -> EvalResult<Vec<Expr>> {
     args.iter().map(|arg| super::eval(arg, env)).collect()
 }
 
-#[cfg(feature = "nightly")]
-pub(super) fn eval_n<const N: usize>(args: &[Expr], env: &Env) -> EvalResult<[Expr; N]> {
-    let args = args_n(args)?;
-    args.try_map(|arg| super::eval(&arg, env))
-}
-
 pub(super) fn args_n<const N: usize>(args: &[Expr]) -> EvalResult<&[Expr; N]> {
-    args.try_into().map_err(|_| EvalError::InvalidArgumentCount)
+    args.try_into()
+        .map_err(|_| EvalError::InvalidArgumentCount(vec![]))
 }
 
 pub(super) fn eval_1(args: &[Expr], env: &Env) -> EvalResult<Expr> {
@@ -87,12 +85,19 @@ pub(super) mod macros {
     pub(crate) use is_type;
 }
 
-pub(super) fn eval_number_args(args: &[Expr], env: &Env) -> EvalResult<(i64, i64)> {
+pub(super) fn eval_numbers(args: &[Expr], env: &Env) -> EvalResult<Vec<Number>> {
+    eval_args(args, env)?
+        .iter()
+        .map(|arg| as_type(arg, Number::from_expr))
+        .collect()
+}
+
+pub(super) fn eval_number_args(args: &[Expr], env: &Env) -> EvalResult<(Number, Number)> {
     let (a, b) = eval_2(args, env)?;
 
-    match (a, b) {
-        (Expr::Int(a), Expr::Int(b)) => Ok((a, b)),
-        (a, b) => Err(EvalError::InvalidArgumentTypes(vec![
+    match (Number::from_expr(&a), Number::from_expr(&b)) {
+        (Some(a), Some(b)) => Ok((a, b)),
+        _ => Err(EvalError::InvalidArgumentTypes(vec![
             a.to_string(),
             b.to_string(),
         ])),
@@ -0,0 +1,700 @@
+//! Bytecode compiler and stack machine, an alternative to the tree walker.
+//!
+//! Expressions are compiled into chunks of instructions after expanding the macros
+//! they call, function bodies when the machine first calls them. Calls between compiled
+//! functions push frames onto the machine's own stack instead of recursing, calls in tail
+//! position replace the current frame, `try*` registers a handler the machine unwinds to
+//! and `loop*` with `recur` becomes a jump.
+//!
+//! Scopes are the same [`Environment`]s the tree walker creates, so closures, `def!` and
+//! `eval*` behave the same with either of them. What the compiler doesn't handle is left
+//! to the tree walker:
+//!
+//! - builtins are called with their already evaluated arguments quoted,
+//! - calls of macros and builtins only known at runtime are evaluated by it,
+//!   as are functions called by builtins like `map`,
+//! - like in [`compile`](super::compile), names resolved while compiling are checked
+//!   to still refer to the same builtin or macro before running the code compiled for them.
+
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{
+    ast::{
+        interner::SymbolId, pattern::Pattern, Expr, Function, FunctionArity, List, Map, MapKey,
+        Set, Symbol,
+    },
+    environment::{Env, Environment, Loop},
+    span::Span,
+};
+
+use self::compiler::Compiler;
+
+use super::{
+    bind_args,
+    builtins::{eval_list_builtin, make_closure, make_quote, BuiltinFn},
+    call_frame, call_native, check_native_arity,
+    compile::Variable,
+    destructure::bind,
+    eval_thunk, limits, trace, CallStack, DepthGuard, EvalError, EvalResult, Thunk, STACK_RED_ZONE,
+    STACK_SEGMENT_SIZE,
+};
+
+mod compiler;
+
+/// Evaluates `expr` in `env` like [`eval`](super::eval), by compiling it to bytecode
+/// and running it on the stack machine.
+///
+/// ```
+/// use rust2::{environment::Environment, eval::vm, parser::parse};
+///
+/// let env = Environment::with_builtins();
+/// let run = |src| vm::eval(&parse(src).unwrap(), &env).unwrap();
+/// run("(def! fib (fn* [n] (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))))");
+/// assert_eq!(run("(fib 20)").to_string(), "6765");
+/// ```
+pub fn eval(expr: &Expr, env: &Env) -> EvalResult<Expr> {
+    let chunk = Compiler::new(env, vec![]).finish(expr);
+    Machine::start(Rc::new(chunk), env.clone())?.run()
+}
+
+/// Instruction of the stack machine, its operands index the pools of the [`Chunk`]
+/// or its code.
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Constant(u32),
+    /// Pushes the value of a variable.
+    Get(u32),
+    Pop,
+    /// Collects the given number of values into a vector.
+    Vector(u32),
+    /// Collects the given number of key and value pairs into a map.
+    Map(u32),
+    Set(u32),
+    /// Pushes a function closing over the current scope.
+    Closure(u32),
+    /// Pushes the value of an expression evaluated by the tree walker.
+    Eval(u32),
+    /// Evaluates the value on top of the stack again, which the tree walker does
+    /// with the value of a macro call.
+    Reeval,
+    /// Same for the value the frame returns, set when a macro call is in tail position.
+    ReevalResult,
+    Jump(u32),
+    /// Pops a value and jumps if it's `nil` or `false`.
+    JumpIfFalse(u32),
+    /// Leaves the call to the tree walker and jumps to `end` if the name at its start
+    /// no longer refers to what it did when compiling.
+    Guard {
+        head: u32,
+        end: u32,
+    },
+    /// Enters a new scope.
+    Scope,
+    /// Enters the scope of the first iteration of a `loop*`.
+    Loop(u32),
+    /// Pops a value and binds it to a pattern in the current scope.
+    Bind(u32),
+    /// Returns to the parent of the current scope.
+    Unscope,
+    /// Defines a variable with the value on top of the stack.
+    Def(u32),
+    DefMacro(u32),
+    DefPattern(u32),
+    /// Pops the given number of arguments and calls a builtin with them.
+    Builtin {
+        builtin: u32,
+        args: u32,
+    },
+    /// Leaves the call to the tree walker and jumps to `end` if the function on top
+    /// of the stack takes its arguments unevaluated, being a macro or a builtin.
+    Dispatch {
+        call: u32,
+        end: u32,
+    },
+    /// Calls the function below the arguments.
+    Call(u32),
+    /// Same, replacing the current frame.
+    TailCall(u32),
+    /// Pops new values for the bindings of the enclosing `loop*` and starts its body again.
+    Recur {
+        args: u32,
+        body: u32,
+    },
+    /// Starts the body of a `try*`, exceptions thrown until [`Op::EndTry`] are passed
+    /// to the function handling them, after which the frame continues at `end`.
+    Try {
+        catch: u32,
+        end: u32,
+    },
+    EndTry,
+    Return,
+}
+
+/// Compiled function body or top level expression.
+#[derive(Default)]
+pub(crate) struct Chunk {
+    code: Vec<Op>,
+    /// For each instruction, the name of the call its errors are attributed to.
+    spans: Vec<Option<Rc<Span>>>,
+    constants: Vec<Expr>,
+    variables: Vec<Variable>,
+    heads: Vec<Head>,
+    patterns: Vec<Pattern>,
+    names: Vec<Symbol>,
+    functions: Vec<Rc<[FunctionArity]>>,
+    loops: Vec<Rc<Loop>>,
+    builtins: Vec<BuiltinFn>,
+    /// Calls of functions, kept whole for the tree walker.
+    calls: Vec<Expr>,
+}
+
+/// Bytecode of a [`FunctionArity`], shared by the functions created from it.
+#[derive(Default)]
+pub(crate) struct Cache(RefCell<Option<Rc<Chunk>>>);
+
+impl Cache {
+    fn get(&self, compile: impl FnOnce() -> Chunk) -> Rc<Chunk> {
+        if let Some(chunk) = &*self.0.borrow() {
+            return Rc::clone(chunk);
+        }
+        let chunk = Rc::new(compile());
+        *self.0.borrow_mut() = Some(Rc::clone(&chunk));
+        chunk
+    }
+
+    /// Takes out the bytecode, the next call compiles the body again.
+    pub(crate) fn take(&self) -> Option<Rc<Chunk>> {
+        self.0.take()
+    }
+}
+
+/// A copy is compiled again.
+impl Clone for Cache {
+    fn clone(&self) -> Self {
+        Self::default()
+    }
+}
+
+/// Doesn't take part in comparisons of functions.
+impl PartialEq for Cache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let instructions = self.0.borrow().as_ref().map(|chunk| chunk.code.len());
+        f.debug_tuple("Cache").field(&instructions).finish()
+    }
+}
+
+/// Name at the start of a call that the compiler resolved.
+struct Head {
+    variable: Variable,
+    expected: Expected,
+    /// The whole call, for the tree walker to evaluate if the name changed.
+    call: Expr,
+}
+
+enum Expected {
+    Builtin(SymbolId),
+    Macro(Function),
+}
+
+impl Head {
+    fn holds(&self, env: &Env) -> bool {
+        let value = env.get_skipping(self.variable.depth, None, self.variable.symbol.id());
+        match (value.as_ref().map(Expr::as_no_meta), &self.expected) {
+            (Some(Expr::BuiltinFunction(name)), Expected::Builtin(builtin)) => name == builtin,
+            (Some(Expr::Function(f)), Expected::Macro(m)) => {
+                f.is_macro
+                    && Rc::ptr_eq(&f.arities, &m.arities)
+                    && Rc::ptr_eq(&f.closure, &m.closure)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Function body or top level expression being run.
+struct Frame {
+    chunk: Rc<Chunk>,
+    pc: usize,
+    env: Env,
+    /// Height of the stack below the frame's values.
+    base: usize,
+    /// Function call shown in stack traces, replaced by tail calls.
+    call: Option<trace::Frame>,
+    elided: usize,
+    /// Whether the returned value is evaluated again, see [`Op::ReevalResult`].
+    reeval: bool,
+    _depth: DepthGuard,
+}
+
+impl Frame {
+    fn new(
+        chunk: Rc<Chunk>,
+        env: Env,
+        base: usize,
+        call: Option<trace::Frame>,
+    ) -> EvalResult<Self> {
+        Ok(Self {
+            chunk,
+            pc: 0,
+            env,
+            base,
+            call,
+            elided: 0,
+            reeval: false,
+            _depth: DepthGuard::enter()?,
+        })
+    }
+
+    /// Records that `error` propagated out of the frame, like [`CallStack::attach_to`].
+    fn unwind(self, mut error: EvalError) -> EvalError {
+        if let Some(call) = self.call {
+            error = error.traced(call);
+        }
+        if self.elided > 0 {
+            error = error.traced(trace::Frame::Elided(self.elided));
+        }
+        error
+    }
+
+    /// Location of errors of the last instruction run.
+    fn span(&self) -> Option<&Rc<Span>> {
+        self.chunk.spans[self.pc - 1].as_ref()
+    }
+}
+
+/// `try*` being run.
+struct Handler {
+    /// Index of the frame that entered it.
+    frame: usize,
+    stack: usize,
+    env: Env,
+    catch: Rc<[FunctionArity]>,
+    end: usize,
+}
+
+struct Machine {
+    stack: Vec<Expr>,
+    frames: Vec<Frame>,
+    handlers: Vec<Handler>,
+}
+
+impl Machine {
+    fn start(chunk: Rc<Chunk>, env: Env) -> EvalResult<Self> {
+        Ok(Self {
+            stack: vec![],
+            frames: vec![Frame::new(chunk, env, 0, None)?],
+            handlers: vec![],
+        })
+    }
+
+    fn run(mut self) -> EvalResult<Expr> {
+        loop {
+            match self.execute() {
+                Ok(value) => return Ok(value),
+                Err(error) => self.catch(error)?,
+            }
+        }
+    }
+
+    /// Runs instructions until the bottom frame returns or one of them fails.
+    fn execute(&mut self) -> EvalResult<Expr> {
+        loop {
+            let frame = self.frames.last_mut().expect("a frame is running");
+            let op = frame.chunk.code[frame.pc];
+            frame.pc += 1;
+            match self.step(op) {
+                Ok(None) => {}
+                Ok(Some(value)) => return Ok(value),
+                Err(error) => {
+                    let frame = self.frames.last().expect("a frame is running");
+                    return Err(error.located(frame.span()));
+                }
+            }
+        }
+    }
+
+    /// Unwinds the frames up to the innermost `try*` and calls its handler if `error`
+    /// is an exception, otherwise returns it with every frame recorded in its trace.
+    fn catch(&mut self, mut error: EvalError) -> EvalResult<()> {
+        let handler = match error.without_context() {
+            EvalError::Exception(_) => self.handlers.pop(),
+            _ => None,
+        };
+
+        let kept = handler.as_ref().map_or(0, |handler| handler.frame + 1);
+        while self.frames.len() > kept {
+            let frame = self.frames.pop().expect("more frames than kept");
+            error = frame.unwind(error);
+            if let Some(caller) = self.frames.last() {
+                error = error.located(caller.span());
+            }
+        }
+
+        let Some(handler) = handler else {
+            return Err(error);
+        };
+        let exception = error.into_exception()?;
+        self.stack.truncate(handler.stack);
+        let frame = self
+            .frames
+            .last_mut()
+            .expect("the frame of the handler is kept");
+        frame.pc = handler.end;
+        frame.env = handler.env.clone();
+
+        let catch = Expr::Function(Function {
+            name: None,
+            arities: handler.catch,
+            closure: handler.env,
+            is_macro: false,
+        });
+        self.stack.extend([catch.clone(), exception]);
+        match self.apply(1, &catch, false) {
+            Ok(()) => Ok(()),
+            Err(error) => {
+                let frame = self
+                    .frames
+                    .last()
+                    .expect("the frame of the handler is kept");
+                let span = frame.span().cloned();
+                self.catch(error.located(span.as_ref()))
+            }
+        }
+    }
+
+    /// Runs one instruction, returning the value of the bottom frame if it returned.
+    fn step(&mut self, op: Op) -> EvalResult<Option<Expr>> {
+        limits::step()?;
+        let frames = self.frames.len();
+        let frame = self.frames.last_mut().expect("a frame is running");
+        let chunk = &*frame.chunk;
+        let stack = &mut self.stack;
+
+        match op {
+            Op::Constant(constant) => stack.push(chunk.constants[constant as usize].clone()),
+            Op::Get(variable) => stack.push(chunk.variables[variable as usize].get(&frame.env)?),
+            Op::Pop => {
+                stack.pop();
+            }
+            Op::Vector(len) => {
+                limits::allocate(len as usize)?;
+                let items = stack.drain(stack.len() - len as usize..);
+                let vector = items.collect();
+                stack.push(Expr::Vector(vector));
+            }
+            Op::Map(len) => {
+                limits::allocate(len as usize)?;
+                let mut items = stack.drain(stack.len() - 2 * len as usize..);
+                let map: Map =
+                    std::iter::from_fn(|| Some((MapKey::new(items.next()?), items.next()?)))
+                        .collect();
+                drop(items);
+                stack.push(Expr::Map(Rc::new(map)));
+            }
+            Op::Set(len) => {
+                limits::allocate(len as usize)?;
+                let items = stack.drain(stack.len() - len as usize..);
+                let set: Set = items.map(MapKey::new).collect();
+                stack.push(Expr::Set(Rc::new(set)));
+            }
+            Op::Closure(function) => {
+                let arities = Rc::clone(&chunk.functions[function as usize]);
+                stack.push(make_closure(arities, &frame.env));
+            }
+            Op::Eval(constant) => {
+                let value = super::eval(&chunk.constants[constant as usize], &frame.env)?;
+                stack.push(value);
+            }
+            Op::Reeval => {
+                let value = pop(stack);
+                stack.push(super::eval(&value, &frame.env)?);
+            }
+            Op::ReevalResult => frame.reeval = true,
+            Op::Jump(target) => frame.pc = target as usize,
+            Op::JumpIfFalse(target) => {
+                if let Expr::Nil | Expr::Bool(false) = pop(stack) {
+                    frame.pc = target as usize;
+                }
+            }
+            Op::Guard { head, end } => {
+                let head = &chunk.heads[head as usize];
+                if !head.holds(&frame.env) {
+                    stack.push(super::eval(&head.call, &frame.env)?);
+                    frame.pc = end as usize;
+                }
+            }
+            Op::Scope => frame.env = Environment::with_parent(frame.env.clone()),
+            Op::Loop(target) => {
+                let target = Rc::clone(&chunk.loops[target as usize]);
+                frame.env = Environment::for_loop(frame.env.clone(), target);
+            }
+            Op::Bind(pattern) => bind(&chunk.patterns[pattern as usize], pop(stack), &frame.env)?,
+            Op::Unscope => {
+                let parent = frame.env.parent().expect("scopes have a parent");
+                frame.env = Rc::clone(parent);
+            }
+            Op::Def(name) => {
+                let name = &chunk.names[name as usize];
+                let value = named(pop(stack), name);
+                frame.env.set(name, value.clone());
+                stack.push(value);
+            }
+            Op::DefMacro(name) => {
+                let name = &chunk.names[name as usize];
+                let f = match named(pop(stack), name).into_no_meta() {
+                    Expr::Function(f) => f,
+                    value => return Err(EvalError::InvalidArgumentTypes(vec![value.to_string()])),
+                };
+                let value = Expr::Function(Function {
+                    is_macro: true,
+                    ..f
+                });
+                frame.env.set(name, value.clone());
+                stack.push(value);
+            }
+            Op::DefPattern(pattern) => {
+                let value = pop(stack);
+                frame.env.mark_extended();
+                bind(&chunk.patterns[pattern as usize], value.clone(), &frame.env)?;
+                stack.push(value);
+            }
+            Op::Builtin { builtin, args } => {
+                let args = stack.drain(stack.len() - args as usize..);
+                let args = args.map(quoted).collect::<Vec<_>>();
+                stack.push(chunk.builtins[builtin as usize](&args, &frame.env)?);
+            }
+            Op::Dispatch { call, end } => {
+                let f = stack.last().expect("function on the stack");
+                if let Some(value) = dispatch(f, &chunk.calls[call as usize], &frame.env)? {
+                    *stack.last_mut().expect("function on the stack") = value;
+                    frame.pc = end as usize;
+                }
+            }
+            Op::Call(call) | Op::TailCall(call) => {
+                let chunk = Rc::clone(&frame.chunk);
+                let call = call_list(&chunk.calls[call as usize]);
+                let tail = matches!(op, Op::TailCall(_));
+                self.apply(call.len() - 1, &call[0], tail)
+                    .map_err(|e| e.located(call.call_span()))?;
+            }
+            Op::Recur { args, body } => {
+                let values = stack.split_off(stack.len() - args as usize);
+                let (target, outer) = frame
+                    .env
+                    .enclosing_loop()
+                    .ok_or_else(|| EvalError::InvalidRecur("used outside of loop*".to_owned()))?;
+                let env = Environment::for_loop(outer, Rc::clone(&target));
+                for (pattern, value) in target.bindings.iter().zip(values) {
+                    bind(pattern, value, &env)?;
+                }
+                frame.env = env;
+                frame.pc = body as usize;
+            }
+            Op::Try { catch, end } => self.handlers.push(Handler {
+                frame: frames - 1,
+                stack: stack.len(),
+                env: frame.env.clone(),
+                catch: Rc::clone(&chunk.functions[catch as usize]),
+                end: end as usize,
+            }),
+            Op::EndTry => {
+                self.handlers.pop();
+            }
+            Op::Return => {
+                let mut value = pop(stack);
+                if frame.reeval {
+                    value = super::eval(&value, &frame.env)?;
+                }
+                let frame = self.frames.pop().expect("a frame is running");
+                self.stack.truncate(frame.base);
+                if self.frames.is_empty() {
+                    return Ok(Some(value));
+                }
+                self.stack.push(value);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Calls the function on the stack under its `args` evaluated arguments, which are
+    /// popped with it, pushing its frame unless it's native.
+    fn apply(&mut self, args: usize, head: &Expr, tail: bool) -> EvalResult<()> {
+        let frame = self.frames.last_mut().expect("a frame is running");
+        let start = self.stack.len() - args;
+        let f = std::mem::replace(&mut self.stack[start - 1], Expr::Nil);
+        let f = match f.into_no_meta() {
+            Expr::Function(f) if !f.is_macro => f,
+            Expr::NativeFunction(native) => {
+                check_native_arity(&native, args)?;
+                let value = call_native(&native, &self.stack[start..], head, &frame.env)?;
+                self.stack.truncate(start - 1);
+                self.stack.push(value);
+                return Ok(());
+            }
+            f => return Err(EvalError::InvalidFunctionName(f.to_string())),
+        };
+
+        let arity = f
+            .find_arity(args)
+            .ok_or_else(|| EvalError::InvalidArgumentCount(f.arities()))?;
+        let call = call_frame(&f, args, head);
+        let env = bind_args(&f, arity, self.stack.drain(start..).map(Ok))?;
+        self.stack.pop();
+        let chunk = arity
+            .bytecode
+            .get(|| compiler::compile_function(arity, &f.closure));
+
+        if tail {
+            self.stack.truncate(frame.base);
+            frame.chunk = chunk;
+            frame.pc = 0;
+            frame.env = env;
+            if frame.call.replace(call).is_some() {
+                frame.elided += 1;
+            }
+        } else {
+            let frame = Frame::new(chunk, env, self.stack.len(), Some(call))?;
+            self.frames.push(frame);
+        }
+        Ok(())
+    }
+}
+
+fn pop(stack: &mut Vec<Expr>) -> Expr {
+    stack
+        .pop()
+        .expect("compiled code pushes the values it pops")
+}
+
+fn call_list(call: &Expr) -> &List {
+    match call {
+        Expr::List(list) => list,
+        _ => unreachable!("calls are lists"),
+    }
+}
+
+/// Names a function defined with `def!`, unless it already has a name.
+fn named(value: Expr, name: &Symbol) -> Expr {
+    match value {
+        Expr::Function(f) if f.name.is_none() => Expr::Function(Function {
+            name: Some(name.name().into()),
+            ..f
+        }),
+        value => value,
+    }
+}
+
+/// Builtins evaluate their arguments, so values that don't evaluate to themselves
+/// are passed to them quoted.
+fn quoted(value: Expr) -> Expr {
+    match value {
+        Expr::List(_)
+        | Expr::Vector(_)
+        | Expr::Map(_)
+        | Expr::Set(_)
+        | Expr::Symbol(_)
+        | Expr::WithMeta { .. } => make_quote(value),
+        value => value,
+    }
+}
+
+/// Evaluates `call` with the tree walker if `f`, the value of its head, takes unevaluated
+/// arguments.
+fn dispatch(f: &Expr, call: &Expr, env: &Env) -> EvalResult<Option<Expr>> {
+    let list = call_list(call);
+    let f = f.as_no_meta();
+    if let Expr::Function(Function { is_macro: true, .. }) = f {
+        // evaluating the head again is only harmless if it's just a name
+        let value = match &list[0] {
+            Expr::Symbol(_) => super::eval(call, env)?,
+            _ => {
+                let call = std::iter::once(f.clone()).chain(list[1..].iter().cloned());
+                super::eval(&Expr::List(call.collect()), env)?
+            }
+        };
+        return Ok(Some(value));
+    }
+
+    match eval_list_builtin(f, &list[1..], env) {
+        Some(thunk) => finish(thunk?).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Evaluates what a builtin continued with.
+fn finish(thunk: Thunk) -> EvalResult<Expr> {
+    stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT_SIZE, || {
+        let mut stack = CallStack::default();
+        eval_thunk(thunk, &mut stack).map_err(|e| stack.attach_to(e))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        ast::Expr,
+        environment::{Env, Environment},
+        eval::{self, EvalResult},
+        parser::parse,
+    };
+
+    /// Result or error of the last program, with the error's location and stack trace.
+    fn outcome(
+        eval: fn(&Expr, &Env) -> EvalResult<Expr>,
+        programs: &[&str],
+    ) -> Result<String, (String, Option<String>, Vec<String>)> {
+        let env = Environment::with_builtins();
+        let mut last = Ok(String::new());
+        for src in programs {
+            last = eval(&parse(src).unwrap(), &env)
+                .map(|value| value.to_string())
+                .map_err(|err| {
+                    let trace = err.trace().iter().map(ToString::to_string).collect();
+                    (err.to_string(), err.span().map(ToString::to_string), trace)
+                });
+        }
+        last
+    }
+
+    #[test]
+    fn matches_tree_walker() {
+        let cases: &[&[&str]] = &[
+            &[
+                "(def! fib (fn* [n] (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))))",
+                "(fib 15)",
+            ],
+            &[
+                "(def! sum (fn* [n acc] (if (= n 0) acc (sum (- n 1) (+ n acc)))))",
+                "(sum 50000 0)",
+            ],
+            &["(let* [[a & r] [1 2 3] {:keys [x] :or {x 4}} {}] (list a r x))"],
+            &["(loop* [i 0 acc []] (if (< i 5) (recur (inc i) (conj acc (fn* [] i))) (map (fn* [f] (f)) acc)))"],
+            &["(try* (try* (throw 1) (catch* e (throw (+ e 1)))) (catch* e (* e 10)))"],
+            &["(def! g (fn* [x] (cond (= x 0) :zero (> x 0) :pos true :neg)))", "(map g [0 1 -1])"],
+            &["(let* [x 5] `(1 ~x ~@(list 2 3) [~(+ x 1)]))"],
+            &["(def! mm (fn* ([] 0) ([x & more] (apply + x more))))", "(list (mm) (mm 1 2 3))"],
+            &["(def! a (atom 1))", "(swap! a (fn* [v] (+ v 10)))"],
+            &["(def! add (fn* [a b] (+ a b)))", "(add 1 2)", "(def! + -)", "(add 5 3)"],
+            &["(defmacro! m (fn* [x] (list 'dec x)))", "(def! h (fn* [] (m 5)))", "(h)", "(defmacro! m (fn* [x] (list '* x x)))", "(h)"],
+            &["(def! k (fn* [a] (let* [b 1] (do (eval* '(def! a 5)) a))))", "(k 2)"],
+            &["(def! e1 (fn* [] (nope)))", "(def! e2 (fn* [] (e1)))", "(+ 1 (e2))"],
+            &["(def! thrower (fn* [x] (throw x)))", "(+ 1 (thrower 5))"],
+            &["((fn* [x] x))"],
+            &["(let* [x 1 y (throw \"boom\")] x)"],
+        ];
+        for programs in cases {
+            assert_eq!(
+                outcome(super::eval, programs),
+                outcome(eval::eval, programs),
+                "{programs:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,522 @@
+use std::rc::Rc;
+
+use crate::{
+    ast::{interner::SymbolId, pattern::Pattern, Expr, Function, FunctionArity},
+    environment::{Env, Loop},
+    span::Span,
+};
+
+use super::{
+    super::{
+        bind_args,
+        builtins::{builtin, eval_quasiquote_expand, parse_fn, parse_loop},
+        compile::{declare, parameters, Variable},
+        eval_maybe_macro, EvalError, EvalResult,
+    },
+    Chunk, Expected, Head, Op,
+};
+
+/// Where the value of an expression goes.
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    /// Returned from the frame, so calls can replace it.
+    tail: bool,
+    /// Evaluated again as the value of a macro call it's the expansion of.
+    reevaluated: bool,
+}
+
+impl Position {
+    const VALUE: Self = Self {
+        tail: false,
+        reevaluated: false,
+    };
+    const TAIL: Self = Self {
+        tail: true,
+        reevaluated: false,
+    };
+}
+
+/// `loop*` whose body is being compiled.
+struct LoopBody {
+    start: u32,
+    bindings: usize,
+}
+
+pub(super) fn compile_function(arity: &FunctionArity, closure: &Env) -> Chunk {
+    Compiler::new(closure, vec![parameters(arity)]).finish(&arity.expr)
+}
+
+pub(super) struct Compiler<'a> {
+    /// Scope the code runs in, or the one the function closes over, telling what
+    /// names it doesn't bind refer to. Only a hint, since they can be redefined.
+    closure: &'a Env,
+    /// Names bound by the scopes the code creates, from its parameters to the innermost.
+    scopes: Vec<Vec<SymbolId>>,
+    loops: Vec<LoopBody>,
+    /// Name of the innermost call the tree walker attributes errors to.
+    span: Option<Rc<Span>>,
+    chunk: Chunk,
+}
+
+impl<'a> Compiler<'a> {
+    pub(super) fn new(closure: &'a Env, scopes: Vec<Vec<SymbolId>>) -> Self {
+        Self {
+            closure,
+            scopes,
+            loops: vec![],
+            span: None,
+            chunk: Chunk::default(),
+        }
+    }
+
+    pub(super) fn finish(mut self, expr: &Expr) -> Chunk {
+        self.compile(expr, Position::TAIL);
+        self.emit(Op::Return);
+        self.chunk
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.spans.push(self.span.clone());
+        self.chunk.code.len() - 1
+    }
+
+    fn here(&self) -> u32 {
+        index(self.chunk.code.len())
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch(&mut self, at: usize) {
+        let here = self.here();
+        match &mut self.chunk.code[at] {
+            Op::Jump(target) | Op::JumpIfFalse(target) => *target = here,
+            Op::Guard { end, .. } | Op::Dispatch { end, .. } | Op::Try { end, .. } => *end = here,
+            op => unreachable!("{op:?} doesn't jump"),
+        }
+    }
+
+    /// Compiles code the tree walker evaluates as part of the call named `head`,
+    /// attributing its errors to it.
+    fn part_of<R>(&mut self, head: &Expr, compile: impl FnOnce(&mut Self) -> R) -> R {
+        let outer = match head.symbol_span() {
+            Some(span) => self.span.replace(Rc::clone(span)),
+            None => self.span.clone(),
+        };
+        let result = compile(self);
+        self.span = outer;
+        result
+    }
+
+    fn compile(&mut self, expr: &Expr, position: Position) {
+        match expr {
+            Expr::Symbol(symbol) => {
+                let variable = Variable::new(symbol, &self.scopes);
+                let variable = push(&mut self.chunk.variables, variable);
+                self.emit(Op::Get(variable));
+            }
+            Expr::List(list) if !list.is_empty() => self.list(expr, list, position),
+            Expr::Vector(vector) => {
+                for item in vector.iter() {
+                    self.compile(item, Position::VALUE);
+                }
+                self.emit(Op::Vector(index(vector.len())));
+            }
+            Expr::Map(map) => {
+                for (key, value) in map.iter() {
+                    self.compile(key.as_expr(), Position::VALUE);
+                    self.compile(value, Position::VALUE);
+                }
+                self.emit(Op::Map(index(map.len())));
+            }
+            Expr::Set(set) => {
+                for key in set.iter() {
+                    self.compile(key.as_expr(), Position::VALUE);
+                }
+                self.emit(Op::Set(index(set.len())));
+            }
+            Expr::WithMeta { .. } => self.fallback(expr),
+            expr => self.constant(expr.clone()),
+        }
+    }
+
+    fn constant(&mut self, expr: Expr) {
+        let constant = push(&mut self.chunk.constants, expr);
+        self.emit(Op::Constant(constant));
+    }
+
+    /// Leaves `expr` to the tree walker.
+    fn fallback(&mut self, expr: &Expr) {
+        let constant = push(&mut self.chunk.constants, expr.clone());
+        self.emit(Op::Eval(constant));
+    }
+
+    /// How many of the scopes, innermost first, don't bind `name`.
+    fn depth(&self, name: SymbolId) -> usize {
+        self.scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains(&name))
+            .unwrap_or(self.scopes.len())
+    }
+
+    /// What the head of a call currently refers to, if the code doesn't bind it.
+    fn resolve(&self, head: &Expr) -> Option<Expr> {
+        match head {
+            Expr::Symbol(symbol) if self.depth(symbol.id()) == self.scopes.len() => {
+                self.closure.get(symbol).map(Expr::into_no_meta)
+            }
+            Expr::BuiltinFunction(_) => Some(head.clone()),
+            _ => None,
+        }
+    }
+
+    fn list(&mut self, expr: &Expr, list: &[Expr], position: Position) {
+        let (head, args) = list.split_first().expect("calls aren't empty");
+        match (self.resolve(head), head) {
+            (Some(Expr::BuiltinFunction(name)), _) => {
+                self.builtin(expr, head, name, args, position)
+            }
+            (Some(Expr::Function(f)), Expr::Symbol(_)) if f.is_macro => {
+                self.macro_call(expr, head, f, args, position)
+            }
+            _ => self.call(expr, head, args, position),
+        }
+    }
+
+    fn call(&mut self, expr: &Expr, head: &Expr, args: &[Expr], position: Position) {
+        let call = push(&mut self.chunk.calls, expr.clone());
+        let dispatch = self.part_of(head, |c| {
+            c.compile(head, Position::VALUE);
+            let dispatch = c.emit(Op::Dispatch { call, end: 0 });
+            for arg in args {
+                c.compile(arg, Position::VALUE);
+            }
+            dispatch
+        });
+        self.emit(match position.tail {
+            true => Op::TailCall(call),
+            false => Op::Call(call),
+        });
+        self.patch(dispatch);
+    }
+
+    /// Compiles code that runs if the head of `expr` still refers to what it did
+    /// when compiling, and leaves the call to the tree walker otherwise.
+    fn guarded(&mut self, expr: &Expr, expected: Expected, compile: impl FnOnce(&mut Self)) {
+        let head = match expr {
+            Expr::List(list) => &list[0],
+            _ => unreachable!("calls are lists"),
+        };
+        let guard = match head {
+            Expr::Symbol(symbol) => {
+                let guarded = Head {
+                    variable: Variable {
+                        symbol: symbol.clone(),
+                        depth: self.scopes.len(),
+                        slot: None,
+                    },
+                    expected,
+                    call: expr.clone(),
+                };
+                let guarded = push(&mut self.chunk.heads, guarded);
+                Some(self.part_of(head, |c| {
+                    c.emit(Op::Guard {
+                        head: guarded,
+                        end: 0,
+                    })
+                }))
+            }
+            // put into the code by `quasiquote`, can't be redefined
+            _ => None,
+        };
+        compile(self);
+        if let Some(guard) = guard {
+            self.patch(guard);
+        }
+    }
+
+    /// Expands a macro call once, compiling the expansion as if it was written
+    /// in place of the call.
+    fn macro_call(
+        &mut self,
+        expr: &Expr,
+        head: &Expr,
+        f: Function,
+        args: &[Expr],
+        position: Position,
+    ) {
+        let expansion = match expand(&f, args) {
+            Ok(expansion) => expansion,
+            // reported when the call is evaluated, if it ever is
+            Err(_) => return self.fallback(expr),
+        };
+
+        self.guarded(expr, Expected::Macro(f), |c| {
+            let reeval = !position.reevaluated;
+            if reeval && position.tail {
+                c.part_of(head, |c| c.emit(Op::ReevalResult));
+            }
+            let inner = Position {
+                reevaluated: true,
+                ..position
+            };
+            c.compile(&expansion, inner);
+            if reeval && !position.tail {
+                c.emit(Op::Reeval);
+            }
+        });
+    }
+
+    /// Compiles a call to the builtin `name`, falling back to the tree walker if the
+    /// arguments are malformed, for it to report the error.
+    fn builtin(
+        &mut self,
+        expr: &Expr,
+        head: &Expr,
+        name: SymbolId,
+        args: &[Expr],
+        position: Position,
+    ) {
+        let expected = Expected::Builtin(name);
+        match (name.name(), args) {
+            ("if", [cond, then, otherwise @ ..]) if otherwise.len() <= 1 => {
+                self.guarded(expr, expected, |c| {
+                    let to_otherwise = c.part_of(head, |c| {
+                        c.compile(cond, Position::VALUE);
+                        c.emit(Op::JumpIfFalse(0))
+                    });
+                    c.compile(then, position);
+                    let to_end = c.emit(Op::Jump(0));
+                    c.patch(to_otherwise);
+                    match otherwise {
+                        [otherwise] => c.compile(otherwise, position),
+                        _ => c.constant(Expr::Nil),
+                    }
+                    c.patch(to_end);
+                })
+            }
+            ("do", []) => self.guarded(expr, expected, |c| c.constant(Expr::Nil)),
+            ("do", [init @ .., last]) => self.guarded(expr, expected, |c| {
+                c.part_of(head, |c| {
+                    for e in init {
+                        c.compile(e, Position::VALUE);
+                        c.emit(Op::Pop);
+                    }
+                });
+                c.compile(last, position);
+            }),
+            ("let*", [vars, body]) => self.let_form(expr, head, name, vars, body, position),
+            ("loop*", _) => match parse_loop(args, self.closure) {
+                Ok((target, values)) => self.guarded(expr, expected, |c| {
+                    c.loop_form(head, target.into(), &values, position)
+                }),
+                Err(_) => self.fallback(expr),
+            },
+            ("recur", _) => match self.loops.last() {
+                Some(target) if target.bindings == args.len() => {
+                    let start = target.start;
+                    self.guarded(expr, expected, |c| {
+                        c.part_of(head, |c| {
+                            for arg in args {
+                                c.compile(arg, Position::VALUE);
+                            }
+                            c.emit(Op::Recur {
+                                args: index(args.len()),
+                                body: start,
+                            });
+                        })
+                    })
+                }
+                _ => self.fallback(expr),
+            },
+            ("fn*", _) => match parse_fn(args) {
+                Ok(arities) => {
+                    let function = push(&mut self.chunk.functions, arities.into());
+                    self.guarded(expr, expected, |c| {
+                        c.part_of(head, |c| c.emit(Op::Closure(function)));
+                    })
+                }
+                Err(_) => self.fallback(expr),
+            },
+            ("quote", [quoted]) => self.guarded(expr, expected, |c| c.constant(quoted.clone())),
+            ("quasiquote", _) => match eval_quasiquote_expand(args, self.closure) {
+                Ok(expansion) => self.guarded(expr, expected, |c| c.compile(&expansion, position)),
+                Err(_) => self.fallback(expr),
+            },
+            ("def!" | "defmacro!", [Expr::Symbol(symbol), value]) => {
+                let def = match name.name() {
+                    "def!" => Op::Def,
+                    _ => Op::DefMacro,
+                };
+                let def = def(push(&mut self.chunk.names, symbol.clone()));
+                self.guarded(expr, expected, |c| {
+                    c.part_of(head, |c| {
+                        c.compile(value, Position::VALUE);
+                        c.emit(def);
+                    })
+                })
+            }
+            ("def!", [pattern @ (Expr::List(_) | Expr::Vector(_) | Expr::Map(_)), value]) => {
+                match Pattern::parse(pattern) {
+                    Ok(pattern) => {
+                        let pattern = push(&mut self.chunk.patterns, pattern);
+                        self.guarded(expr, expected, |c| {
+                            c.part_of(head, |c| {
+                                c.compile(value, Position::VALUE);
+                                c.emit(Op::DefPattern(pattern));
+                            })
+                        })
+                    }
+                    Err(_) => self.fallback(expr),
+                }
+            }
+            ("try*", [body]) => self.guarded(expr, expected, |c| {
+                c.part_of(head, |c| c.compile(body, Position::VALUE))
+            }),
+            ("try*", [body, catch]) => match parse_catch(catch) {
+                Some(arities) => {
+                    let catch = push(&mut self.chunk.functions, arities);
+                    self.guarded(expr, expected, |c| {
+                        c.part_of(head, |c| {
+                            let start = c.emit(Op::Try { catch, end: 0 });
+                            c.compile(body, Position::VALUE);
+                            c.emit(Op::EndTry);
+                            c.patch(start);
+                        })
+                    })
+                }
+                None => self.fallback(expr),
+            },
+            (builtin_name, _) => match builtin(name) {
+                // the ones taking unevaluated arguments are handled above, or left
+                // to the tree walker
+                Some(f) if !matches!(builtin_name, "macroexpand" | "quasiquoteexpand") => {
+                    let builtin = push(&mut self.chunk.builtins, f);
+                    self.guarded(expr, expected, |c| {
+                        c.part_of(head, |c| {
+                            for arg in args {
+                                c.compile(arg, Position::VALUE);
+                            }
+                            c.emit(Op::Builtin {
+                                builtin,
+                                args: index(args.len()),
+                            });
+                        })
+                    })
+                }
+                _ => self.fallback(expr),
+            },
+        }
+    }
+
+    fn let_form(
+        &mut self,
+        expr: &Expr,
+        head: &Expr,
+        name: SymbolId,
+        vars: &Expr,
+        body: &Expr,
+        position: Position,
+    ) {
+        let Some(vars) = vars.as_list_like().filter(|vars| vars.len() % 2 == 0) else {
+            return self.fallback(expr);
+        };
+        let vars = vars.to_slice();
+        let Ok(patterns) = vars
+            .iter()
+            .step_by(2)
+            .map(Pattern::parse)
+            .collect::<EvalResult<Vec<_>>>()
+        else {
+            return self.fallback(expr);
+        };
+
+        self.guarded(expr, Expected::Builtin(name), |c| {
+            c.scopes.push(vec![]);
+            c.part_of(head, |c| {
+                c.emit(Op::Scope);
+                for (pattern, value) in patterns.into_iter().zip(vars.iter().skip(1).step_by(2)) {
+                    // the value can only see the names bound before it
+                    c.compile(value, Position::VALUE);
+                    c.bind(pattern);
+                }
+            });
+            c.compile(body, position);
+            c.scopes.pop();
+            if !position.tail {
+                c.emit(Op::Unscope);
+            }
+        })
+    }
+
+    fn loop_form(&mut self, head: &Expr, target: Rc<Loop>, values: &[Expr], position: Position) {
+        let target_index = push(&mut self.chunk.loops, Rc::clone(&target));
+        self.scopes.push(vec![]);
+        self.part_of(head, |c| {
+            c.emit(Op::Loop(target_index));
+            for (pattern, value) in target.bindings.iter().zip(values) {
+                c.compile(value, Position::VALUE);
+                c.bind(pattern.clone());
+            }
+        });
+
+        self.loops.push(LoopBody {
+            start: self.here(),
+            bindings: target.bindings.len(),
+        });
+        self.compile(&target.body, position);
+        self.loops.pop();
+        self.scopes.pop();
+        if !position.tail {
+            self.emit(Op::Unscope);
+        }
+    }
+
+    /// Binds the value on top of the stack in the innermost scope.
+    fn bind(&mut self, pattern: Pattern) {
+        let scope = self.scopes.last_mut().expect("a scope was pushed");
+        declare(scope, pattern.names());
+        let pattern = push(&mut self.chunk.patterns, pattern);
+        self.emit(Op::Bind(pattern));
+    }
+}
+
+/// Expands a call of the macro `f` once, like the tree walker does before evaluating
+/// the expansion.
+fn expand(f: &Function, args: &[Expr]) -> EvalResult<Expr> {
+    let arity = f
+        .find_arity(args.len())
+        .ok_or_else(|| EvalError::InvalidArgumentCount(f.arities()))?;
+    let env = bind_args(f, arity, args.iter().cloned().map(Ok))?;
+    eval_maybe_macro(&arity.expr, &env, false)
+}
+
+/// Parses `(catch* name expr)` into the function `try*` calls with the exception.
+fn parse_catch(catch: &Expr) -> Option<Rc<[FunctionArity]>> {
+    let Expr::List(catch) = catch.as_no_meta() else {
+        return None;
+    };
+    let [catch, name, expr] = &catch[..] else {
+        return None;
+    };
+    if catch.as_no_meta().as_symbol() != Some("catch*") {
+        return None;
+    }
+    let Expr::Symbol(name) = name.as_no_meta() else {
+        return None;
+    };
+    Some(Rc::new([FunctionArity::new(
+        vec![Pattern::Symbol(name.id())],
+        None,
+        Rc::new(expr.clone()),
+    )]))
+}
+
+fn push<T>(pool: &mut Vec<T>, item: T) -> u32 {
+    pool.push(item);
+    index(pool.len() - 1)
+}
+
+fn index(i: usize) -> u32 {
+    u32::try_from(i).expect("chunks are smaller than 4G instructions")
+}
@@ -1,10 +1,24 @@
 use std::borrow::Cow;
 use std::fmt::{self, Write};
+use std::rc::Rc;
+
+use crate::span::{Source, Span};
 
 #[derive(Debug)]
 pub struct Lexer<'source> {
     source: &'source str,
     index: usize,
+    origin: Rc<Source>,
+    cursor: Cursor,
+}
+
+/// Last position translated into a line and column, so that computing spans
+/// doesn't rescan the source from the start for every token.
+#[derive(Debug, Default)]
+struct Cursor {
+    index: usize,
+    line: u32,
+    line_start: usize,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -40,44 +54,87 @@ impl fmt::Display for Token<'_> {
 
 impl<'a> Lexer<'a> {
     pub fn new(source: &'a str) -> Self {
-        Self { source, index: 0 }
+        Self::with_name(source, None)
     }
-}
-impl<'a> Iterator for Lexer<'a> {
-    type Item = Token<'a>;
-    fn next(&mut self) -> Option<Self::Item> {
+
+    pub fn with_name(source: &'a str, name: Option<&str>) -> Self {
+        Self {
+            source,
+            index: 0,
+            origin: Source::new(source, name),
+            cursor: Cursor {
+                line: 1,
+                ..Default::default()
+            },
+        }
+    }
+
+    pub fn spanned(self) -> Spanned<'a> {
+        Spanned(self)
+    }
+
+    pub fn next_spanned(&mut self) -> Option<(Token<'a>, Span)> {
         loop {
-            match self.curr()? {
+            let start = self.index;
+            let token = match self.curr()? {
                 b'~' => {
                     self.eat(1);
                     if self.curr() == Some(b'@') {
                         self.eat(1);
-                        break Some(Token::Special([b'~', b'@']));
+                        Token::Special([b'~', b'@'])
                     } else {
-                        break Some(Token::Special([b'~', b'\0']));
+                        Token::Special([b'~', b'\0'])
                     }
                 }
+                b'#' if self.source.as_bytes().get(self.index + 1) == Some(&b'{') => {
+                    self.eat(2);
+                    Token::Special([b'#', b'{'])
+                }
                 b if SPECIAL.contains(&b) => {
                     self.eat(1);
-                    break Some(Token::Special([b, b'\0']));
+                    Token::Special([b, b'\0'])
+                }
+                b'"' => self.eat_string().map_or_else(Token::Error, Token::String),
+                b';' => {
+                    self.eat_comment();
+                    continue;
+                }
+                b if b == b',' || b.is_ascii_whitespace() => {
+                    self.eat(1);
+                    continue;
                 }
-                b'"' => break Some(self.eat_string().map_or_else(Token::Error, Token::String)),
-                b';' => self.eat_comment(),
-                b',' => self.eat(1),
-                b if b.is_ascii_whitespace() => self.eat(1),
                 _ => {
                     let atom = self.eat_atom()?;
-                    let token = match atom.strip_prefix(':') {
+                    match atom.strip_prefix(':') {
                         Some(kw) => Token::Keyword(kw),
                         None => Token::Atom(atom),
-                    };
-                    break Some(token);
+                    }
                 }
-            }
+            };
+
+            break Some((token, self.span(start)));
         }
     }
 }
 
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_spanned().map(|(token, _)| token)
+    }
+}
+
+/// Iterator over tokens together with their [`Span`]s.
+#[derive(Debug)]
+pub struct Spanned<'a>(Lexer<'a>);
+
+impl<'a> Iterator for Spanned<'a> {
+    type Item = (Token<'a>, Span);
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next_spanned()
+    }
+}
+
 impl<'a> Lexer<'a> {
     fn curr(&self) -> Option<u8> {
         self.source.as_bytes().get(self.index).copied()
@@ -87,6 +144,29 @@ impl<'a> Lexer<'a> {
         self.index += how_many;
     }
 
+    fn span(&mut self, start: usize) -> Span {
+        let cursor = &mut self.cursor;
+        for (i, b) in self.source[cursor.index..start].bytes().enumerate() {
+            if b == b'\n' {
+                cursor.line += 1;
+                cursor.line_start = cursor.index + i + 1;
+            }
+        }
+        cursor.index = start;
+
+        let column = self.source[cursor.line_start..start].chars().count() + 1;
+        let len = self.source[start..self.index]
+            .chars()
+            .take_while(|&c| c != '\n')
+            .count();
+        Span {
+            source: Rc::clone(&self.origin),
+            line: cursor.line,
+            column: column as u32,
+            len: len as u32,
+        }
+    }
+
     fn eat_comment(&mut self) {
         self.index = self.source[self.index..]
             .find('\n')
@@ -98,7 +178,7 @@ impl<'a> Lexer<'a> {
         self.eat(1); // `"`
         let source = &self.source[self.index..];
 
-        let i = source.find(&['\\', '"']).ok_or_else(str_eof)?;
+        let i = source.find(['\\', '"']).ok_or_else(str_eof)?;
         if source.as_bytes()[i] == b'\\' {
             self.eat_escaped_string(i).map(Cow::Owned)
         } else {
@@ -124,7 +204,7 @@ impl<'a> Lexer<'a> {
 
             let subsource = &source[index..];
 
-            let i = subsource.find(&['\\', '"']).ok_or_else(str_eof)?;
+            let i = subsource.find(['\\', '"']).ok_or_else(str_eof)?;
             escaped.push_str(&subsource[..i]);
             index += i + 1;
             if subsource.as_bytes()[i] == b'\\' {
@@ -187,6 +267,9 @@ mod tests {
             ("~@1", &[S([b'~', b'@']), A("1")][..]),
             ("~ @1", &[S([b'~', b'\0']), S([b'@', b'\0']), A("1")][..]),
             ("~,@,1", &[S([b'~', b'\0']), S([b'@', b'\0']), A("1")][..]),
+            ("#{1}", &[S([b'#', b'{']), A("1"), S([b'}', b'\0'])][..]),
+            ("# {", &[A("#"), S([b'{', b'\0'])][..]),
+            ("##Inf", &[A("##Inf")][..]),
         ];
 
         for (input, expected) in cases {
@@ -290,4 +373,24 @@ mod tests {
             assert_eq!(lexed.as_slice(), expected);
         }
     }
+
+    #[test]
+    fn spans() {
+        let source = "(a \"b\nc\"\n  ; comment\n  :d)";
+        let lexed: Vec<_> = Lexer::new(source)
+            .spanned()
+            .map(|(token, span)| (token.to_string(), span.line, span.column, span.len))
+            .collect();
+
+        assert_eq!(
+            lexed,
+            [
+                ("(".into(), 1, 1, 1),
+                ("a".into(), 1, 2, 1),
+                (r#""b\nc""#.into(), 1, 4, 2),
+                (":d".into(), 4, 3, 2),
+                (")".into(), 4, 5, 1),
+            ]
+        );
+    }
 }
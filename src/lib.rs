@@ -8,3 +8,4 @@ pub mod eval;
 pub mod lexer;
 pub mod parser;
 pub mod repl;
+pub mod span;
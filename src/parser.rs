@@ -1,9 +1,17 @@
 use std::{iter::Peekable, rc::Rc};
 
 use crate::{
-    ast::{Expr, Keyword},
-    eval::builtins::list_to_hash_map,
-    lexer::{Lexer, Token},
+    ast::{
+        interner::SymbolId,
+        number::{parse_float, parse_ratio, BigInt, Number},
+        Expr, Keyword, MapKey, Symbol,
+    },
+    eval::{
+        builtins::list_to_hash_map,
+        limits::{self, Limit},
+    },
+    lexer::{self, Lexer, Token},
+    span::{Span, Spanned},
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -20,52 +28,126 @@ pub enum ParseError {
     LexError(String),
     #[error("{0}")]
     MapError(String),
+    #[error("invalid number: {0}")]
+    InvalidNumber(String),
     #[error("internal error: unknown token")]
     UnknownToken,
     #[error("internal error: {0}")]
     InternalError(String),
+    #[error("{0} limit exceeded")]
+    LimitExceeded(Limit),
+    #[error("{error}")]
+    At {
+        error: Box<ParseError>,
+        span: Rc<Span>,
+    },
+}
+
+impl ParseError {
+    fn at(self, span: Span) -> Self {
+        match self {
+            error @ Self::At { .. } => error,
+            error => Self::At {
+                error: Box::new(error),
+                span: Rc::new(span),
+            },
+        }
+    }
+
+    /// Location of the offending token, if known.
+    pub fn span(&self) -> Option<&Rc<Span>> {
+        match self {
+            Self::At { span, .. } => Some(span),
+            _ => None,
+        }
+    }
+
+    pub fn without_span(&self) -> &Self {
+        match self {
+            Self::At { error, .. } => error,
+            error => error,
+        }
+    }
 }
 
 pub type ParseResult<T> = std::result::Result<T, ParseError>;
 
+type Tokens<'a> = Peekable<lexer::Spanned<'a>>;
+
 pub fn parse(s: &str) -> ParseResult<Expr> {
-    let mut lexer = Lexer::new(s).peekable();
+    let mut lexer = Lexer::new(s).spanned().peekable();
     let expr = parse_term(&mut lexer)?;
+    let next = lexer.peek().map(|(_, span)| span.clone());
     match parse_term(&mut lexer) {
-        Ok(_) => Err(ParseError::UnexpectedTerm),
+        Ok(_) => Err(ParseError::UnexpectedTerm.at(next.expect("a term was parsed"))),
         Err(ParseError::Empty) => Ok(expr),
         Err(e) => Err(e),
     }
 }
 
-fn parse_term(lexer: &mut Peekable<Lexer<'_>>) -> ParseResult<Expr> {
-    let token = lexer.next().ok_or(ParseError::Empty)?;
-    match token {
-        Token::Atom(atom) => parse_atom(atom),
+/// Parses every form in `s`, attributing their spans to the file `name`.
+pub fn parse_all(s: &str, name: &str) -> ParseResult<Vec<Expr>> {
+    let mut lexer = Lexer::with_name(s, Some(name)).spanned().peekable();
+    let mut exprs = vec![];
+    loop {
+        match parse_term(&mut lexer) {
+            Ok(expr) => exprs.push(expr),
+            Err(ParseError::Empty) => break Ok(exprs),
+            Err(e) => break Err(e),
+        }
+    }
+}
+
+fn parse_term(lexer: &mut Tokens<'_>) -> ParseResult<Expr> {
+    let (token, span) = lexer.next().ok_or(ParseError::Empty)?;
+    let result = match token {
+        Token::Atom(atom) => parse_atom(atom, span.clone()),
         Token::Keyword(k) => Ok(Expr::Keyword(Keyword::new(k))),
-        Token::Special([b'~', b'@']) => parse_special_form(lexer, "splice-unquote"),
-        Token::Special([b'~', b'\0']) => parse_special_form(lexer, "unquote"),
-        Token::Special([b'`', _]) => parse_special_form(lexer, "quasiquote"),
-        Token::Special([b'^', _]) => parse_special_form(lexer, "with-meta"),
-        Token::Special([b'@', _]) => parse_special_form(lexer, "deref"),
-        Token::Special([b'\'', _]) => parse_special_form(lexer, "quote"),
-        Token::Special([b'(', _]) => parse_list(lexer, b')'),
-        Token::Special([b'[', _]) => parse_list(lexer, b']'),
-        Token::Special([b'{', _]) => parse_list(lexer, b'}'),
+        Token::Special([b'~', b'@']) => parse_special_form(lexer, "splice-unquote", &span),
+        Token::Special([b'~', b'\0']) => parse_special_form(lexer, "unquote", &span),
+        Token::Special([b'`', _]) => parse_special_form(lexer, "quasiquote", &span),
+        Token::Special([b'^', _]) => parse_special_form(lexer, "with-meta", &span),
+        Token::Special([b'@', _]) => parse_special_form(lexer, "deref", &span),
+        Token::Special([b'\'', _]) => parse_special_form(lexer, "quote", &span),
+        Token::Special([b'(', _]) => parse_list(lexer, b')', span.clone()),
+        Token::Special([b'[', _]) => parse_list(lexer, b']', span.clone()),
+        Token::Special([b'{', _]) => parse_list(lexer, b'}', span.clone()),
+        Token::Special([b'#', b'{']) => parse_items(lexer, b'}')
+            .map(|items| items.into_iter().map(MapKey::new).collect())
+            .map(|set| Spanned::with_span(set, span.clone()))
+            .map(Rc::new)
+            .map(Expr::Set),
         Token::Special([b')', _]) => Err(ParseError::UnmatchedDelimiter(')')),
         Token::Special([b']', _]) => Err(ParseError::UnmatchedDelimiter(']')),
         Token::Special([b'}', _]) => Err(ParseError::UnmatchedDelimiter('}')),
         Token::String(s) => Ok(Expr::String(s.into_owned())),
         Token::Error(e) => Err(ParseError::LexError(e)),
         _ => Err(ParseError::UnknownToken),
-    }
+    };
+    // errors without a more specific location point to the token they started at, like
+    // the opening delimiter of a list missing its closing one
+    result.map_err(|e| e.at(span))
 }
 
-fn parse_atom(atom: &str) -> Result<Expr, ParseError> {
+fn parse_atom(atom: &str, span: Span) -> Result<Expr, ParseError> {
     if let Ok(num) = atom.parse() {
         return Ok(Expr::Int(num));
     }
 
+    if let Ok(num) = atom.parse::<BigInt>() {
+        return Ok(Expr::BigInt(Rc::new(num)));
+    }
+
+    if let Some((numer, denom)) = parse_ratio(atom) {
+        return Number::ratio(numer, denom)
+            .map(Number::to_expr)
+            .ok_or_else(|| ParseError::InvalidNumber(atom.into()));
+    }
+
+    if let Some(num) = parse_float(atom) {
+        return Ok(Expr::Float(num));
+    }
+
     if let Ok(num) = atom.parse() {
         return Ok(Expr::Bool(num));
     }
@@ -74,35 +156,59 @@ fn parse_atom(atom: &str) -> Result<Expr, ParseError> {
         return Ok(Expr::Nil);
     }
 
-    Ok(Expr::Symbol(atom.into()))
+    let id = SymbolId::try_intern(atom).map_err(ParseError::LimitExceeded)?;
+    Ok(Expr::Symbol(Symbol::with_span(id, span)))
 }
 
-fn parse_special_form(lexer: &mut Peekable<Lexer<'_>>, name: &'static str) -> ParseResult<Expr> {
-    let expr = parse_term(lexer)?;
-    if name == "with-meta" {
-        let meta = parse_term(lexer)?;
-        Ok(Expr::List(vec![Expr::BuiltinFunction(name), meta, expr]))
+/// Expands a reader macro into a call to `name`, located at the macro's character.
+fn parse_special_form(
+    lexer: &mut Tokens<'_>,
+    name: &'static str,
+    span: &Span,
+) -> ParseResult<Expr> {
+    let head = Expr::BuiltinFunction(SymbolId::intern(name));
+    let expr = parse_operand(lexer)?;
+    let items = if name == "with-meta" {
+        vec![head, parse_operand(lexer)?, expr]
     } else {
-        Ok(Expr::List(vec![Expr::BuiltinFunction(name), expr]))
+        vec![head, expr]
+    };
+    Ok(Expr::List(Spanned::with_span(items.into(), span.clone())))
+}
+
+/// Parses the term a reader macro like `'` applies to, which must follow it.
+fn parse_operand(lexer: &mut Tokens<'_>) -> ParseResult<Expr> {
+    match parse_term(lexer) {
+        Err(ParseError::Empty) => Err(ParseError::UnexpectedEof),
+        result => result,
     }
 }
 
-fn parse_list(lexer: &mut Peekable<Lexer<'_>>, end: u8) -> ParseResult<Expr> {
+fn parse_items(lexer: &mut Tokens<'_>, end: u8) -> ParseResult<Vec<Expr>> {
     let mut list = vec![];
     loop {
         match lexer.peek().ok_or(ParseError::UnexpectedEof)? {
-            Token::Special([s, _]) if *s == end => {
+            (Token::Special([s, _]), _) if *s == end => {
                 lexer.next();
                 break;
             }
-            _ => list.push(parse_term(&mut *lexer)?),
+            _ => {
+                limits::count_elements(1).map_err(ParseError::LimitExceeded)?;
+                list.push(parse_term(&mut *lexer)?)
+            }
         }
     }
+    Ok(list)
+}
+
+fn parse_list(lexer: &mut Tokens<'_>, end: u8, span: Span) -> ParseResult<Expr> {
+    let list = parse_items(lexer, end)?;
 
     match end {
-        b')' => Ok(Expr::List(list)),
-        b']' => Ok(Expr::Vector(list)),
+        b')' => Ok(Expr::List(Spanned::with_span(list.into(), span))),
+        b']' => Ok(Expr::Vector(Spanned::with_span(list.into(), span))),
         b'}' => list_to_hash_map(&list)
+            .map(|map| Spanned::with_span(map.into_inner(), span))
             .map(Rc::new)
             .map(Expr::Map)
             .map_err(|e| ParseError::MapError(e.to_string())),
@@ -111,3 +217,75 @@ fn parse_list(lexer: &mut Peekable<Lexer<'_>>, end: u8) -> ParseResult<Expr> {
         )),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{parse, parse_all, ParseError};
+    use crate::{
+        ast::Expr,
+        environment::Environment,
+        eval::{eval, EvalError},
+        span::Span,
+    };
+
+    fn error_at(src: &str) -> (String, String) {
+        let err = parse(src).unwrap_err();
+        let span = err.span().expect("errors point to a token");
+        (err.to_string(), format!("{}:{}", span.line, span.column))
+    }
+
+    #[test]
+    fn errors_have_spans() {
+        for (src, message, at) in [
+            ("(+ 1\n  (- 2)", "unexpected EOF", "1:1"),
+            ("(+ 1\n  (- 2", "unexpected EOF", "2:3"),
+            ("[1 2)", "unmatched delimiter: ')'", "1:5"),
+            ("1 2", "too many terms", "1:3"),
+            ("(quote\n ')", "unmatched delimiter: ')'", "2:3"),
+            ("'", "unexpected EOF", "1:1"),
+            ("{:a}", "invalid number of arguments", "1:1"),
+            ("(1/0)", "invalid number: 1/0", "1:2"),
+        ] {
+            assert_eq!(error_at(src), (message.into(), at.into()), "{src}");
+        }
+        assert!(matches!(parse(""), Err(ParseError::Empty)));
+    }
+
+    #[test]
+    fn collections_have_spans() {
+        let Expr::List(list) = parse("(f\n  [1 2]\n  {:a 1} #{1} 'x)").unwrap() else {
+            panic!("expected a list");
+        };
+        let at = |span: Option<&Rc<Span>>| span.unwrap().to_string();
+        assert_eq!(at(list.span()), "<input>:1:1");
+        assert_eq!(at(list.call_span()), "<input>:1:2");
+        match &list[..] {
+            [_, Expr::Vector(v), Expr::Map(m), Expr::Set(s), Expr::List(quote)] => {
+                assert_eq!(at(v.span()), "<input>:2:3");
+                assert_eq!(at(m.span()), "<input>:3:3");
+                assert_eq!(at(s.span()), "<input>:3:10");
+                assert_eq!(at(quote.call_span()), "<input>:3:15");
+            }
+            items => panic!("unexpected items {items:?}"),
+        }
+        // spans don't take part in comparisons
+        assert_eq!(parse("(f [1 2])").unwrap(), parse(" (f  [1 2] )").unwrap());
+    }
+
+    #[test]
+    fn reader_macro_errors_point_to_the_macro() {
+        let env = Environment::with_builtins();
+        let err = eval(&parse("(list 1\n  @2)").unwrap(), &env).unwrap_err();
+        assert_eq!(err.span().unwrap().to_string(), "<input>:2:3");
+    }
+
+    #[test]
+    fn file_errors_point_into_the_file() {
+        let err = parse_all("(def! a 1)\n(def! f (fn* [x]\n  (+ x 1))))", "lib.mal").unwrap_err();
+        let err = EvalError::from(err);
+        assert_eq!(err.to_string(), "parsing error: unmatched delimiter: ')'");
+        assert_eq!(err.span().unwrap().to_string(), "lib.mal:3:12");
+    }
+}
@@ -1,18 +1,60 @@
-use std::io::{self, Write};
+use std::{
+    cell::Cell,
+    io::{self, Write},
+    rc::Rc,
+};
 
 use crate::{
     ast::Expr,
     environment::{Env, Environment},
-    eval::{self, EvalError},
+    eval::{
+        self,
+        trace::{Frame, StackTrace},
+        EvalError,
+    },
     parser::{self, ParseError},
+    span::Span,
 };
 
 use self::repl_funcs::{NoPrint, ReplFuncs, WithStaticInput};
 
 pub mod repl_funcs;
 
+/// Flag of [`main`] selecting [`Engine::Vm`], given before the program file.
+pub const VM_FLAG: &str = "--vm";
+
+/// What [`execute_eval`] evaluates expressions with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// [`eval::eval`].
+    #[default]
+    TreeWalker,
+    /// [`eval::vm::eval`].
+    Vm,
+}
+
+thread_local! {
+    static ENGINE: Cell<Engine> = const { Cell::new(Engine::TreeWalker) };
+}
+
+/// Selects the engine used by [`execute_eval`] on the current thread.
+pub fn set_engine(engine: Engine) {
+    ENGINE.with(|e| e.set(engine));
+}
+
+pub fn engine() -> Engine {
+    ENGINE.with(Cell::get)
+}
+
+/// Runs the program file given as the first argument, or the REPL without one.
+///
+/// With [`VM_FLAG`] before them, expressions are evaluated by the bytecode VM.
 pub fn main(funcs: impl ReplFuncs) {
-    let mut args = std::env::args().skip(1);
+    if std::env::args().nth(1).as_deref() == Some(VM_FLAG) {
+        set_engine(Engine::Vm);
+    }
+
+    let mut args = program_args();
     match args.next() {
         Some(program) => repl(NoPrint(WithStaticInput::new(
             std::iter::once(format!("(load-file {:?})", program)),
@@ -22,6 +64,13 @@ pub fn main(funcs: impl ReplFuncs) {
     }
 }
 
+/// Command line arguments following the flags: the program file and its arguments.
+fn program_args() -> impl Iterator<Item = String> {
+    let mut args = std::env::args().skip(1).peekable();
+    args.next_if_eq(VM_FLAG);
+    args
+}
+
 pub fn repl(funcs: impl ReplFuncs) {
     let env = define_builtins(&funcs);
 
@@ -40,12 +89,8 @@ pub fn repl(funcs: impl ReplFuncs) {
                     println!();
                 }
             }
-            Err(Error::Parse(e)) => {
-                println!("Error: {e}");
-            }
-            Err(Error::Eval(e)) => {
-                println!("Error: {e}");
-            }
+            Err(Error::Parse(e)) => print_error(&e, e.span().map(Rc::as_ref), &[]),
+            Err(Error::Eval(e)) => print_error(&e, e.span(), e.trace()),
             Err(e) => {
                 eprintln!("Error: {e}");
                 break;
@@ -54,19 +99,24 @@ pub fn repl(funcs: impl ReplFuncs) {
     }
 }
 
+/// Prints an error with the source it points to and the calls it propagated out of, all
+/// to stdout like the results, so they stay in order.
+fn print_error(error: &dyn std::error::Error, span: Option<&Span>, trace: &[Frame]) {
+    println!("Error: {error}");
+    if let Some(span) = span {
+        print!("{}", span.snippet());
+    }
+    if !trace.is_empty() {
+        print!("{}", StackTrace(trace));
+    }
+}
+
 pub fn define_builtins(funcs: &impl ReplFuncs) -> Env {
     let env = Environment::with_builtins();
     funcs
         .execute("(def! not (fn* [arg] (if arg false true)))", &env)
         .unwrap();
 
-    funcs
-        .execute(
-            r##"(def! load-file (fn* (f) (eval (read-string (str "(do " (slurp f) "\nnil)")))))"##,
-            &env,
-        )
-        .unwrap();
-
     funcs
         .execute(
             r##"(defmacro! cond (fn* (& xs) (if (> (count xs) 0) (list 'if (first xs) (if (> (count xs) 1) (nth xs 1) (throw "odd number of forms to cond")) (cons 'cond (rest (rest xs)))))))"##,
@@ -74,12 +124,12 @@ pub fn define_builtins(funcs: &impl ReplFuncs) -> Env {
         )
         .unwrap();
 
-    env.set_special(
+    env.set(
         "*ARGV*",
-        Expr::List(std::env::args().skip(2).map(Expr::String).collect()),
+        Expr::List(program_args().skip(1).map(Expr::String).collect()),
     );
 
-    env.set_special("*host-language*", Expr::String("rust2".into()));
+    env.set("*host-language*", Expr::String("rust2".into()));
 
     env
 }
@@ -104,7 +154,7 @@ pub fn read(pr: Option<&str>) -> Result<String> {
     if std::io::stdin().read_line(&mut s)? == 0 {
         Err(Error::Eof)
     } else {
-        let new_len = s.trim_end_matches(&['\n', '\r']).len();
+        let new_len = s.trim_end_matches(['\n', '\r']).len();
         s.truncate(new_len);
         Ok(s)
     }
@@ -112,7 +162,11 @@ pub fn read(pr: Option<&str>) -> Result<String> {
 
 pub fn execute_eval(s: &str, env: &Env) -> Result<Expr> {
     let expr = parser::parse(s)?;
-    Ok(eval::eval(&expr, env)?)
+    let value = match engine() {
+        Engine::TreeWalker => eval::eval(&expr, env)?,
+        Engine::Vm => eval::vm::eval(&expr, env)?,
+    };
+    Ok(value)
 }
 
 pub fn execute_no_eval(s: &str, _env: &Env) -> Result<Expr> {
@@ -0,0 +1,183 @@
+use std::{
+    fmt::{self, Write},
+    iter::FromIterator,
+    ops::{Deref, DerefMut},
+    rc::Rc,
+};
+
+/// Text of a single parsed input, shared by all spans pointing into it.
+pub struct Source {
+    name: Option<Rc<str>>,
+    text: Rc<str>,
+}
+
+impl Source {
+    pub fn new(text: &str, name: Option<&str>) -> Rc<Self> {
+        Rc::new(Self {
+            name: name.map(Into::into),
+            text: text.into(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("<input>")
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn line(&self, line: u32) -> &str {
+        self.text.lines().nth(line as usize - 1).unwrap_or("")
+    }
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Source")
+            .field("name", &self.name())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Location of a token in its [`Source`], lines and columns are 1-based.
+///
+/// The parser gives one to symbols, collections and the calls reader macros like `'`
+/// expand to. Numbers, strings and keywords have none, errors about them point to the
+/// call they're an argument of.
+#[derive(Clone)]
+pub struct Span {
+    pub source: Rc<Source>,
+    pub line: u32,
+    pub column: u32,
+    pub len: u32,
+}
+
+impl Span {
+    /// Renders the offending line with a caret marker underneath it.
+    pub fn snippet(&self) -> Snippet<'_> {
+        Snippet(self)
+    }
+}
+
+impl PartialEq for Span {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.source, &other.source)
+            && (self.line, self.column, self.len) == (other.line, other.column, other.len)
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.source.name(), self.line, self.column)
+    }
+}
+
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Span({self})")
+    }
+}
+
+/// A collection remembering where it was read from, like a list or vector in the source.
+///
+/// The span doesn't take part in comparisons, like the span of a
+/// [`Symbol`](crate::ast::Symbol). Collections made from it by updating a copy keep it.
+#[derive(Clone, Default)]
+pub struct Spanned<T> {
+    value: T,
+    span: Option<Rc<Span>>,
+}
+
+impl<T> Spanned<T> {
+    pub fn new() -> Self
+    where
+        T: Default,
+    {
+        Self::default()
+    }
+
+    pub fn with_span(value: T, span: Span) -> Self {
+        Self {
+            value,
+            span: Some(Rc::new(span)),
+        }
+    }
+
+    pub fn span(&self) -> Option<&Rc<Span>> {
+        self.span.as_ref()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Spanned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> From<T> for Spanned<T> {
+    fn from(value: T) -> Self {
+        Self { value, span: None }
+    }
+}
+
+impl<T: FromIterator<A>, A> FromIterator<A> for Spanned<T> {
+    fn from_iter<I: IntoIterator<Item = A>>(iter: I) -> Self {
+        T::from_iter(iter).into()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Spanned<T>
+where
+    &'a T: IntoIterator,
+{
+    type Item = <&'a T as IntoIterator>::Item;
+    type IntoIter = <&'a T as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.value.into_iter()
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Spanned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+pub struct Snippet<'a>(&'a Span);
+
+impl fmt::Display for Snippet<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self(span) = self;
+        let line_no = span.line.to_string();
+        let gutter = " ".repeat(line_no.len());
+
+        writeln!(f, "{gutter}--> {span}")?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{line_no} | {}", span.source.line(span.line))?;
+        write!(f, "{gutter} | {}", " ".repeat(span.column as usize - 1))?;
+        for _ in 0..span.len.max(1) {
+            f.write_char('^')?;
+        }
+        writeln!(f)
+    }
+}